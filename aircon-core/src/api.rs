@@ -0,0 +1,528 @@
+//! Routes and JSON bodies of the HTTP API.
+//!
+//! | method | path          | body                                 |
+//! |--------|---------------|--------------------------------------|
+//! | GET    | `/`           | dashboard page, served by the caller |
+//! | GET    | `/api/sensor` | latest reading                       |
+//! | GET    | `/api/status` | controller state and time remaining  |
+//! | GET    | `/api/config` | controller config                    |
+//! | PUT    | `/api/config` | partial config, returns the new one  |
+//! | GET    | `/api/wifi`   | Wi-Fi connection state               |
+//! | GET    | `/metrics`    | Prometheus metrics, see `metrics`    |
+//!
+//! Temperatures are in the selected display unit, which every body names in its
+//! `unit` field. A PUT may name a different `unit` for the values it sends.
+//! Sensor and status bodies carry the local `time` they were rendered at, `null`
+//! until the clock has been synchronised.
+//!
+//! A `failsafe_duty_percent`, `maximum_runtime_secs` or `duty_limit_percent` of 0
+//! turns that feature off. Its period or window is still shown and may be set on
+//! its own, it applies once the feature is on.
+
+use core::fmt::{self, Write};
+
+use embassy_time::{Duration, Instant};
+
+use crate::dht11::Reading;
+use crate::home_assistant::HvacAction;
+use crate::http::{Method, Request, Status};
+use crate::json::{self, JsonError, Value};
+use crate::temp_controller::{
+    user_duration, ControllerState, DutyLimit, Failsafe, RuntimeLimit, TempControllerConfig,
+    DEFAULT_DUTY_WINDOW, DEFAULT_FAILSAFE_PERIOD,
+};
+use crate::units::{TemperatureInput, TemperatureUnit};
+use crate::wall_clock::LocalTime;
+use crate::wifi::WifiStatus;
+
+pub const CONTENT_TYPE_JSON: &str = "application/json";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Route {
+    Dashboard,
+    Sensor,
+    Status,
+    GetConfig,
+    PutConfig,
+    Wifi,
+    Metrics,
+    /// The path exists but not for this method
+    MethodNotAllowed,
+    NotFound,
+}
+
+pub fn route(request: &Request<'_>) -> Route {
+    match (request.method, request.path) {
+        (Method::Get, "/" | "/index.html") => Route::Dashboard,
+        (Method::Get, "/api/sensor") => Route::Sensor,
+        (Method::Get, "/api/status") => Route::Status,
+        (Method::Get, "/api/config") => Route::GetConfig,
+        (Method::Put, "/api/config") => Route::PutConfig,
+        (Method::Get, "/api/wifi") => Route::Wifi,
+        (Method::Get, "/metrics") => Route::Metrics,
+        (
+            _,
+            "/" | "/index.html" | "/api/sensor" | "/api/status" | "/api/config" | "/api/wifi"
+            | "/metrics",
+        ) => Route::MethodNotAllowed,
+        _ => Route::NotFound,
+    }
+}
+
+fn unit_code(unit: TemperatureUnit) -> &'static str {
+    match unit {
+        TemperatureUnit::Celsius => "C",
+        TemperatureUnit::Fahrenheit => "F",
+    }
+}
+
+/// Writes `"time":` and the timestamp as a JSON string, or `null`
+fn write_time(out: &mut impl Write, time: Option<&LocalTime>) -> fmt::Result {
+    match time {
+        Some(time) => write!(out, "\"time\":\"{}\"", time),
+        None => out.write_str("\"time\":null"),
+    }
+}
+
+pub fn render_sensor(
+    out: &mut impl Write,
+    reading: &Reading,
+    unit: TemperatureUnit,
+    time: Option<&LocalTime>,
+) -> fmt::Result {
+    write!(
+        out,
+        "{{\"temperature\":{},\"humidity\":{},\"unit\":\"{}\",",
+        reading.temperature.in_unit(unit),
+        reading.humidity,
+        unit_code(unit)
+    )?;
+    write_time(out, time)?;
+    out.write_str("}")
+}
+
+pub fn render_status(
+    out: &mut impl Write,
+    state: &ControllerState,
+    config: &TempControllerConfig,
+    now: Instant,
+    time: Option<&LocalTime>,
+) -> fmt::Result {
+    write!(
+        out,
+        "{{\"state\":\"{}\",\"hvac_action\":\"{}\",\"relay\":{},\"remaining_secs\":{},",
+        state.name(),
+        HvacAction::from(state).as_str(),
+        state.is_relay_on(),
+        state.time_remaining(config, now).as_secs()
+    )?;
+    write_time(out, time)?;
+    out.write_str("}")
+}
+
+/// Body of the event raised when the compressor is rested for running into `limit`
+pub fn render_warning(
+    out: &mut impl Write,
+    limit: RuntimeLimit,
+    time: Option<&LocalTime>,
+) -> fmt::Result {
+    write!(out, "{{\"warning\":\"{}\",", limit.name())?;
+    write_time(out, time)?;
+    out.write_str("}")
+}
+
+/// Failsafe duty cycle and period, 0% while it is off
+fn failsafe_fields(failsafe: Failsafe) -> (u8, Duration) {
+    match failsafe {
+        Failsafe::Off => (0, DEFAULT_FAILSAFE_PERIOD),
+        Failsafe::DutyCycle { percent, period } => (percent, period),
+    }
+}
+
+/// Duty limit and window, 0% without a limit
+fn duty_limit_fields(duty_limit: Option<DutyLimit>) -> (u8, Duration) {
+    duty_limit.map_or((0, DEFAULT_DUTY_WINDOW), |limit| {
+        (limit.percent, limit.window)
+    })
+}
+
+pub fn render_config(
+    out: &mut impl Write,
+    config: &TempControllerConfig,
+    unit: TemperatureUnit,
+) -> fmt::Result {
+    write!(
+        out,
+        "{{\"threshold_temperature\":{},\"hysteresis\":{},\"minimum_runtime_secs\":{},\"cooldown_time_secs\":{},",
+        config.threshold_temperature.in_unit(unit),
+        config.hysteresis.difference_in_unit(unit),
+        config.minimum_runtime.as_secs(),
+        config.cooldown_time.as_secs(),
+    )?;
+    let (failsafe_percent, failsafe_period) = failsafe_fields(config.failsafe);
+    write!(
+        out,
+        "\"sensor_timeout_secs\":{},\"failsafe_duty_percent\":{},\"failsafe_period_secs\":{},",
+        config.sensor_timeout.as_secs(),
+        failsafe_percent,
+        failsafe_period.as_secs(),
+    )?;
+    let (duty_percent, duty_window) = duty_limit_fields(config.duty_limit);
+    write!(
+        out,
+        "\"maximum_runtime_secs\":{},\"duty_limit_percent\":{},\"duty_limit_window_secs\":{},\"forced_rest_secs\":{},",
+        config.maximum_runtime.unwrap_or_default().as_secs(),
+        duty_percent,
+        duty_window.as_secs(),
+        config.forced_rest.as_secs(),
+    )?;
+    write!(out, "\"unit\":\"{}\"}}", unit_code(unit))
+}
+
+pub fn render_wifi(out: &mut impl Write, status: &WifiStatus) -> fmt::Result {
+    write!(out, "{{\"state\":\"{}\",\"rssi_dbm\":", status.state.name())?;
+    match status.rssi_dbm {
+        Some(rssi) => write!(out, "{}", rssi)?,
+        None => write!(out, "null")?,
+    }
+    write!(out, ",\"retries\":{}}}", status.retries)
+}
+
+pub fn render_error(out: &mut impl Write, status: Status) -> fmt::Result {
+    write!(out, "{{\"error\":\"{}\"}}", status.reason())
+}
+
+fn number<T: core::str::FromStr>(value: Value<'_>) -> Result<T, JsonError> {
+    match value {
+        Value::Number(number) => number.parse().map_err(|_| JsonError::InvalidValue),
+        _ => Err(JsonError::InvalidValue),
+    }
+}
+
+fn duration(value: Value<'_>) -> Result<Duration, JsonError> {
+    user_duration(number(value)?).ok_or(JsonError::InvalidValue)
+}
+
+/// Applies the fields of a PUT `/api/config` body on top of `config`
+pub fn apply_config_update(
+    body: &[u8],
+    config: TempControllerConfig,
+    unit: TemperatureUnit,
+) -> Result<TempControllerConfig, JsonError> {
+    let body = core::str::from_utf8(body).map_err(|_| JsonError::Syntax)?;
+
+    // The unit has to be known before the temperatures can be converted
+    let mut unit = unit;
+    let mut threshold: Option<TemperatureInput> = None;
+    let mut hysteresis: Option<TemperatureInput> = None;
+    let (mut failsafe_percent, mut failsafe_period) = failsafe_fields(config.failsafe);
+    let (mut duty_percent, mut duty_window) = duty_limit_fields(config.duty_limit);
+    let mut new_config = config;
+    json::parse_object(body, |name, value| {
+        match name {
+            "threshold_temperature" => threshold = Some(number(value)?),
+            "hysteresis" => hysteresis = Some(number(value)?),
+            "minimum_runtime_secs" => new_config.minimum_runtime = duration(value)?,
+            "cooldown_time_secs" => new_config.cooldown_time = duration(value)?,
+            "sensor_timeout_secs" => new_config.sensor_timeout = duration(value)?,
+            "failsafe_duty_percent" => failsafe_percent = number(value)?,
+            "failsafe_period_secs" => failsafe_period = duration(value)?,
+            "maximum_runtime_secs" => {
+                new_config.maximum_runtime = Some(duration(value)?).filter(|d| d.as_ticks() > 0)
+            }
+            "duty_limit_percent" => duty_percent = number(value)?,
+            "duty_limit_window_secs" => duty_window = duration(value)?,
+            "forced_rest_secs" => new_config.forced_rest = duration(value)?,
+            "unit" => match value {
+                Value::String(code) => unit = code.parse().map_err(|_| JsonError::InvalidValue)?,
+                _ => return Err(JsonError::InvalidValue),
+            },
+            _ => return Err(JsonError::UnknownField),
+        }
+        Ok(())
+    })?;
+
+    if let Some(threshold) = threshold {
+        // Clients send back the setpoint they were shown when changing something else, that
+        // keeps the exact one in effect, e.g. a scheduled one
+        let threshold = threshold.temperature(unit);
+        if !threshold.same_in_unit(config.threshold_temperature, unit) {
+            new_config.threshold_temperature = threshold;
+        }
+    }
+    if let Some(hysteresis) = hysteresis {
+        new_config.hysteresis = hysteresis.difference(unit);
+    }
+    new_config.failsafe = match failsafe_percent {
+        0 => Failsafe::Off,
+        percent => Failsafe::DutyCycle {
+            percent,
+            period: failsafe_period,
+        },
+    };
+    new_config.duty_limit = (duty_percent > 0).then_some(DutyLimit {
+        percent: duty_percent,
+        window: duty_window,
+    });
+    new_config.validate().map_err(|_| JsonError::InvalidValue)?;
+    Ok(new_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::string::String;
+
+    use crate::http::parse_request;
+    use crate::units::{Humidity, Temperature};
+    use crate::wifi::LinkState;
+
+    fn render(write: impl FnOnce(&mut String) -> fmt::Result) -> String {
+        let mut out = String::new();
+        write(&mut out).unwrap();
+        out
+    }
+
+    fn update(body: &str, unit: TemperatureUnit) -> Result<TempControllerConfig, JsonError> {
+        apply_config_update(body.as_bytes(), TempControllerConfig::default(), unit)
+    }
+
+    #[test]
+    fn routes_recorded_requests() {
+        let route_of = |raw: &[u8]| route(&parse_request(raw, 1024).unwrap());
+        assert_eq!(
+            route_of(b"GET /index.html HTTP/1.1\r\n\r\n"),
+            Route::Dashboard
+        );
+        assert_eq!(
+            route_of(b"GET /api/config?pretty HTTP/1.1\r\n\r\n"),
+            Route::GetConfig
+        );
+        assert_eq!(
+            route_of(b"PUT /api/config HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}"),
+            Route::PutConfig
+        );
+        assert_eq!(
+            route_of(b"POST /metrics HTTP/1.1\r\n\r\n"),
+            Route::MethodNotAllowed
+        );
+        assert_eq!(
+            route_of(b"GET /favicon.ico HTTP/1.1\r\n\r\n"),
+            Route::NotFound
+        );
+    }
+
+    #[test]
+    fn renders_the_config() {
+        let config = TempControllerConfig {
+            failsafe: Failsafe::DutyCycle {
+                percent: 20,
+                period: Duration::from_secs(900),
+            },
+            maximum_runtime: Some(Duration::from_secs(3600)),
+            ..TempControllerConfig::default()
+        };
+        assert_eq!(
+            render(|out| render_config(out, &config, TemperatureUnit::Celsius)),
+            "{\"threshold_temperature\":20.0,\"hysteresis\":1.0,\"minimum_runtime_secs\":10,\
+             \"cooldown_time_secs\":10,\"sensor_timeout_secs\":60,\"failsafe_duty_percent\":20,\
+             \"failsafe_period_secs\":900,\"maximum_runtime_secs\":3600,\"duty_limit_percent\":0,\
+             \"duty_limit_window_secs\":3600,\"forced_rest_secs\":600,\"unit\":\"C\"}"
+        );
+        let fahrenheit = render(|out| render_config(out, &config, TemperatureUnit::Fahrenheit));
+        assert!(fahrenheit.starts_with("{\"threshold_temperature\":68.0,\"hysteresis\":1.8,"));
+        assert!(fahrenheit.ends_with(",\"unit\":\"F\"}"));
+    }
+
+    #[test]
+    fn renders_readings_and_status() {
+        let reading = Reading {
+            temperature: Temperature::from_tenths(235),
+            humidity: Humidity::from_tenths(412),
+            taken_at: Instant::from_secs(0),
+        };
+        assert_eq!(
+            render(|out| render_sensor(out, &reading, TemperatureUnit::Celsius, None)),
+            "{\"temperature\":23.5,\"humidity\":41.2,\"unit\":\"C\",\"time\":null}"
+        );
+
+        let state = ControllerState::Running {
+            starttime: Instant::from_secs(100),
+        };
+        let config = TempControllerConfig::default();
+        assert_eq!(
+            render(|out| render_status(out, &state, &config, Instant::from_secs(104), None)),
+            "{\"state\":\"running\",\"hvac_action\":\"cooling\",\"relay\":true,\"remaining_secs\":6,\"time\":null}"
+        );
+
+        let wifi = WifiStatus {
+            state: LinkState::Up,
+            rssi_dbm: Some(-61),
+            retries: 0,
+        };
+        assert_eq!(
+            render(|out| render_wifi(out, &wifi)),
+            "{\"state\":\"up\",\"rssi_dbm\":-61,\"retries\":0}"
+        );
+        assert_eq!(
+            render(|out| render_error(out, Status::NotFound)),
+            "{\"error\":\"Not Found\"}"
+        );
+    }
+
+    #[test]
+    fn applies_a_recorded_dashboard_update() {
+        let raw = b"PUT /api/config HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 79\r\n\r\n{\"threshold_temperature\": 22.5, \"hysteresis\": 0.5, \"minimum_runtime_secs\": 120}";
+        let request = parse_request(raw, 1024).unwrap();
+        let config = apply_config_update(
+            request.body,
+            TempControllerConfig::default(),
+            TemperatureUnit::Celsius,
+        )
+        .unwrap();
+        assert_eq!(config.threshold_temperature, Temperature::from_tenths(225));
+        assert_eq!(config.hysteresis, Temperature::from_tenths(5));
+        assert_eq!(config.minimum_runtime, Duration::from_secs(120));
+        assert_eq!(
+            config.cooldown_time,
+            TempControllerConfig::default().cooldown_time
+        );
+    }
+
+    #[test]
+    fn converts_from_the_named_unit() {
+        let config = update(
+            "{\"unit\":\"F\",\"threshold_temperature\":77,\"hysteresis\":0.9}",
+            TemperatureUnit::Celsius,
+        )
+        .unwrap();
+        assert_eq!(config.threshold_temperature, Temperature::from_celsius(25));
+        assert_eq!(config.hysteresis.to_tenths(), 5);
+
+        // Without a unit field the display unit applies, the config still ends up in °C
+        let config = update(
+            "{\"threshold_temperature\":72.5}",
+            TemperatureUnit::Fahrenheit,
+        )
+        .unwrap();
+        assert_eq!(config.threshold_temperature, Temperature::from_tenths(225));
+    }
+
+    #[test]
+    fn keeps_the_exact_setpoint_when_it_is_echoed_back() {
+        let current = TempControllerConfig {
+            threshold_temperature: Temperature::from_tenths(217),
+            ..TempControllerConfig::default()
+        };
+        // 21.7 °C is shown as 71.1 °F, which converts back to 21.72 °C
+        let config = apply_config_update(
+            b"{\"threshold_temperature\":71.1,\"cooldown_time_secs\":60}",
+            current,
+            TemperatureUnit::Fahrenheit,
+        )
+        .unwrap();
+        assert_eq!(config.threshold_temperature, current.threshold_temperature);
+        assert_eq!(config.cooldown_time, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn sets_the_failsafe_and_runtime_limits() {
+        let config = update(
+            "{\"failsafe_duty_percent\":30,\"failsafe_period_secs\":1200,\"maximum_runtime_secs\":5400,\
+             \"duty_limit_percent\":75,\"forced_rest_secs\":300}",
+            TemperatureUnit::Celsius,
+        )
+        .unwrap();
+        assert_eq!(
+            config.failsafe,
+            Failsafe::DutyCycle {
+                percent: 30,
+                period: Duration::from_secs(1200)
+            }
+        );
+        assert_eq!(config.maximum_runtime, Some(Duration::from_secs(5400)));
+        assert_eq!(
+            config.duty_limit,
+            Some(DutyLimit {
+                percent: 75,
+                window: DEFAULT_DUTY_WINDOW
+            })
+        );
+        assert_eq!(config.forced_rest, Duration::from_secs(300));
+
+        let off = apply_config_update(
+            b"{\"failsafe_duty_percent\":0,\"maximum_runtime_secs\":0,\"duty_limit_percent\":0}",
+            config,
+            TemperatureUnit::Celsius,
+        )
+        .unwrap();
+        assert_eq!(off.failsafe, Failsafe::Off);
+        assert_eq!(off.maximum_runtime, None);
+        assert_eq!(off.duty_limit, None);
+
+        // The shortest window and period and the highest limit still allowed
+        let edge = update(
+            "{\"failsafe_duty_percent\":100,\"failsafe_period_secs\":1,\
+             \"duty_limit_percent\":99,\"duty_limit_window_secs\":1}",
+            TemperatureUnit::Celsius,
+        )
+        .unwrap();
+        assert_eq!(
+            edge.duty_limit,
+            Some(DutyLimit {
+                percent: 99,
+                window: Duration::from_secs(1)
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        for body in [
+            "{\"threshold_temperature\":51}",
+            "{\"threshold_temperature\":-5}",
+            "{\"unit\":\"F\",\"threshold_temperature\":3000}",
+            "{\"hysteresis\":-1}",
+            "{\"hysteresis\":0}",
+            "{\"minimum_runtime_secs\":86401}",
+            "{\"cooldown_time_secs\":18446744073709551615}",
+            "{\"forced_rest_secs\":99999999999999999999}",
+            "{\"failsafe_duty_percent\":101}",
+            "{\"duty_limit_percent\":300}",
+            "{\"duty_limit_percent\":100}",
+            "{\"duty_limit_percent\":50,\"duty_limit_window_secs\":0}",
+            "{\"failsafe_duty_percent\":50,\"failsafe_period_secs\":0}",
+            "{\"minimum_runtime_secs\":-1}",
+            "{\"unit\":\"K\"}",
+            "{\"threshold_temperature\":\"22\"}",
+        ] {
+            assert_eq!(
+                update(body, TemperatureUnit::Celsius),
+                Err(JsonError::InvalidValue),
+                "{body}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_fields_and_bad_json() {
+        assert_eq!(
+            update("{\"setpoint\":22}", TemperatureUnit::Celsius),
+            Err(JsonError::UnknownField)
+        );
+        assert_eq!(
+            update("{\"hysteresis\":1", TemperatureUnit::Celsius),
+            Err(JsonError::Syntax)
+        );
+        assert_eq!(
+            apply_config_update(
+                b"{\xff}",
+                TempControllerConfig::default(),
+                TemperatureUnit::Celsius
+            ),
+            Err(JsonError::Syntax)
+        );
+    }
+}
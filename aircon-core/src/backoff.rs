@@ -0,0 +1,31 @@
+use embassy_time::Duration;
+
+/// Exponential backoff between reconnect attempts
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Backoff {
+            min,
+            max,
+            next: min,
+        }
+    }
+
+    /// Delay before the next attempt, doubling every call up to the maximum
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    /// Call after a successful attempt so the next failure starts over at the minimum
+    pub fn reset(&mut self) {
+        self.next = self.min;
+    }
+}
@@ -0,0 +1,159 @@
+use embedded_cli::arguments::{FromArgument, FromArgumentError};
+use embedded_cli::Command;
+
+use crate::schedule::{ActionInput, Days, TimeOfDay};
+use crate::temp_controller::ModeInput;
+use crate::units::{TemperatureInput, TemperatureUnit};
+use crate::wall_clock::{DstRule, UtcOffset};
+
+/// Commands understood by the serial command line
+#[derive(Debug, Command)]
+pub enum BaseCommand<'a> {
+    Temp,
+    Addr,
+    Status,
+    GetConfig,
+    /// Temperatures are in the selected units unless suffixed with `C` or `F`
+    SetConfig {
+        set_temp: Option<TemperatureInput>,
+        hysteresis: Option<TemperatureInput>,
+        min_runtime_secs: Option<u64>,
+        min_cooldown_secs: Option<u64>,
+    },
+    /// Shows or selects the unit temperatures are displayed in, `c` or `f`
+    Units {
+        unit: Option<TemperatureUnit>,
+    },
+    /// Shows or sets the sensor failsafe. Without a reading for `timeout_secs` the controller faults and runs the compressor for `duty_percent` of every `period_secs`, 0 keeps it off
+    Failsafe {
+        timeout_secs: Option<u64>,
+        duty_percent: Option<u8>,
+        period_secs: Option<u64>,
+    },
+    /// Shows or sets the compressor runtime limits. It rests for `rest_secs` after running `max_runtime_secs` in one go or `duty_percent` of the last `window_secs`, 0 removes a limit
+    Limits {
+        max_runtime_secs: Option<u64>,
+        duty_percent: Option<u8>,
+        window_secs: Option<u64>,
+        rest_secs: Option<u64>,
+    },
+    /// Shows or sets the controller mode, `auto`, `off`, `on` or `boost`. A boost runs the compressor for `minutes`, 30 unless given
+    Mode {
+        mode: Option<ModeInput>,
+        minutes: Option<u64>,
+    },
+    /// Shows the local time and when the clock was last synchronised
+    Time,
+    /// Shows or sets the time zone, e.g. `timezone utc+1 eu` or `timezone utc-5 us`. DST rule is `none`, `eu` or `us`
+    Timezone {
+        offset: Option<UtcOffset>,
+        dst: Option<DstRule>,
+    },
+    /// Manages the saved Wi-Fi networks
+    #[command(subcommand)]
+    Wifi(WifiCommand<'a>),
+    /// Manages the weekly setpoint schedule
+    #[command(subcommand)]
+    Schedule(ScheduleCommand),
+}
+
+#[derive(Debug, Command)]
+pub enum WifiCommand<'a> {
+    /// Saves a network, tried after the ones saved before it. Leave out the passphrase, or pass "", for an open network
+    Set {
+        ssid: &'a str,
+        passphrase: Option<&'a str>,
+    },
+    /// Shows the connection state and the saved networks
+    Show,
+    /// Removes a saved network
+    Forget { ssid: &'a str },
+}
+
+#[derive(Debug, Command)]
+pub enum ScheduleCommand {
+    /// Lists the blocks, numbered for `schedule remove`
+    Show,
+    /// Adds a block lasting until the next one starts, e.g. `schedule add mon-fri 07:00 22` or `schedule add daily 23:00 off`
+    Add {
+        days: Days,
+        start: TimeOfDay,
+        setpoint: ActionInput,
+    },
+    /// Removes the block with the number `schedule show` lists it under
+    Remove { number: usize },
+    /// Removes every block, the controller keeps its own setpoint
+    Clear,
+}
+
+impl<'a> FromArgument<'a> for TemperatureInput {
+    fn from_arg(arg: &'a str) -> Result<Self, FromArgumentError<'a>> {
+        arg.parse().map_err(|_| FromArgumentError {
+            value: arg,
+            expected: "temperature",
+        })
+    }
+}
+
+impl<'a> FromArgument<'a> for TemperatureUnit {
+    fn from_arg(arg: &'a str) -> Result<Self, FromArgumentError<'a>> {
+        arg.parse().map_err(|_| FromArgumentError {
+            value: arg,
+            expected: "c or f",
+        })
+    }
+}
+
+impl<'a> FromArgument<'a> for UtcOffset {
+    fn from_arg(arg: &'a str) -> Result<Self, FromArgumentError<'a>> {
+        arg.parse().map_err(|_| FromArgumentError {
+            value: arg,
+            expected: "UTC offset",
+        })
+    }
+}
+
+impl<'a> FromArgument<'a> for DstRule {
+    fn from_arg(arg: &'a str) -> Result<Self, FromArgumentError<'a>> {
+        arg.parse().map_err(|_| FromArgumentError {
+            value: arg,
+            expected: "none, eu or us",
+        })
+    }
+}
+
+impl<'a> FromArgument<'a> for Days {
+    fn from_arg(arg: &'a str) -> Result<Self, FromArgumentError<'a>> {
+        arg.parse().map_err(|_| FromArgumentError {
+            value: arg,
+            expected: "days such as mon-fri, sat,sun or daily",
+        })
+    }
+}
+
+impl<'a> FromArgument<'a> for TimeOfDay {
+    fn from_arg(arg: &'a str) -> Result<Self, FromArgumentError<'a>> {
+        arg.parse().map_err(|_| FromArgumentError {
+            value: arg,
+            expected: "HH:MM",
+        })
+    }
+}
+
+impl<'a> FromArgument<'a> for ActionInput {
+    fn from_arg(arg: &'a str) -> Result<Self, FromArgumentError<'a>> {
+        arg.parse().map_err(|_| FromArgumentError {
+            value: arg,
+            expected: "temperature or off",
+        })
+    }
+}
+
+impl<'a> FromArgument<'a> for ModeInput {
+    fn from_arg(arg: &'a str) -> Result<Self, FromArgumentError<'a>> {
+        arg.parse().map_err(|_| FromArgumentError {
+            value: arg,
+            expected: "auto, off, on or boost",
+        })
+    }
+}
@@ -0,0 +1,515 @@
+//! Versioned, CRC protected settings records in a reserved region of NOR flash.
+//!
+//! The region is split into fixed size slots and every save appends a record with
+//! an increasing sequence number to the next slot, so writes are spread over all
+//! sectors of the region. A sector is only erased when the writer moves into it.
+//! Loading picks the valid record with the newest sequence number and falls back
+//! to nothing if the region is blank or every record is corrupt.
+//!
+//! Record layout, little-endian:
+//!
+//! | offset | size | field                                 |
+//! |--------|------|---------------------------------------|
+//! | 0      | 4    | magic `ACFG`                          |
+//! | 4      | 2    | record version                        |
+//! | 6      | 2    | payload length                        |
+//! | 8      | 4    | sequence number                       |
+//! | 12     | 2    | threshold temperature, tenths of a °C |
+//! | 14     | 2    | hysteresis, tenths of a °C            |
+//! | 16     | 4    | minimum runtime, seconds              |
+//! | 20     | 4    | cooldown time, seconds                |
+//! | 24     | 1    | display unit, 0 = °C, 1 = °F          |
+//! | 25     | 2    | standard UTC offset, minutes          |
+//! | 27     | 1    | DST rule, 0 = none, 1 = EU, 2 = US    |
+//! | 28     | 4    | sensor timeout, seconds               |
+//! | 32     | 1    | failsafe duty cycle, %, 0 = off       |
+//! | 33     | 4    | failsafe period, seconds              |
+//! | 37     | 4    | maximum runtime, seconds, 0 = none    |
+//! | 41     | 1    | duty limit, %, 0 = none               |
+//! | 42     | 4    | duty limit window, seconds            |
+//! | 46     | 4    | forced rest, seconds                  |
+//! | 60     | 4    | CRC-32 of bytes 0..60                 |
+
+use embassy_time::Duration;
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::temp_controller::{DutyLimit, Failsafe, TempControllerConfig};
+use crate::units::{Temperature, TemperatureUnit};
+use crate::wall_clock::{DstRule, TimeZone, UtcOffset};
+
+/// Size of one record, every slot in the region holds exactly one
+pub const SLOT_SIZE: usize = 64;
+/// Version written by this firmware, records with another version are ignored
+pub const RECORD_VERSION: u16 = 1;
+
+const MAGIC: [u8; 4] = *b"ACFG";
+const PAYLOAD_LENGTH: u16 = 38;
+const CRC_OFFSET: usize = SLOT_SIZE - 4;
+
+/// Everything that survives a reboot
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Settings {
+    pub controller: TempControllerConfig,
+    pub display_unit: TemperatureUnit,
+    pub time_zone: TimeZone,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RecordError {
+    /// The slot has never been written since the last erase
+    Blank,
+    BadMagic,
+    UnsupportedVersion(u16),
+    BadCrc,
+    /// The CRC matches but a field holds a value this firmware does not know
+    InvalidField,
+}
+
+/// CRC-32 (IEEE 802.3), bitwise since records are tiny
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Whether sequence number `a` was written after `b`, correct across wrap-around as
+/// long as the two are less than 2^31 saves apart
+pub fn is_newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+fn secs_u32(duration: Duration) -> u32 {
+    duration.as_secs().min(u32::MAX as u64) as u32
+}
+
+pub fn encode_record(settings: &Settings, sequence: u32) -> [u8; SLOT_SIZE] {
+    let mut slot = [0u8; SLOT_SIZE];
+    let config = &settings.controller;
+    slot[0..4].copy_from_slice(&MAGIC);
+    slot[4..6].copy_from_slice(&RECORD_VERSION.to_le_bytes());
+    slot[6..8].copy_from_slice(&PAYLOAD_LENGTH.to_le_bytes());
+    slot[8..12].copy_from_slice(&sequence.to_le_bytes());
+    slot[12..14].copy_from_slice(&config.threshold_temperature.to_tenths().to_le_bytes());
+    slot[14..16].copy_from_slice(&config.hysteresis.to_tenths().to_le_bytes());
+    slot[16..20].copy_from_slice(&secs_u32(config.minimum_runtime).to_le_bytes());
+    slot[20..24].copy_from_slice(&secs_u32(config.cooldown_time).to_le_bytes());
+    slot[24] = match settings.display_unit {
+        TemperatureUnit::Celsius => 0,
+        TemperatureUnit::Fahrenheit => 1,
+    };
+    slot[25..27].copy_from_slice(&settings.time_zone.standard.minutes().to_le_bytes());
+    slot[27] = match settings.time_zone.dst {
+        DstRule::None => 0,
+        DstRule::Eu => 1,
+        DstRule::Us => 2,
+    };
+    slot[28..32].copy_from_slice(&secs_u32(config.sensor_timeout).to_le_bytes());
+    let (duty, period) = match config.failsafe {
+        Failsafe::Off => (0, Duration::from_ticks(0)),
+        Failsafe::DutyCycle { percent, period } => (percent, period),
+    };
+    slot[32] = duty;
+    slot[33..37].copy_from_slice(&secs_u32(period).to_le_bytes());
+    let maximum_runtime = config.maximum_runtime.unwrap_or_default();
+    slot[37..41].copy_from_slice(&secs_u32(maximum_runtime).to_le_bytes());
+    let (duty_limit, window) = match config.duty_limit {
+        None => (0, Duration::from_ticks(0)),
+        Some(limit) => (limit.percent, limit.window),
+    };
+    slot[41] = duty_limit;
+    slot[42..46].copy_from_slice(&secs_u32(window).to_le_bytes());
+    slot[46..50].copy_from_slice(&secs_u32(config.forced_rest).to_le_bytes());
+    let crc = crc32(&slot[..CRC_OFFSET]);
+    slot[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+    slot
+}
+
+/// Decodes a slot into its sequence number and settings
+pub fn decode_record(slot: &[u8; SLOT_SIZE]) -> Result<(u32, Settings), RecordError> {
+    let u16_at = |offset: usize| u16::from_le_bytes([slot[offset], slot[offset + 1]]);
+    let u32_at = |offset: usize| {
+        u32::from_le_bytes([
+            slot[offset],
+            slot[offset + 1],
+            slot[offset + 2],
+            slot[offset + 3],
+        ])
+    };
+
+    if slot.iter().all(|byte| *byte == 0xFF) {
+        return Err(RecordError::Blank);
+    }
+    if slot[0..4] != MAGIC {
+        return Err(RecordError::BadMagic);
+    }
+    let version = u16_at(4);
+    if version != RECORD_VERSION {
+        return Err(RecordError::UnsupportedVersion(version));
+    }
+    if u32_at(CRC_OFFSET) != crc32(&slot[..CRC_OFFSET]) {
+        return Err(RecordError::BadCrc);
+    }
+    if u16_at(6) != PAYLOAD_LENGTH {
+        return Err(RecordError::InvalidField);
+    }
+
+    let display_unit = match slot[24] {
+        0 => TemperatureUnit::Celsius,
+        1 => TemperatureUnit::Fahrenheit,
+        _ => return Err(RecordError::InvalidField),
+    };
+    let dst = match slot[27] {
+        0 => DstRule::None,
+        1 => DstRule::Eu,
+        2 => DstRule::Us,
+        _ => return Err(RecordError::InvalidField),
+    };
+    let standard = UtcOffset::from_minutes(u16_at(25) as i16).ok_or(RecordError::InvalidField)?;
+    let failsafe = match slot[32] {
+        0 => Failsafe::Off,
+        percent @ 1..=100 => Failsafe::DutyCycle {
+            percent,
+            period: Duration::from_secs(u32_at(33) as u64),
+        },
+        _ => return Err(RecordError::InvalidField),
+    };
+    let duty_limit = match slot[41] {
+        0 => None,
+        percent @ 1..=100 => Some(DutyLimit {
+            percent,
+            window: Duration::from_secs(u32_at(42) as u64),
+        }),
+        _ => return Err(RecordError::InvalidField),
+    };
+    let maximum_runtime = Some(u32_at(37)).filter(|secs| *secs > 0);
+    let settings = Settings {
+        controller: TempControllerConfig {
+            threshold_temperature: Temperature::from_tenths(u16_at(12) as i16),
+            hysteresis: Temperature::from_tenths(u16_at(14) as i16),
+            minimum_runtime: Duration::from_secs(u32_at(16) as u64),
+            cooldown_time: Duration::from_secs(u32_at(20) as u64),
+            sensor_timeout: Duration::from_secs(u32_at(28) as u64),
+            failsafe,
+            maximum_runtime: maximum_runtime.map(|secs| Duration::from_secs(secs as u64)),
+            duty_limit,
+            forced_rest: Duration::from_secs(u32_at(46) as u64),
+        },
+        display_unit,
+        time_zone: TimeZone { standard, dst },
+    };
+    settings
+        .controller
+        .validate()
+        .map_err(|_| RecordError::InvalidField)?;
+    Ok((u32_at(8), settings))
+}
+
+/// Settings storage spread over `sector_count` erase sectors starting at `start`
+pub struct ConfigStore<F: NorFlash> {
+    flash: F,
+    start: u32,
+    end: u32,
+    next_offset: u32,
+    next_sequence: u32,
+}
+
+impl<F: NorFlash> ConfigStore<F> {
+    pub fn new(flash: F, start: u32, sector_count: u32) -> Self {
+        assert!((start as usize).is_multiple_of(F::ERASE_SIZE));
+        assert!(SLOT_SIZE.is_multiple_of(F::WRITE_SIZE) && F::ERASE_SIZE.is_multiple_of(SLOT_SIZE));
+        ConfigStore {
+            flash,
+            start,
+            end: start + sector_count * F::ERASE_SIZE as u32,
+            next_offset: start,
+            next_sequence: 0,
+        }
+    }
+
+    fn read_slot(&mut self, offset: u32) -> Result<[u8; SLOT_SIZE], F::Error> {
+        let mut slot = [0u8; SLOT_SIZE];
+        self.flash.read(offset, &mut slot)?;
+        Ok(slot)
+    }
+
+    /// Finds the newest valid record, `None` if the region is blank or corrupt
+    pub fn load(&mut self) -> Result<Option<Settings>, F::Error> {
+        let mut newest: Option<(u32, u32, Settings)> = None;
+        for offset in (self.start..self.end).step_by(SLOT_SIZE) {
+            let slot = self.read_slot(offset)?;
+            if let Ok((sequence, settings)) = decode_record(&slot) {
+                if newest.is_none_or(|(newest_sequence, _, _)| is_newer(sequence, newest_sequence))
+                {
+                    newest = Some((sequence, offset, settings));
+                }
+            }
+        }
+
+        Ok(newest.map(|(sequence, offset, settings)| {
+            self.next_sequence = sequence.wrapping_add(1);
+            self.next_offset = self.advance(offset);
+            settings
+        }))
+    }
+
+    /// Appends a new record, erasing the next sector when the current one is full
+    pub fn save(&mut self, settings: &Settings) -> Result<(), F::Error> {
+        let mut offset = self.next_offset;
+        let sector_size = F::ERASE_SIZE as u32;
+        let at_sector_start = (offset - self.start).is_multiple_of(sector_size);
+        // A torn or foreign write leaves a slot that cannot be programmed over, move on to a fresh sector
+        if !at_sector_start && self.read_slot(offset)?.iter().any(|byte| *byte != 0xFF) {
+            offset = self.start + ((offset - self.start) / sector_size + 1) * sector_size;
+            if offset >= self.end {
+                offset = self.start;
+            }
+        }
+        if (offset - self.start).is_multiple_of(sector_size) {
+            self.flash.erase(offset, offset + sector_size)?;
+        }
+
+        let record = encode_record(settings, self.next_sequence);
+        self.flash.write(offset, &record)?;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        self.next_offset = self.advance(offset);
+        Ok(())
+    }
+
+    /// Start of the slot after the one `offset` is in
+    fn advance(&self, offset: u32) -> u32 {
+        let slot_size = SLOT_SIZE as u32;
+        let next = self.start + ((offset - self.start) / slot_size + 1) * slot_size;
+        if next >= self.end {
+            self.start
+        } else {
+            next
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mock_flash::{MockFlash, SECTOR_SIZE};
+
+    const SLOTS_PER_SECTOR: usize = SECTOR_SIZE / SLOT_SIZE;
+
+    fn settings(setpoint_tenths: i16) -> Settings {
+        Settings {
+            controller: TempControllerConfig {
+                threshold_temperature: Temperature::from_tenths(setpoint_tenths),
+                hysteresis: Temperature::from_tenths(5),
+                minimum_runtime: Duration::from_secs(180),
+                cooldown_time: Duration::from_secs(300),
+                sensor_timeout: Duration::from_secs(90),
+                failsafe: Failsafe::DutyCycle {
+                    percent: 25,
+                    period: Duration::from_secs(1200),
+                },
+                maximum_runtime: Some(Duration::from_secs(7200)),
+                duty_limit: Some(DutyLimit {
+                    percent: 80,
+                    window: Duration::from_secs(3600),
+                }),
+                forced_rest: Duration::from_secs(900),
+            },
+            display_unit: TemperatureUnit::Fahrenheit,
+            time_zone: TimeZone {
+                standard: UtcOffset::from_minutes(-300).unwrap(),
+                dst: DstRule::Us,
+            },
+        }
+    }
+
+    fn store(flash: MockFlash) -> ConfigStore<MockFlash> {
+        let sectors = (flash.data.len() / SECTOR_SIZE) as u32;
+        ConfigStore::new(flash, 0, sectors)
+    }
+
+    /// Writes a record with `sequence` straight into `slot`
+    fn put(flash: &mut MockFlash, slot: usize, settings: &Settings, sequence: u32) {
+        let offset = slot * SLOT_SIZE;
+        flash.data[offset..offset + SLOT_SIZE].copy_from_slice(&encode_record(settings, sequence));
+    }
+
+    #[test]
+    fn record_round_trips() {
+        let settings = settings(215);
+        let record = encode_record(&settings, 42);
+        assert_eq!(decode_record(&record), Ok((42, settings)));
+
+        let defaults = Settings::default();
+        assert_eq!(
+            decode_record(&encode_record(&defaults, 0)),
+            Ok((0, defaults))
+        );
+    }
+
+    #[test]
+    fn record_rejects_damage() {
+        assert_eq!(decode_record(&[0xFF; SLOT_SIZE]), Err(RecordError::Blank));
+
+        let mut record = encode_record(&settings(215), 1);
+        record[13] ^= 0x01;
+        assert_eq!(decode_record(&record), Err(RecordError::BadCrc));
+
+        let mut record = encode_record(&settings(215), 1);
+        record[0] = b'X';
+        assert_eq!(decode_record(&record), Err(RecordError::BadMagic));
+
+        let mut record = encode_record(&settings(215), 1);
+        record[4] = 2;
+        assert_eq!(
+            decode_record(&record),
+            Err(RecordError::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn record_rejects_invalid_fields_behind_a_good_crc() {
+        let mut record = encode_record(&settings(215), 1);
+        record[24] = 7;
+        let crc = crc32(&record[..CRC_OFFSET]);
+        record[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(decode_record(&record), Err(RecordError::InvalidField));
+
+        // Negative hysteresis, which no firmware would have saved
+        let mut record = encode_record(&settings(215), 1);
+        record[14..16].copy_from_slice(&(-5i16).to_le_bytes());
+        let crc = crc32(&record[..CRC_OFFSET]);
+        record[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(decode_record(&record), Err(RecordError::InvalidField));
+    }
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn loads_nothing_from_blank_flash() {
+        let mut store = store(MockFlash::new(2));
+        assert_eq!(store.load(), Ok(None));
+    }
+
+    #[test]
+    fn loads_what_was_saved() {
+        let mut store = store(MockFlash::new(2));
+        store.save(&settings(200)).unwrap();
+        store.save(&settings(210)).unwrap();
+
+        let mut reopened = self::store(store.flash);
+        assert_eq!(reopened.load(), Ok(Some(settings(210))));
+    }
+
+    #[test]
+    fn picks_the_newest_sequence() {
+        let mut flash = MockFlash::new(2);
+        put(&mut flash, 0, &settings(200), 5);
+        put(&mut flash, 1, &settings(220), 7);
+        put(&mut flash, SLOTS_PER_SECTOR + 3, &settings(210), 6);
+
+        let mut store = store(flash);
+        assert_eq!(store.load(), Ok(Some(settings(220))));
+        store.save(&settings(230)).unwrap();
+        assert_eq!(
+            decode_record(&store.read_slot(2 * SLOT_SIZE as u32).unwrap())
+                .unwrap()
+                .0,
+            8
+        );
+    }
+
+    #[test]
+    fn picks_the_newest_sequence_across_wrap_around() {
+        let mut flash = MockFlash::new(2);
+        put(&mut flash, 0, &settings(200), u32::MAX - 1);
+        put(&mut flash, 1, &settings(210), u32::MAX);
+        put(&mut flash, 2, &settings(220), 0);
+
+        let mut store = store(flash);
+        assert_eq!(store.load(), Ok(Some(settings(220))));
+        assert_eq!(store.next_sequence, 1);
+    }
+
+    #[test]
+    fn is_newer_wraps() {
+        assert!(is_newer(1, 0));
+        assert!(!is_newer(0, 1));
+        assert!(!is_newer(3, 3));
+        assert!(is_newer(0, u32::MAX));
+        assert!(is_newer(5, u32::MAX - 5));
+    }
+
+    #[test]
+    fn falls_back_past_a_corrupt_record() {
+        let mut store = store(MockFlash::new(2));
+        store.save(&settings(200)).unwrap();
+        store.save(&settings(210)).unwrap();
+        store.flash.data[SLOT_SIZE + 12] ^= 0xFF;
+
+        let mut reopened = self::store(store.flash);
+        assert_eq!(reopened.load(), Ok(Some(settings(200))));
+    }
+
+    #[test]
+    fn recovers_from_a_torn_write() {
+        let mut store = store(MockFlash::new(2));
+        store.save(&settings(200)).unwrap();
+        // Power lost halfway through programming the second record
+        let torn = encode_record(&settings(210), 1);
+        store.flash.data[SLOT_SIZE..SLOT_SIZE + 20].copy_from_slice(&torn[..20]);
+
+        let mut reopened = self::store(store.flash);
+        assert_eq!(reopened.load(), Ok(Some(settings(200))));
+        // The torn slot can't be programmed again, the next save moves to a fresh sector
+        reopened.save(&settings(220)).unwrap();
+        assert_eq!(reopened.next_offset, (SECTOR_SIZE + SLOT_SIZE) as u32);
+
+        let mut reopened = self::store(reopened.flash);
+        assert_eq!(reopened.load(), Ok(Some(settings(220))));
+    }
+
+    #[test]
+    fn moves_on_when_a_sector_is_full() {
+        let mut store = store(MockFlash::new(2));
+        for index in 0..SLOTS_PER_SECTOR {
+            store.save(&settings(index as i16)).unwrap();
+        }
+        assert_eq!(store.flash.erases, 1);
+
+        store.save(&settings(300)).unwrap();
+        assert_eq!(store.flash.erases, 2);
+        // The first sector is untouched until the writer comes back to it
+        assert_eq!(
+            decode_record(&store.read_slot(0).unwrap()).map(|(sequence, _)| sequence),
+            Ok(0)
+        );
+
+        let mut reopened = self::store(store.flash);
+        assert_eq!(reopened.load(), Ok(Some(settings(300))));
+    }
+
+    #[test]
+    fn wraps_around_the_region() {
+        let mut store = store(MockFlash::new(2));
+        let saves = 2 * SLOTS_PER_SECTOR + 5;
+        for index in 0..saves {
+            store.save(&settings((index % 500) as i16)).unwrap();
+        }
+        assert_eq!(store.flash.erases, 3);
+
+        let mut reopened = self::store(store.flash);
+        let last = settings(((saves - 1) % 500) as i16);
+        assert_eq!(reopened.load(), Ok(Some(last)));
+    }
+}
@@ -0,0 +1,630 @@
+//! Just enough of a DHCPv4 server to hand addresses to the phones joining the setup
+//! access point.
+//!
+//! Leases come from a small pool right after a fixed first address. Only DISCOVER,
+//! REQUEST, DECLINE and RELEASE are handled. The unit is both the router and the
+//! DNS server of the network it hands out, see `dns_server`; there is nothing behind
+//! it. Replies are meant to be broadcast to `CLIENT_PORT`, since clients have no
+//! address yet.
+//!
+//! Message layout, big-endian, see RFC 2131:
+//!
+//! | offset | size | field                                     |
+//! |--------|------|-------------------------------------------|
+//! | 0      | 1    | op, 1 = request, 2 = reply                |
+//! | 1      | 1    | hardware type, 1 = Ethernet               |
+//! | 2      | 1    | hardware address length                   |
+//! | 4      | 4    | transaction ID                            |
+//! | 10     | 2    | flags                                     |
+//! | 12     | 4    | client address, when it already has one   |
+//! | 16     | 4    | "your" address, the one being handed out  |
+//! | 28     | 16   | client hardware address                   |
+//! | 236    | 4    | magic cookie                              |
+//! | 240    | ..   | options, terminated by 255                |
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+/// Size of every reply, the minimum BOOTP message size some clients insist on
+pub const REPLY_SIZE: usize = 300;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const OPTIONS_OFFSET: usize = 240;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS_SERVER: u8 = 6;
+const OPTION_REQUESTED_ADDRESS: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+/// How long an offered address is held for a client that doesn't follow up with a request
+const OFFER_HOLD: Duration = Duration::from_secs(60);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MessageType {
+    Discover,
+    Offer,
+    Request,
+    Decline,
+    Ack,
+    Nak,
+    Release,
+    Inform,
+}
+
+impl MessageType {
+    fn from_code(code: u8) -> Option<Self> {
+        Some(match code {
+            1 => MessageType::Discover,
+            2 => MessageType::Offer,
+            3 => MessageType::Request,
+            4 => MessageType::Decline,
+            5 => MessageType::Ack,
+            6 => MessageType::Nak,
+            7 => MessageType::Release,
+            8 => MessageType::Inform,
+            _ => return None,
+        })
+    }
+
+    fn code(&self) -> u8 {
+        match self {
+            MessageType::Discover => 1,
+            MessageType::Offer => 2,
+            MessageType::Request => 3,
+            MessageType::Decline => 4,
+            MessageType::Ack => 5,
+            MessageType::Nak => 6,
+            MessageType::Release => 7,
+            MessageType::Inform => 8,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DhcpError {
+    /// Not a DHCP request from an Ethernet client, or cut short
+    Malformed,
+    /// The reply buffer is smaller than `REPLY_SIZE`
+    BufferTooSmall,
+}
+
+/// The fields of a client message the server looks at
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Message {
+    pub message_type: MessageType,
+    pub transaction_id: u32,
+    pub flags: u16,
+    pub client_address: [u8; 4],
+    pub hardware_address: [u8; 6],
+    pub requested_address: Option<[u8; 4]>,
+    pub server_id: Option<[u8; 4]>,
+}
+
+fn address_at(buf: &[u8], offset: usize) -> [u8; 4] {
+    [
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ]
+}
+
+pub fn parse_message(buf: &[u8]) -> Result<Message, DhcpError> {
+    if buf.len() < OPTIONS_OFFSET
+        || buf[0] != OP_REQUEST
+        || buf[1] != HTYPE_ETHERNET
+        || buf[2] != 6
+        || buf[236..240] != MAGIC_COOKIE
+    {
+        return Err(DhcpError::Malformed);
+    }
+
+    let mut message_type = None;
+    let mut requested_address = None;
+    let mut server_id = None;
+    let mut offset = OPTIONS_OFFSET;
+    while offset < buf.len() {
+        let code = buf[offset];
+        if code == OPTION_END {
+            break;
+        }
+        if code == OPTION_PAD {
+            offset += 1;
+            continue;
+        }
+        let length = *buf.get(offset + 1).ok_or(DhcpError::Malformed)? as usize;
+        let value = buf
+            .get(offset + 2..offset + 2 + length)
+            .ok_or(DhcpError::Malformed)?;
+        match (code, length) {
+            (OPTION_MESSAGE_TYPE, 1) => message_type = MessageType::from_code(value[0]),
+            (OPTION_REQUESTED_ADDRESS, 4) => requested_address = Some(address_at(value, 0)),
+            (OPTION_SERVER_ID, 4) => server_id = Some(address_at(value, 0)),
+            _ => {}
+        }
+        offset += 2 + length;
+    }
+
+    let mut hardware_address = [0; 6];
+    hardware_address.copy_from_slice(&buf[28..34]);
+    Ok(Message {
+        message_type: message_type.ok_or(DhcpError::Malformed)?,
+        transaction_id: u32::from_be_bytes(address_at(buf, 4)),
+        flags: u16::from_be_bytes([buf[10], buf[11]]),
+        client_address: address_at(buf, 12),
+        hardware_address,
+        requested_address,
+        server_id,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Lease {
+    hardware_address: [u8; 6],
+    address: [u8; 4],
+    expires: Instant,
+}
+
+/// Hands out up to `N` addresses, starting with `pool_start`
+pub struct DhcpServer<const N: usize> {
+    address: [u8; 4],
+    prefix_length: u8,
+    pool_start: [u8; 4],
+    lease_time: Duration,
+    leases: Vec<Lease, N>,
+}
+
+impl<const N: usize> DhcpServer<N> {
+    /// `address` is the unit's own address on the `prefix_length` network, the pool
+    /// must lie within the same network
+    pub fn new(
+        address: [u8; 4],
+        prefix_length: u8,
+        pool_start: [u8; 4],
+        lease_time: Duration,
+    ) -> Self {
+        assert!((1..=30).contains(&prefix_length));
+        DhcpServer {
+            address,
+            prefix_length,
+            pool_start,
+            lease_time,
+            leases: Vec::new(),
+        }
+    }
+
+    fn netmask(&self) -> [u8; 4] {
+        (u32::MAX << (32 - self.prefix_length)).to_be_bytes()
+    }
+
+    fn pool_address(&self, index: usize) -> [u8; 4] {
+        (u32::from_be_bytes(self.pool_start) + index as u32).to_be_bytes()
+    }
+
+    fn in_pool(&self, address: [u8; 4]) -> bool {
+        (0..N).any(|index| self.pool_address(index) == address)
+    }
+
+    /// Address currently leased or offered to the client
+    fn lease_of(&self, hardware_address: [u8; 6]) -> Option<[u8; 4]> {
+        self.leases
+            .iter()
+            .find(|lease| lease.hardware_address == hardware_address)
+            .map(|lease| lease.address)
+    }
+
+    fn is_available(&self, address: [u8; 4], now: Instant) -> bool {
+        self.in_pool(address)
+            && self
+                .leases
+                .iter()
+                .all(|lease| lease.address != address || lease.expires <= now)
+    }
+
+    /// Address for a client without a lease, a free one or else the longest expired
+    fn free_address(&self, now: Instant) -> Option<[u8; 4]> {
+        let unused = (0..N)
+            .map(|index| self.pool_address(index))
+            .find(|address| self.leases.iter().all(|lease| lease.address != *address));
+        unused.or_else(|| {
+            self.leases
+                .iter()
+                .filter(|lease| lease.expires <= now)
+                .min_by_key(|lease| lease.expires)
+                .map(|lease| lease.address)
+        })
+    }
+
+    /// Gives `address` to the client, taking it over from whoever held it before
+    fn grant(&mut self, hardware_address: [u8; 6], address: [u8; 4], expires: Instant) {
+        self.leases
+            .retain(|lease| lease.address != address && lease.hardware_address != hardware_address);
+        // Room is guaranteed, every lease holds a distinct address of the pool
+        let _ = self.leases.push(Lease {
+            hardware_address,
+            address,
+            expires,
+        });
+    }
+
+    /// Answers one client message, returns the length of the reply written to `out`
+    /// or `None` if the message needs no reply
+    pub fn handle(
+        &mut self,
+        request: &[u8],
+        now: Instant,
+        out: &mut [u8],
+    ) -> Result<Option<usize>, DhcpError> {
+        if out.len() < REPLY_SIZE {
+            return Err(DhcpError::BufferTooSmall);
+        }
+        let message = parse_message(request)?;
+        let client = message.hardware_address;
+
+        let reply = match message.message_type {
+            MessageType::Discover => {
+                let held = self.lease_of(client);
+                match held.or_else(|| self.free_address(now)) {
+                    Some(address) => {
+                        if held.is_none() {
+                            self.grant(client, address, now + OFFER_HOLD);
+                        }
+                        Some((MessageType::Offer, address))
+                    }
+                    // Pool exhausted, stay quiet and let the client retry
+                    None => None,
+                }
+            }
+            MessageType::Request => {
+                if message.server_id.is_some_and(|id| id != self.address) {
+                    // The client picked another server's offer
+                    None
+                } else {
+                    let requested = message.requested_address.unwrap_or(message.client_address);
+                    let acked = match self.lease_of(client) {
+                        Some(address) => address == requested,
+                        // A client coming back with an address from before our last restart
+                        None => self.is_available(requested, now),
+                    };
+                    if acked {
+                        self.grant(client, requested, now + self.lease_time);
+                    }
+                    if acked {
+                        Some((MessageType::Ack, requested))
+                    } else {
+                        Some((MessageType::Nak, [0; 4]))
+                    }
+                }
+            }
+            MessageType::Decline | MessageType::Release => {
+                self.leases.retain(|lease| lease.hardware_address != client);
+                None
+            }
+            _ => None,
+        };
+
+        Ok(reply
+            .map(|(message_type, address)| self.write_reply(&message, message_type, address, out)))
+    }
+
+    fn write_reply(
+        &self,
+        request: &Message,
+        message_type: MessageType,
+        address: [u8; 4],
+        out: &mut [u8],
+    ) -> usize {
+        let out = &mut out[..REPLY_SIZE];
+        out.fill(0);
+        out[0] = OP_REPLY;
+        out[1] = HTYPE_ETHERNET;
+        out[2] = 6;
+        out[4..8].copy_from_slice(&request.transaction_id.to_be_bytes());
+        out[10..12].copy_from_slice(&request.flags.to_be_bytes());
+        if message_type == MessageType::Ack {
+            out[12..16].copy_from_slice(&request.client_address);
+        }
+        out[16..20].copy_from_slice(&address);
+        out[28..34].copy_from_slice(&request.hardware_address);
+        out[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+        let mut offset = OPTIONS_OFFSET;
+        let mut option = |code: u8, value: &[u8]| {
+            out[offset] = code;
+            out[offset + 1] = value.len() as u8;
+            out[offset + 2..offset + 2 + value.len()].copy_from_slice(value);
+            offset += 2 + value.len();
+        };
+        option(OPTION_MESSAGE_TYPE, &[message_type.code()]);
+        option(OPTION_SERVER_ID, &self.address);
+        if message_type != MessageType::Nak {
+            let lease_secs = self.lease_time.as_secs().min(u32::MAX as u64) as u32;
+            option(OPTION_LEASE_TIME, &lease_secs.to_be_bytes());
+            option(OPTION_SUBNET_MASK, &self.netmask());
+            option(OPTION_ROUTER, &self.address);
+            option(OPTION_DNS_SERVER, &self.address);
+        }
+        out[offset] = OPTION_END;
+        REPLY_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNIT: [u8; 4] = [192, 168, 4, 1];
+    const PHONE: [u8; 6] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55];
+    const LAPTOP: [u8; 6] = [0x02, 0x66, 0x77, 0x88, 0x99, 0xAA];
+    const LEASE_TIME: Duration = Duration::from_secs(3600);
+
+    fn server() -> DhcpServer<2> {
+        DhcpServer::new(UNIT, 24, [192, 168, 4, 10], LEASE_TIME)
+    }
+
+    /// A client message as a phone sends it, with the options given
+    fn message(
+        message_type: MessageType,
+        client: [u8; 6],
+        options: &[(u8, &[u8])],
+    ) -> Vec<u8, 300> {
+        let mut buf = Vec::new();
+        buf.resize(OPTIONS_OFFSET, 0).unwrap();
+        buf[0] = OP_REQUEST;
+        buf[1] = HTYPE_ETHERNET;
+        buf[2] = 6;
+        buf[4..8].copy_from_slice(&0x3903_F326u32.to_be_bytes());
+        buf[10] = 0x80;
+        buf[28..34].copy_from_slice(&client);
+        buf[236..240].copy_from_slice(&MAGIC_COOKIE);
+        buf.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, message_type.code()])
+            .unwrap();
+        for (code, value) in options {
+            buf.extend_from_slice(&[*code, value.len() as u8]).unwrap();
+            buf.extend_from_slice(value).unwrap();
+        }
+        // Parameter request list and padding, which the server skips
+        buf.extend_from_slice(&[55, 3, 1, 3, 6, OPTION_PAD, OPTION_PAD, OPTION_END])
+            .unwrap();
+        buf
+    }
+
+    fn request(client: [u8; 6], address: [u8; 4]) -> Vec<u8, 300> {
+        message(
+            MessageType::Request,
+            client,
+            &[
+                (OPTION_REQUESTED_ADDRESS, &address),
+                (OPTION_SERVER_ID, &UNIT),
+            ],
+        )
+    }
+
+    /// The reply's message type, "your" address and options
+    fn reply(
+        server: &mut DhcpServer<2>,
+        request: &[u8],
+        now: Instant,
+    ) -> Option<(MessageType, [u8; 4], [u8; REPLY_SIZE])> {
+        let mut out = [0xAA; REPLY_SIZE];
+        let length = server.handle(request, now, &mut out).unwrap()?;
+        assert_eq!(length, REPLY_SIZE);
+        assert_eq!(out[0], OP_REPLY);
+        assert_eq!(out[4..8], request[4..8]);
+        assert_eq!(out[28..34], request[28..34]);
+        assert_eq!(out[236..240], MAGIC_COOKIE);
+        let message_type = MessageType::from_code(option(&out, OPTION_MESSAGE_TYPE)?[0])?;
+        Some((message_type, address_at(&out, 16), out))
+    }
+
+    fn option(reply: &[u8], code: u8) -> Option<&[u8]> {
+        let mut offset = OPTIONS_OFFSET;
+        while reply[offset] != OPTION_END {
+            let length = reply[offset + 1] as usize;
+            if reply[offset] == code {
+                return Some(&reply[offset + 2..offset + 2 + length]);
+            }
+            offset += 2 + length;
+        }
+        None
+    }
+
+    #[test]
+    fn parses_a_request() {
+        let message = parse_message(&request(PHONE, [192, 168, 4, 10])).unwrap();
+        assert_eq!(
+            message,
+            Message {
+                message_type: MessageType::Request,
+                transaction_id: 0x3903_F326,
+                flags: 0x8000,
+                client_address: [0; 4],
+                hardware_address: PHONE,
+                requested_address: Some([192, 168, 4, 10]),
+                server_id: Some(UNIT),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        let mut buf = message(MessageType::Discover, PHONE, &[]);
+        assert_eq!(parse_message(&buf[..200]), Err(DhcpError::Malformed));
+
+        buf[236] = 0;
+        assert_eq!(parse_message(&buf), Err(DhcpError::Malformed));
+
+        // An option running past the end
+        let mut buf = message(MessageType::Discover, PHONE, &[]);
+        buf.truncate(buf.len() - 8);
+        buf.extend_from_slice(&[OPTION_SERVER_ID, 4, 192]).unwrap();
+        assert_eq!(parse_message(&buf), Err(DhcpError::Malformed));
+
+        let buf = message(MessageType::Offer, PHONE, &[]);
+        let mut no_type = buf.clone();
+        no_type[OPTIONS_OFFSET..OPTIONS_OFFSET + 3].fill(OPTION_PAD);
+        assert_eq!(parse_message(&no_type), Err(DhcpError::Malformed));
+
+        let mut out = [0; REPLY_SIZE - 1];
+        assert_eq!(
+            server().handle(&buf, Instant::from_secs(0), &mut out),
+            Err(DhcpError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn offers_and_acknowledges_an_address() {
+        let mut server = server();
+        let now = Instant::from_secs(100);
+        let discover = message(MessageType::Discover, PHONE, &[]);
+        let (message_type, offered, out) = reply(&mut server, &discover, now).unwrap();
+        assert_eq!(message_type, MessageType::Offer);
+        assert_eq!(offered, [192, 168, 4, 10]);
+        assert_eq!(option(&out, OPTION_SERVER_ID), Some(&UNIT[..]));
+        assert_eq!(
+            option(&out, OPTION_SUBNET_MASK),
+            Some(&[255, 255, 255, 0][..])
+        );
+        assert_eq!(option(&out, OPTION_ROUTER), Some(&UNIT[..]));
+        assert_eq!(option(&out, OPTION_DNS_SERVER), Some(&UNIT[..]));
+        assert_eq!(
+            option(&out, OPTION_LEASE_TIME),
+            Some(&3600u32.to_be_bytes()[..])
+        );
+
+        // Discovering again gets the same offer
+        let (_, again, _) = reply(&mut server, &discover, now).unwrap();
+        assert_eq!(again, offered);
+
+        let (message_type, acked, _) = reply(&mut server, &request(PHONE, offered), now).unwrap();
+        assert_eq!((message_type, acked), (MessageType::Ack, offered));
+    }
+
+    #[test]
+    fn naks_a_request_for_another_address() {
+        let mut server = server();
+        let now = Instant::from_secs(0);
+        reply(
+            &mut server,
+            &message(MessageType::Discover, PHONE, &[]),
+            now,
+        )
+        .unwrap();
+
+        let (message_type, address, out) =
+            reply(&mut server, &request(PHONE, [192, 168, 4, 11]), now).unwrap();
+        assert_eq!((message_type, address), (MessageType::Nak, [0; 4]));
+        assert_eq!(option(&out, OPTION_LEASE_TIME), None);
+
+        // Outside the pool, from a client the server has never seen
+        let (message_type, _, _) =
+            reply(&mut server, &request(LAPTOP, [10, 0, 0, 5]), now).unwrap();
+        assert_eq!(message_type, MessageType::Nak);
+    }
+
+    #[test]
+    fn acks_an_address_from_before_a_restart() {
+        let mut server = server();
+        let (message_type, address, _) = reply(
+            &mut server,
+            &request(PHONE, [192, 168, 4, 11]),
+            Instant::from_secs(0),
+        )
+        .unwrap();
+        assert_eq!(
+            (message_type, address),
+            (MessageType::Ack, [192, 168, 4, 11])
+        );
+
+        // The laptop's offer skips the address the phone kept
+        let discover = message(MessageType::Discover, LAPTOP, &[]);
+        let (_, offered, _) = reply(&mut server, &discover, Instant::from_secs(0)).unwrap();
+        assert_eq!(offered, [192, 168, 4, 10]);
+    }
+
+    #[test]
+    fn ignores_requests_for_another_server() {
+        let mut server = server();
+        let request = message(
+            MessageType::Request,
+            PHONE,
+            &[
+                (OPTION_REQUESTED_ADDRESS, &[192, 168, 4, 10]),
+                (OPTION_SERVER_ID, &[192, 168, 4, 254]),
+            ],
+        );
+        assert!(reply(&mut server, &request, Instant::from_secs(0)).is_none());
+    }
+
+    #[test]
+    fn decline_frees_the_lease() {
+        let mut server = server();
+        let now = Instant::from_secs(0);
+        let (_, address, _) = reply(
+            &mut server,
+            &message(MessageType::Discover, PHONE, &[]),
+            now,
+        )
+        .unwrap();
+        reply(&mut server, &request(PHONE, address), now).unwrap();
+        reply(
+            &mut server,
+            &message(MessageType::Discover, LAPTOP, &[]),
+            now,
+        )
+        .unwrap();
+
+        // The phone found the address in use, the pool is full until it lets go
+        let decline = message(
+            MessageType::Decline,
+            PHONE,
+            &[(OPTION_REQUESTED_ADDRESS, &address)],
+        );
+        assert!(reply(&mut server, &decline, now).is_none());
+
+        let (_, offered, _) = reply(
+            &mut server,
+            &message(MessageType::Discover, PHONE, &[]),
+            now,
+        )
+        .unwrap();
+        assert_eq!(offered, address);
+    }
+
+    #[test]
+    fn reuses_expired_leases_once_the_pool_is_exhausted() {
+        let mut server = server();
+        let start = Instant::from_secs(0);
+        for client in [PHONE, LAPTOP] {
+            let discover = message(MessageType::Discover, client, &[]);
+            let (_, address, _) = reply(&mut server, &discover, start).unwrap();
+            reply(&mut server, &request(client, address), start).unwrap();
+        }
+
+        let tablet = [0x02, 0, 0, 0, 0, 0x01];
+        let discover = message(MessageType::Discover, tablet, &[]);
+        assert!(reply(&mut server, &discover, start + Duration::from_secs(60)).is_none());
+
+        // The phone's lease ran out first, so the tablet gets its address
+        let renewed = start + Duration::from_secs(1800);
+        reply(&mut server, &request(LAPTOP, [192, 168, 4, 11]), renewed).unwrap();
+        let later = start + LEASE_TIME;
+        let (_, offered, _) = reply(&mut server, &discover, later).unwrap();
+        assert_eq!(offered, [192, 168, 4, 10]);
+        let (message_type, _, _) = reply(&mut server, &request(PHONE, offered), later).unwrap();
+        assert_eq!(message_type, MessageType::Nak);
+    }
+}
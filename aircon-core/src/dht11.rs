@@ -0,0 +1,229 @@
+//! Decoding of the 5 byte frame DHT11 and DHT22/AM2302 sensors send after a start signal.
+//!
+//! Both send two humidity bytes, two temperature bytes and a checksum, which is
+//! the low byte of the sum of the first four bytes. They differ in how the data
+//! bytes are encoded:
+//!
+//! - DHT11: integer and decimal part of each value, the decimal byte is usually 0
+//! - DHT22: 16 bit big-endian values in tenths, bit 15 of the temperature is the sign
+
+use embassy_time::{Duration, Instant};
+
+use crate::units::{Humidity, Temperature};
+
+/// Number of bytes in a frame
+pub const FRAME_LENGTH: usize = 5;
+
+/// Which sensor is attached to the data pin
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SensorModel {
+    Dht11,
+    /// DHT22 and the AM2302, which is the same sensor with a pull-up on board
+    Dht22,
+}
+
+impl SensorModel {
+    /// Shortest time between two reads the sensor can handle
+    pub fn min_sampling_interval(&self) -> Duration {
+        match self {
+            SensorModel::Dht11 => Duration::from_secs(1),
+            SensorModel::Dht22 => Duration::from_secs(2),
+        }
+    }
+
+    /// Range of temperatures the sensor can report
+    pub fn temperature_range(&self) -> (Temperature, Temperature) {
+        match self {
+            SensorModel::Dht11 => (Temperature::from_celsius(0), Temperature::from_celsius(50)),
+            SensorModel::Dht22 => (
+                Temperature::from_celsius(-40),
+                Temperature::from_celsius(80),
+            ),
+        }
+    }
+}
+
+/// Highest relative humidity any sensor can report, in tenths of a %
+pub const MAX_HUMIDITY: u16 = 1000;
+
+/// A single valid reading
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Reading {
+    pub temperature: Temperature,
+    pub humidity: Humidity,
+    /// When the frame was received, lets consumers tell a stale reading from a fresh one
+    pub taken_at: Instant,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Dht11Error {
+    /// The checksum byte does not match the sum of the data bytes
+    Checksum { expected: u8, actual: u8 },
+    /// The frame is intact but holds values the sensor cannot produce, in tenths
+    OutOfRange { temperature: i16, humidity: u16 },
+    /// The sensor did not answer in time, most likely it is missing or unplugged
+    Timeout,
+}
+
+/// Low byte of the sum of the four data bytes
+pub fn checksum(frame: &[u8; FRAME_LENGTH]) -> u8 {
+    frame[..4]
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Validates and decodes a raw frame sent by `model`, received at `taken_at`
+pub fn decode_frame(
+    model: SensorModel,
+    frame: &[u8; FRAME_LENGTH],
+    taken_at: Instant,
+) -> Result<Reading, Dht11Error> {
+    let expected = checksum(frame);
+    if frame[4] != expected {
+        return Err(Dht11Error::Checksum {
+            expected,
+            actual: frame[4],
+        });
+    }
+
+    let (temperature, humidity) = match model {
+        SensorModel::Dht11 => (
+            frame[2] as i16 * 10 + frame[3] as i16,
+            frame[0] as u16 * 10 + frame[1] as u16,
+        ),
+        SensorModel::Dht22 => {
+            let magnitude = u16::from_be_bytes([frame[2] & 0x7F, frame[3]]) as i16;
+            let temperature = if frame[2] & 0x80 != 0 {
+                -magnitude
+            } else {
+                magnitude
+            };
+            (temperature, u16::from_be_bytes([frame[0], frame[1]]))
+        }
+    };
+
+    let (min_temperature, max_temperature) = model.temperature_range();
+    // DHT11 decimal bytes above 9 would silently shift the integer part
+    let bad_decimals = model == SensorModel::Dht11 && (frame[1] > 9 || frame[3] > 9);
+    if bad_decimals
+        || !(min_temperature..=max_temperature).contains(&Temperature::from_tenths(temperature))
+        || humidity > MAX_HUMIDITY
+    {
+        return Err(Dht11Error::OutOfRange {
+            temperature,
+            humidity,
+        });
+    }
+
+    Ok(Reading {
+        temperature: Temperature::from_tenths(temperature),
+        humidity: Humidity::from_tenths(humidity),
+        taken_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `data` with its checksum appended
+    fn frame(data: [u8; 4]) -> [u8; FRAME_LENGTH] {
+        let mut frame = [data[0], data[1], data[2], data[3], 0];
+        frame[4] = checksum(&frame);
+        frame
+    }
+
+    fn decode(model: SensorModel, frame: &[u8; FRAME_LENGTH]) -> Result<(i16, u16), Dht11Error> {
+        decode_frame(model, frame, Instant::from_secs(7)).map(|reading| {
+            assert_eq!(reading.taken_at, Instant::from_secs(7));
+            (
+                reading.temperature.to_tenths(),
+                reading.humidity.to_tenths(),
+            )
+        })
+    }
+
+    #[test]
+    fn decodes_dht11_frames() {
+        // 45 %, 23 °C as sent by a DHT11
+        assert_eq!(
+            decode(SensorModel::Dht11, &[0x2D, 0x00, 0x17, 0x00, 0x44]),
+            Ok((230, 450))
+        );
+        assert_eq!(
+            decode(SensorModel::Dht11, &frame([38, 0, 24, 7])),
+            Ok((247, 380))
+        );
+    }
+
+    #[test]
+    fn decodes_dht22_frames() {
+        // 65.2 %, 35.1 °C as sent by a DHT22
+        assert_eq!(
+            decode(SensorModel::Dht22, &[0x02, 0x8C, 0x01, 0x5F, 0xEE]),
+            Ok((351, 652))
+        );
+    }
+
+    #[test]
+    fn decodes_negative_dht22_temperatures() {
+        // -10.1 °C, the sign is bit 15 of the temperature, not two's complement
+        assert_eq!(
+            decode(SensorModel::Dht22, &frame([0x01, 0xF4, 0x80, 0x65])),
+            Ok((-101, 500))
+        );
+        assert_eq!(
+            decode(SensorModel::Dht22, &frame([0x01, 0xF4, 0x80, 0x00])),
+            Ok((0, 500))
+        );
+    }
+
+    #[test]
+    fn scales_the_same_bytes_by_model() {
+        let bytes = frame([0x00, 0xC8, 0x00, 0xFA]);
+        // As a DHT22 that is 20.0 % and 25.0 °C, a DHT11 can't have decimals that large
+        assert_eq!(decode(SensorModel::Dht22, &bytes), Ok((250, 200)));
+        assert!(matches!(
+            decode(SensorModel::Dht11, &bytes),
+            Err(Dht11Error::OutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        assert_eq!(
+            decode(SensorModel::Dht11, &[0x2D, 0x00, 0x17, 0x00, 0x45]),
+            Err(Dht11Error::Checksum {
+                expected: 0x44,
+                actual: 0x45
+            })
+        );
+    }
+
+    #[test]
+    fn checksum_wraps() {
+        assert_eq!(checksum(&[0xFF, 0xFF, 0x02, 0x00, 0x00]), 0x00);
+    }
+
+    #[test]
+    fn rejects_values_the_sensor_cannot_produce() {
+        assert_eq!(
+            decode(SensorModel::Dht11, &frame([40, 0, 60, 0])),
+            Err(Dht11Error::OutOfRange {
+                temperature: 600,
+                humidity: 400
+            })
+        );
+        assert_eq!(
+            decode(SensorModel::Dht22, &frame([0x03, 0xE9, 0x00, 0xC8])),
+            Err(Dht11Error::OutOfRange {
+                temperature: 200,
+                humidity: 1001
+            })
+        );
+        assert!(decode(SensorModel::Dht22, &frame([0x01, 0xF4, 0x81, 0x91])).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Catch-all DNS server for the setup access point.
+//!
+//! Every name resolves to the unit itself, so the connectivity checks phones and
+//! laptops run after joining a network reach the setup page instead of the
+//! internet, and they open it as a captive portal. Only standard queries with a
+//! single question are answered; A queries get the unit's address and every other
+//! type an empty answer, so clients don't wait for an IPv6 address that never comes.
+//!
+//! Message layout, big-endian, see RFC 1035:
+//!
+//! | offset | size | field                                          |
+//! |--------|------|------------------------------------------------|
+//! | 0      | 2    | ID, copied into the reply                      |
+//! | 2      | 2    | flags, QR in bit 15, opcode in bits 11..15     |
+//! | 4      | 2    | question count                                 |
+//! | 6      | 2    | answer count                                   |
+//! | 8      | 2    | authority count                                |
+//! | 10     | 2    | additional count                               |
+//! | 12     | ..   | question: name labels, then type and class     |
+
+pub const SERVER_PORT: u16 = 53;
+/// Largest reply, the question name can't be longer than 255 bytes
+pub const MAX_REPLY_SIZE: usize = HEADER_LENGTH + MAX_NAME_LENGTH + 4 + ANSWER_LENGTH;
+
+const HEADER_LENGTH: usize = 12;
+const MAX_NAME_LENGTH: usize = 255;
+/// Name pointer, type, class, TTL, data length and the address
+const ANSWER_LENGTH: usize = 16;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const OPCODE_MASK: u16 = 0x7800;
+
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Pointer to the question name right after the header
+const NAME_POINTER: [u8; 2] = [0xC0, HEADER_LENGTH as u8];
+/// Short, so clients look the names up again once they're back on a real network
+const TTL_SECS: u32 = 60;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DnsError {
+    /// Not a single question query, or cut short
+    Malformed,
+    /// The reply buffer is smaller than `MAX_REPLY_SIZE`
+    BufferTooSmall,
+}
+
+/// Length of the question name at the start of `buf`, including the final empty label
+fn name_length(buf: &[u8]) -> Result<usize, DnsError> {
+    let mut offset = 0;
+    loop {
+        let label = *buf.get(offset).ok_or(DnsError::Malformed)? as usize;
+        offset += 1;
+        if label == 0 {
+            return Ok(offset);
+        }
+        // Queries never compress the name, a pointer here is as wrong as a long label
+        if label > 63 || offset + label > MAX_NAME_LENGTH {
+            return Err(DnsError::Malformed);
+        }
+        offset += label;
+    }
+}
+
+/// Answers `query` with `address`, returns the length of the reply written to `out`
+/// or `None` if the message needs no reply
+pub fn answer(query: &[u8], address: [u8; 4], out: &mut [u8]) -> Result<Option<usize>, DnsError> {
+    if out.len() < MAX_REPLY_SIZE {
+        return Err(DnsError::BufferTooSmall);
+    }
+    if query.len() < HEADER_LENGTH {
+        return Err(DnsError::Malformed);
+    }
+    let u16_at = |offset: usize| u16::from_be_bytes([query[offset], query[offset + 1]]);
+    let flags = u16_at(2);
+    if flags & FLAG_RESPONSE != 0 {
+        // Someone else's reply, answering it could start a loop
+        return Ok(None);
+    }
+    if flags & OPCODE_MASK != 0 || u16_at(4) != 1 {
+        return Err(DnsError::Malformed);
+    }
+
+    let question_length = name_length(&query[HEADER_LENGTH..])? + 4;
+    let question = query
+        .get(HEADER_LENGTH..HEADER_LENGTH + question_length)
+        .ok_or(DnsError::Malformed)?;
+    let qtype = u16::from_be_bytes([question[question_length - 4], question[question_length - 3]]);
+    let qclass = u16::from_be_bytes([question[question_length - 2], question[question_length - 1]]);
+    let answered = matches!(qtype, TYPE_A | TYPE_ANY) && qclass == CLASS_IN;
+
+    let reply_flags = FLAG_RESPONSE | FLAG_AUTHORITATIVE | (flags & FLAG_RECURSION_DESIRED);
+    out[0..2].copy_from_slice(&query[0..2]);
+    out[2..4].copy_from_slice(&reply_flags.to_be_bytes());
+    out[4..6].copy_from_slice(&1u16.to_be_bytes());
+    out[6..8].copy_from_slice(&(answered as u16).to_be_bytes());
+    out[8..12].fill(0);
+    let mut length = HEADER_LENGTH;
+    out[length..length + question_length].copy_from_slice(question);
+    length += question_length;
+
+    if answered {
+        let answer = &mut out[length..length + ANSWER_LENGTH];
+        answer[0..2].copy_from_slice(&NAME_POINTER);
+        answer[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+        answer[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+        answer[6..10].copy_from_slice(&TTL_SECS.to_be_bytes());
+        answer[10..12].copy_from_slice(&4u16.to_be_bytes());
+        answer[12..16].copy_from_slice(&address);
+        length += ANSWER_LENGTH;
+    }
+    Ok(Some(length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNIT: [u8; 4] = [192, 168, 4, 1];
+
+    /// `connectivitycheck.gstatic.com` as Android asks for it
+    fn query(qtype: u16) -> std::vec::Vec<u8> {
+        let mut query = std::vec![0xBE, 0xEF, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in ["connectivitycheck", "gstatic", "com"] {
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0);
+        query.extend_from_slice(&qtype.to_be_bytes());
+        query.extend_from_slice(&CLASS_IN.to_be_bytes());
+        query
+    }
+
+    #[test]
+    fn answers_a_queries_with_the_unit() {
+        let query = query(TYPE_A);
+        let mut out = [0; MAX_REPLY_SIZE];
+        let length = answer(&query, UNIT, &mut out).unwrap().unwrap();
+        assert_eq!(length, query.len() + ANSWER_LENGTH);
+        // Same ID, an authoritative response with recursion desired copied over
+        assert_eq!(out[0..4], [0xBE, 0xEF, 0x85, 0x00]);
+        assert_eq!(out[4..12], [0, 1, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(out[12..query.len()], query[12..]);
+        assert_eq!(
+            out[query.len()..length],
+            [0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 168, 4, 1]
+        );
+    }
+
+    #[test]
+    fn answers_other_types_with_no_records() {
+        const TYPE_AAAA: u16 = 28;
+        let query = query(TYPE_AAAA);
+        let mut out = [0; MAX_REPLY_SIZE];
+        assert_eq!(answer(&query, UNIT, &mut out), Ok(Some(query.len())));
+        assert_eq!(out[6..8], [0, 0]);
+    }
+
+    #[test]
+    fn ignores_responses() {
+        let mut response = query(TYPE_A);
+        response[2] |= 0x80;
+        let mut out = [0; MAX_REPLY_SIZE];
+        assert_eq!(answer(&response, UNIT, &mut out), Ok(None));
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        let mut out = [0; MAX_REPLY_SIZE];
+        let query = query(TYPE_A);
+        // Cut short in the header, the name and the type
+        for length in [4, 20, query.len() - 2] {
+            assert_eq!(
+                answer(&query[..length], UNIT, &mut out),
+                Err(DnsError::Malformed)
+            );
+        }
+
+        let mut two_questions = query.clone();
+        two_questions[5] = 2;
+        assert_eq!(
+            answer(&two_questions, UNIT, &mut out),
+            Err(DnsError::Malformed)
+        );
+
+        let mut compressed = query.clone();
+        compressed[12] = 0xC0;
+        assert_eq!(
+            answer(&compressed, UNIT, &mut out),
+            Err(DnsError::Malformed)
+        );
+
+        let mut status = query.clone();
+        status[2] = 0x10;
+        assert_eq!(answer(&status, UNIT, &mut out), Err(DnsError::Malformed));
+
+        assert_eq!(
+            answer(&query, UNIT, &mut out[..100]),
+            Err(DnsError::BufferTooSmall)
+        );
+    }
+}
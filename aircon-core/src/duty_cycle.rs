@@ -0,0 +1,168 @@
+//! Share of a rolling window the compressor ran for.
+//!
+//! The window is split into buckets that each collect the on time of a slice of it,
+//! the oldest one is dropped as time moves on. The share may include up to one bucket
+//! of time from before the window, so it errs on the side of resting too early.
+
+use embassy_time::{Duration, Instant};
+
+const BUCKETS: usize = 12;
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DutyCycleTracker {
+    window: Duration,
+    /// On time per slice, `current` is the one being filled
+    buckets: [Duration; BUCKETS],
+    current: usize,
+    bucket_start: Instant,
+    last_update: Instant,
+}
+
+impl DutyCycleTracker {
+    pub fn new(window: Duration, now: Instant) -> Self {
+        DutyCycleTracker {
+            window,
+            buckets: [Duration::from_ticks(0); BUCKETS],
+            current: 0,
+            bucket_start: now,
+            last_update: now,
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    fn bucket_length(&self) -> Duration {
+        Duration::from_ticks((self.window.as_ticks() / BUCKETS as u64).max(1))
+    }
+
+    /// Credits the time since the last call as on time if the relay was closed through it
+    pub fn update(&mut self, now: Instant, relay_was_on: bool) {
+        let Some(elapsed) = now.checked_duration_since(self.last_update) else {
+            return;
+        };
+        // Nothing in the buckets is recent enough to keep
+        if elapsed >= self.window {
+            let on_time = if relay_was_on {
+                self.bucket_length()
+            } else {
+                Duration::from_ticks(0)
+            };
+            *self = DutyCycleTracker::new(self.window, now);
+            self.buckets = [on_time; BUCKETS];
+            return;
+        }
+
+        let bucket_length = self.bucket_length();
+        loop {
+            let bucket_end = self.bucket_start + bucket_length;
+            let until = now.min(bucket_end);
+            if relay_was_on {
+                self.buckets[self.current] += until - self.last_update;
+            }
+            self.last_update = until;
+            if now < bucket_end {
+                break;
+            }
+            self.current = (self.current + 1) % BUCKETS;
+            self.buckets[self.current] = Duration::from_ticks(0);
+            self.bucket_start = bucket_end;
+        }
+    }
+
+    /// On time within about the last window
+    pub fn on_time(&self) -> Duration {
+        self.buckets
+            .iter()
+            .fold(Duration::from_ticks(0), |total, bucket| total + *bucket)
+    }
+
+    /// On time as a percentage of the window
+    pub fn percent(&self) -> u8 {
+        let window = self.window.as_ticks().max(1);
+        (self.on_time().as_ticks() * 100 / window).min(100) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(120);
+
+    fn at(secs: u64) -> Instant {
+        Instant::from_secs(secs)
+    }
+
+    #[test]
+    fn adds_up_the_on_time() {
+        let mut tracker = DutyCycleTracker::new(WINDOW, at(0));
+        tracker.update(at(5), true);
+        assert_eq!(tracker.on_time(), Duration::from_secs(5));
+        assert_eq!(tracker.percent(), 4);
+
+        // Across several buckets, off time adds nothing
+        tracker.update(at(30), false);
+        assert_eq!(tracker.on_time(), Duration::from_secs(5));
+        tracker.update(at(60), true);
+        assert_eq!(tracker.on_time(), Duration::from_secs(35));
+        assert_eq!(tracker.percent(), 29);
+    }
+
+    #[test]
+    fn drops_buckets_that_leave_the_window() {
+        let mut tracker = DutyCycleTracker::new(WINDOW, at(0));
+        tracker.update(at(10), true);
+        tracker.update(at(119), false);
+        assert_eq!(tracker.on_time(), Duration::from_secs(10));
+
+        // The first bucket is reused once the window moved past it
+        tracker.update(at(121), false);
+        assert_eq!(tracker.on_time(), Duration::from_secs(0));
+
+        // On time leaves a bucket at a time, 121 to 130 first and 130 to 135 next
+        tracker.update(at(135), true);
+        assert_eq!(tracker.on_time(), Duration::from_secs(14));
+        tracker.update(at(245), false);
+        assert_eq!(tracker.on_time(), Duration::from_secs(5));
+        tracker.update(at(250), false);
+        assert_eq!(tracker.on_time(), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn starts_over_after_a_whole_window() {
+        let mut tracker = DutyCycleTracker::new(WINDOW, at(0));
+        tracker.update(at(30), true);
+        // Missed updates for a whole window with the relay closed, it ran all along
+        tracker.update(at(150), true);
+        assert_eq!(tracker.on_time(), WINDOW);
+        assert_eq!(tracker.percent(), 100);
+
+        tracker.update(at(400), false);
+        assert_eq!(tracker.on_time(), Duration::from_secs(0));
+        tracker.update(at(410), true);
+        assert_eq!(tracker.on_time(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn ignores_time_going_backwards() {
+        let mut tracker = DutyCycleTracker::new(WINDOW, at(100));
+        tracker.update(at(110), true);
+        tracker.update(at(50), true);
+        assert_eq!(tracker.on_time(), Duration::from_secs(10));
+        tracker.update(at(115), true);
+        assert_eq!(tracker.on_time(), Duration::from_secs(15));
+    }
+
+    #[test]
+    fn percent_stops_at_100() {
+        // Shorter than a tick per bucket, the buckets hold more than the window
+        let window = Duration::from_ticks(5);
+        let mut tracker = DutyCycleTracker::new(window, at(0));
+        tracker.update(at(1), true);
+        assert!(tracker.on_time() > window);
+        assert_eq!(tracker.percent(), 100);
+    }
+}
@@ -0,0 +1,326 @@
+//! Home Assistant MQTT discovery.
+//!
+//! The device shows up as one HA device with a `climate` entity, a humidity `sensor`
+//! and a `binary_sensor` for the relay. Their state comes from the topics the MQTT
+//! client already publishes, so discovery only describes how to read them.
+
+use core::fmt::{self, Write};
+
+use heapless::String;
+
+use crate::temp_controller::ControllerState;
+use crate::units::TemperatureUnit;
+
+pub const DISCOVERY_PREFIX: &str = "homeassistant";
+
+// Jinja templates HA uses to pull values out of, or build, the JSON bodies the client exchanges
+const CURRENT_TEMPERATURE_TEMPLATE: &str = "{{ value_json.temperature }}";
+const TARGET_TEMPERATURE_TEMPLATE: &str = "{{ value_json.threshold_temperature }}";
+const TARGET_TEMPERATURE_COMMAND_TEMPLATE: &str = "{\\\"threshold_temperature\\\":{{ value }}}";
+const ACTION_TEMPLATE: &str = "{{ value_json.hvac_action }}";
+const HUMIDITY_TEMPLATE: &str = "{{ value_json.humidity }}";
+const RELAY_TEMPLATE: &str = "{{ 'ON' if value_json.relay else 'OFF' }}";
+
+/// Values HA accepts for a climate entity's `action`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HvacAction {
+    Idle,
+    Cooling,
+}
+
+impl HvacAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HvacAction::Idle => "idle",
+            HvacAction::Cooling => "cooling",
+        }
+    }
+}
+
+impl From<&ControllerState> for HvacAction {
+    fn from(state: &ControllerState) -> Self {
+        match state {
+            ControllerState::Idle => HvacAction::Idle,
+            ControllerState::Running { .. } => HvacAction::Cooling,
+            // The compressor is off while it rests, HA has no separate value for that
+            ControllerState::Cooldown { .. } => HvacAction::Idle,
+            ControllerState::Fault { relay, .. } if *relay => HvacAction::Cooling,
+            ControllerState::Fault { .. } => HvacAction::Idle,
+            ControllerState::Rest { .. } => HvacAction::Idle,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Component {
+    Climate,
+    Humidity,
+    Relay,
+}
+
+impl Component {
+    pub const ALL: [Component; 3] = [Component::Climate, Component::Humidity, Component::Relay];
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Component::Climate => "climate",
+            Component::Humidity => "sensor",
+            Component::Relay => "binary_sensor",
+        }
+    }
+
+    fn object_id(&self) -> &'static str {
+        match self {
+            Component::Climate => "climate",
+            Component::Humidity => "humidity",
+            Component::Relay => "relay",
+        }
+    }
+}
+
+/// What the discovery payloads need to know about this unit
+#[derive(Debug, Clone, Copy)]
+pub struct Device<'a> {
+    /// Unique for this board, see [`format_device_id`]
+    pub id: &'a str,
+    /// Prefix the MQTT client publishes its `sensor`, `status` and `config` topics under
+    pub topic_prefix: &'a str,
+    pub sw_version: &'a str,
+}
+
+/// Hex string of the flash unique ID, used as node ID and unique ID base
+pub fn format_device_id(unique_id: &[u8; 8]) -> String<16> {
+    let mut id = String::new();
+    for byte in unique_id {
+        let _ = write!(id, "{:02x}", byte);
+    }
+    id
+}
+
+/// `<discovery prefix>/<component>/<device id>/<object id>/config`
+pub fn discovery_topic(
+    out: &mut impl Write,
+    discovery_prefix: &str,
+    component: Component,
+    device: &Device<'_>,
+) -> fmt::Result {
+    write!(
+        out,
+        "{}/{}/{}/{}/config",
+        discovery_prefix,
+        component.kind(),
+        device.id,
+        component.object_id()
+    )
+}
+
+/// Discovery payload for `component`, temperatures are in `unit` like everything the client publishes
+pub fn render_discovery(
+    out: &mut impl Write,
+    component: Component,
+    device: &Device<'_>,
+    unit: TemperatureUnit,
+) -> fmt::Result {
+    write!(
+        out,
+        "{{\"~\":\"{}\",\"unique_id\":\"aircon_{}_{}\",\"availability_topic\":\"~/availability\",",
+        device.topic_prefix,
+        device.id,
+        component.object_id()
+    )?;
+    write!(
+        out,
+        "\"device\":{{\"identifiers\":[\"aircon_{}\"],\"name\":\"Air Conditioner\",\"model\":\"Pico W controller\",\"sw_version\":\"{}\"}},",
+        device.id, device.sw_version
+    )?;
+
+    match component {
+        Component::Climate => write!(
+            out,
+            "\"name\":null,\"modes\":[\"cool\"],\"precision\":0.1,\"temp_step\":0.5,\"temperature_unit\":\"{}\",\
+             \"current_temperature_topic\":\"~/sensor\",\"current_temperature_template\":\"{}\",\
+             \"temperature_state_topic\":\"~/config\",\"temperature_state_template\":\"{}\",\
+             \"temperature_command_topic\":\"~/set\",\"temperature_command_template\":\"{}\",\
+             \"action_topic\":\"~/status\",\"action_template\":\"{}\"}}",
+            match unit {
+                TemperatureUnit::Celsius => "C",
+                TemperatureUnit::Fahrenheit => "F",
+            },
+            CURRENT_TEMPERATURE_TEMPLATE,
+            TARGET_TEMPERATURE_TEMPLATE,
+            TARGET_TEMPERATURE_COMMAND_TEMPLATE,
+            ACTION_TEMPLATE,
+        ),
+        Component::Humidity => write!(
+            out,
+            "\"name\":\"Humidity\",\"device_class\":\"humidity\",\"state_class\":\"measurement\",\
+             \"unit_of_measurement\":\"%\",\"state_topic\":\"~/sensor\",\"value_template\":\"{}\"}}",
+            HUMIDITY_TEMPLATE,
+        ),
+        Component::Relay => write!(
+            out,
+            "\"name\":\"Compressor\",\"device_class\":\"running\",\
+             \"state_topic\":\"~/status\",\"value_template\":\"{}\"}}",
+            RELAY_TEMPLATE,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use embassy_time::Instant;
+
+    use crate::temp_controller::RuntimeLimit;
+
+    const DEVICE: Device<'static> = Device {
+        id: "e66038b7134f2a2c",
+        topic_prefix: "aircon/e66038b7134f2a2c",
+        sw_version: "0.1.0",
+    };
+
+    fn render(component: Component, unit: TemperatureUnit) -> std::string::String {
+        let mut out = std::string::String::new();
+        render_discovery(&mut out, component, &DEVICE, unit).unwrap();
+        out
+    }
+
+    /// The fields every payload starts with
+    const COMMON: &str = "{\"~\":\"aircon/e66038b7134f2a2c\",\
+        \"unique_id\":\"aircon_e66038b7134f2a2c_{object_id}\",\"availability_topic\":\"~/availability\",\
+        \"device\":{\"identifiers\":[\"aircon_e66038b7134f2a2c\"],\"name\":\"Air Conditioner\",\
+        \"model\":\"Pico W controller\",\"sw_version\":\"0.1.0\"},";
+
+    fn common(object_id: &str) -> std::string::String {
+        COMMON.replace("{object_id}", object_id)
+    }
+
+    #[test]
+    fn maps_every_state_to_an_action() {
+        let at = Instant::from_secs(5);
+        for (state, action) in [
+            (ControllerState::Idle, HvacAction::Idle),
+            (
+                ControllerState::Running { starttime: at },
+                HvacAction::Cooling,
+            ),
+            (
+                ControllerState::Cooldown { starttime: at },
+                HvacAction::Idle,
+            ),
+            (
+                ControllerState::Rest {
+                    starttime: at,
+                    limit: RuntimeLimit::DutyCycle,
+                },
+                HvacAction::Idle,
+            ),
+            // The failsafe running the compressor is cooling all the same
+            (
+                ControllerState::Fault {
+                    since: at,
+                    relay: true,
+                },
+                HvacAction::Cooling,
+            ),
+            (
+                ControllerState::Fault {
+                    since: at,
+                    relay: false,
+                },
+                HvacAction::Idle,
+            ),
+        ] {
+            assert_eq!(HvacAction::from(&state), action, "{state:?}");
+        }
+        assert_eq!(HvacAction::Idle.as_str(), "idle");
+        assert_eq!(HvacAction::Cooling.as_str(), "cooling");
+    }
+
+    #[test]
+    fn formats_the_device_id() {
+        let id = format_device_id(&[0xE6, 0x60, 0x38, 0xB7, 0x13, 0x4F, 0x2A, 0x2C]);
+        assert_eq!(id.as_str(), DEVICE.id);
+        assert_eq!(format_device_id(&[0; 8]).as_str(), "0000000000000000");
+    }
+
+    #[test]
+    fn names_a_topic_per_component() {
+        let topics: std::vec::Vec<_> = Component::ALL
+            .iter()
+            .map(|component| {
+                let mut topic = std::string::String::new();
+                discovery_topic(&mut topic, DISCOVERY_PREFIX, *component, &DEVICE).unwrap();
+                topic
+            })
+            .collect();
+        assert_eq!(
+            topics,
+            [
+                "homeassistant/climate/e66038b7134f2a2c/climate/config",
+                "homeassistant/sensor/e66038b7134f2a2c/humidity/config",
+                "homeassistant/binary_sensor/e66038b7134f2a2c/relay/config",
+            ]
+        );
+    }
+
+    #[test]
+    fn describes_the_climate_entity() {
+        let payload = render(Component::Climate, TemperatureUnit::Celsius);
+        assert_eq!(
+            payload,
+            common("climate")
+                + "\"name\":null,\"modes\":[\"cool\"],\"precision\":0.1,\"temp_step\":0.5,\
+                   \"temperature_unit\":\"C\",\
+                   \"current_temperature_topic\":\"~/sensor\",\
+                   \"current_temperature_template\":\"{{ value_json.temperature }}\",\
+                   \"temperature_state_topic\":\"~/config\",\
+                   \"temperature_state_template\":\"{{ value_json.threshold_temperature }}\",\
+                   \"temperature_command_topic\":\"~/set\",\
+                   \"temperature_command_template\":\"{\\\"threshold_temperature\\\":{{ value }}}\",\
+                   \"action_topic\":\"~/status\",\"action_template\":\"{{ value_json.hvac_action }}\"}"
+        );
+
+        let fahrenheit = render(Component::Climate, TemperatureUnit::Fahrenheit);
+        assert!(fahrenheit.contains("\"temperature_unit\":\"F\""));
+    }
+
+    #[test]
+    fn describes_the_sensors() {
+        assert_eq!(
+            render(Component::Humidity, TemperatureUnit::Celsius),
+            common("humidity")
+                + "\"name\":\"Humidity\",\"device_class\":\"humidity\",\"state_class\":\"measurement\",\
+                   \"unit_of_measurement\":\"%\",\"state_topic\":\"~/sensor\",\
+                   \"value_template\":\"{{ value_json.humidity }}\"}"
+        );
+        assert_eq!(
+            render(Component::Relay, TemperatureUnit::Celsius),
+            common("relay")
+                + "\"name\":\"Compressor\",\"device_class\":\"running\",\"state_topic\":\"~/status\",\
+                   \"value_template\":\"{{ 'ON' if value_json.relay else 'OFF' }}\"}"
+        );
+    }
+
+    #[test]
+    fn unique_ids_differ_per_component() {
+        let mut ids: std::vec::Vec<_> = Component::ALL
+            .iter()
+            .map(|component| {
+                let payload = render(*component, TemperatureUnit::Celsius);
+                let start = payload.find("\"unique_id\":\"").unwrap() + 13;
+                let end = start + payload[start..].find('"').unwrap();
+                payload[start..end].to_owned()
+            })
+            .collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), Component::ALL.len());
+        assert!(ids
+            .iter()
+            .all(|id| id.starts_with("aircon_e66038b7134f2a2c_")));
+    }
+}
@@ -0,0 +1,258 @@
+//! Just enough HTTP/1.1 to serve a handful of small requests, one per connection.
+
+use core::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Other,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Request<'a> {
+    pub method: Method,
+    /// Path without the query string
+    pub path: &'a str,
+    /// Value of the `If-None-Match` header, for revalidating cached pages
+    pub if_none_match: Option<&'a str>,
+    pub body: &'a [u8],
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParseError {
+    /// More bytes are needed, keep reading
+    Incomplete,
+    BadRequest,
+    /// The announced body doesn't fit in the receive buffer
+    TooLarge,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Status {
+    Ok,
+    NotModified,
+    BadRequest,
+    NotFound,
+    MethodNotAllowed,
+    PayloadTooLarge,
+    ServiceUnavailable,
+}
+
+impl Status {
+    pub fn code(&self) -> u16 {
+        match self {
+            Status::Ok => 200,
+            Status::NotModified => 304,
+            Status::BadRequest => 400,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::PayloadTooLarge => 413,
+            Status::ServiceUnavailable => 503,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::NotModified => "Not Modified",
+            Status::BadRequest => "Bad Request",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::PayloadTooLarge => "Payload Too Large",
+            Status::ServiceUnavailable => "Service Unavailable",
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Parses a request from the bytes received so far into a buffer of `capacity` bytes
+pub fn parse_request(buf: &[u8], capacity: usize) -> Result<Request<'_>, ParseError> {
+    let head_end = find(buf, b"\r\n\r\n").ok_or(ParseError::Incomplete)?;
+    let head = core::str::from_utf8(&buf[..head_end]).map_err(|_| ParseError::BadRequest)?;
+    let mut lines = head.split("\r\n");
+
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let method = match request_line.next() {
+        Some("GET") => Method::Get,
+        Some("HEAD") => Method::Head,
+        Some("POST") => Method::Post,
+        Some("PUT") => Method::Put,
+        Some(method) if !method.is_empty() => Method::Other,
+        _ => return Err(ParseError::BadRequest),
+    };
+    let target = request_line.next().ok_or(ParseError::BadRequest)?;
+    match request_line.next() {
+        Some(version) if version.starts_with("HTTP/1.") => {}
+        _ => return Err(ParseError::BadRequest),
+    }
+    let path = target.split('?').next().unwrap_or(target);
+    if !path.starts_with('/') {
+        return Err(ParseError::BadRequest);
+    }
+
+    let mut content_length = 0;
+    let mut if_none_match = None;
+    for line in lines {
+        let (name, value) = line.split_once(':').ok_or(ParseError::BadRequest)?;
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.trim().parse().map_err(|_| ParseError::BadRequest)?;
+        } else if name.eq_ignore_ascii_case("if-none-match") {
+            if_none_match = Some(value.trim());
+        }
+    }
+
+    let body_start = head_end + 4;
+    let body_end = body_start
+        .checked_add(content_length)
+        .filter(|end| *end <= capacity)
+        .ok_or(ParseError::TooLarge)?;
+    let body = buf
+        .get(body_start..body_end)
+        .ok_or(ParseError::Incomplete)?;
+    Ok(Request {
+        method,
+        path,
+        if_none_match,
+        body,
+    })
+}
+
+/// Writes the status line and headers, the connection is always closed after the response
+pub fn write_head(
+    out: &mut impl fmt::Write,
+    status: Status,
+    content_type: &str,
+    content_length: usize,
+    headers: &[(&str, &str)],
+) -> fmt::Result {
+    write!(
+        out,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status.code(),
+        status.reason(),
+        content_type,
+        content_length
+    )?;
+    for (name, value) in headers {
+        write!(out, "{}: {}\r\n", name, value)?;
+    }
+    out.write_str("\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::string::String;
+
+    const CAPACITY: usize = 1024;
+
+    #[test]
+    fn parses_a_recorded_curl_get() {
+        let raw = b"GET /api/status HTTP/1.1\r\nHost: 192.168.1.50\r\nUser-Agent: curl/8.5.0\r\nAccept: */*\r\n\r\n";
+        assert_eq!(
+            parse_request(raw, CAPACITY),
+            Ok(Request {
+                method: Method::Get,
+                path: "/api/status",
+                if_none_match: None,
+                body: b"",
+            })
+        );
+    }
+
+    #[test]
+    fn parses_a_recorded_browser_revalidation() {
+        let raw = b"GET /?refresh=1 HTTP/1.1\r\nHost: aircon.local\r\nConnection: keep-alive\r\nIf-None-Match: \"3f2a\"\r\nAccept-Encoding: gzip, deflate\r\nAccept-Language: en-GB,en;q=0.9\r\n\r\n";
+        let request = parse_request(raw, CAPACITY).unwrap();
+        assert_eq!(request.path, "/");
+        assert_eq!(request.if_none_match, Some("\"3f2a\""));
+    }
+
+    #[test]
+    fn parses_a_recorded_put_with_body() {
+        let raw = b"PUT /api/config HTTP/1.1\r\nHost: 192.168.1.50\r\nContent-Type: application/json\r\ncontent-length: 30\r\n\r\n{\"threshold_temperature\":22.5}";
+        let request = parse_request(raw, CAPACITY).unwrap();
+        assert_eq!(request.method, Method::Put);
+        assert_eq!(request.body, b"{\"threshold_temperature\":22.5}");
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_the_request() {
+        assert_eq!(
+            parse_request(b"GET / HTTP/1.1\r\nHost: a", CAPACITY),
+            Err(ParseError::Incomplete)
+        );
+        assert_eq!(
+            parse_request(
+                b"PUT /api/config HTTP/1.1\r\nContent-Length: 10\r\n\r\n{\"a\":",
+                CAPACITY
+            ),
+            Err(ParseError::Incomplete)
+        );
+    }
+
+    #[test]
+    fn rejects_a_body_that_cannot_fit() {
+        let too_large = |length: &str| {
+            let raw =
+                std::format!("PUT /api/config HTTP/1.1\r\nContent-Length: {length}\r\n\r\n{{}}");
+            parse_request(raw.as_bytes(), CAPACITY).map(|request| request.body.len())
+        };
+        assert_eq!(too_large("2000"), Err(ParseError::TooLarge));
+        assert_eq!(too_large("18446744073709551615"), Err(ParseError::TooLarge));
+        assert_eq!(
+            too_large("18446744073709551616"),
+            Err(ParseError::BadRequest)
+        );
+        assert_eq!(too_large("-1"), Err(ParseError::BadRequest));
+    }
+
+    #[test]
+    fn rejects_malformed_heads() {
+        for raw in [
+            &b"\r\n\r\n"[..],
+            b"GET\r\n\r\n",
+            b"GET / SPDY/3\r\n\r\n",
+            b"GET api HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/1.1\r\nno colon here\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: \xff\r\n\r\n",
+        ] {
+            assert_eq!(parse_request(raw, CAPACITY), Err(ParseError::BadRequest));
+        }
+        assert_eq!(
+            parse_request(b"DELETE / HTTP/1.0\r\n\r\n", CAPACITY).map(|request| request.method),
+            Ok(Method::Other)
+        );
+    }
+
+    #[test]
+    fn writes_the_head() {
+        let mut out = String::new();
+        write_head(
+            &mut out,
+            Status::NotModified,
+            "text/html",
+            0,
+            &[("ETag", "\"3f2a\"")],
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "HTTP/1.1 304 Not Modified\r\nContent-Type: text/html\r\nContent-Length: 0\r\nConnection: close\r\nETag: \"3f2a\"\r\n\r\n"
+        );
+    }
+}
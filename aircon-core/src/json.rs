@@ -0,0 +1,122 @@
+//! Minimal JSON support for flat objects such as `{"hysteresis": 1.5, "unit": "C"}`.
+//!
+//! Nested objects, arrays and escape sequences in strings are rejected, nothing
+//! the device accepts needs them.
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Value<'a> {
+    /// The number as written, parse it into whatever type the field needs
+    Number(&'a str),
+    String(&'a str),
+    Bool(bool),
+    Null,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum JsonError {
+    Syntax,
+    Unsupported,
+    UnknownField,
+    InvalidValue,
+}
+
+struct Cursor<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.text.as_bytes().get(self.position).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), JsonError> {
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(JsonError::Syntax)
+        }
+    }
+
+    fn string(&mut self) -> Result<&'a str, JsonError> {
+        self.expect(b'"')?;
+        let rest = &self.text[self.position..];
+        let end = rest.find('"').ok_or(JsonError::Syntax)?;
+        let string = &rest[..end];
+        if string.contains('\\') {
+            return Err(JsonError::Unsupported);
+        }
+        self.position += end + 1;
+        Ok(string)
+    }
+
+    fn literal(&mut self, literal: &str) -> Result<(), JsonError> {
+        if self.text[self.position..].starts_with(literal) {
+            self.position += literal.len();
+            Ok(())
+        } else {
+            Err(JsonError::Syntax)
+        }
+    }
+
+    fn value(&mut self) -> Result<Value<'a>, JsonError> {
+        match self.peek().ok_or(JsonError::Syntax)? {
+            b'"' => self.string().map(Value::String),
+            b't' => self.literal("true").map(|_| Value::Bool(true)),
+            b'f' => self.literal("false").map(|_| Value::Bool(false)),
+            b'n' => self.literal("null").map(|_| Value::Null),
+            b'{' | b'[' => Err(JsonError::Unsupported),
+            _ => {
+                let rest = &self.text[self.position..];
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+                    .unwrap_or(rest.len());
+                if end == 0 {
+                    return Err(JsonError::Syntax);
+                }
+                self.position += end;
+                Ok(Value::Number(&rest[..end]))
+            }
+        }
+    }
+}
+
+/// Calls `field` for every member of a flat JSON object
+pub fn parse_object<'a>(
+    text: &'a str,
+    mut field: impl FnMut(&'a str, Value<'a>) -> Result<(), JsonError>,
+) -> Result<(), JsonError> {
+    let mut cursor = Cursor { text, position: 0 };
+    cursor.expect(b'{')?;
+    if cursor.peek() == Some(b'}') {
+        cursor.position += 1;
+    } else {
+        loop {
+            let name = cursor.string()?;
+            cursor.expect(b':')?;
+            let value = cursor.value()?;
+            field(name, value)?;
+            match cursor.peek() {
+                Some(b',') => cursor.position += 1,
+                Some(b'}') => {
+                    cursor.position += 1;
+                    break;
+                }
+                _ => return Err(JsonError::Syntax),
+            }
+        }
+    }
+    if cursor.peek().is_some() {
+        return Err(JsonError::Syntax);
+    }
+    Ok(())
+}
@@ -0,0 +1,34 @@
+//! Platform independent logic of the air conditioning controller.
+//!
+//! Everything in here builds for both `thumbv6m-none-eabi` and the host, the
+//! firmware crate only wires the RP2040 peripherals into it.
+
+#![cfg_attr(not(test), no_std)]
+
+mod fmt;
+#[cfg(test)]
+mod mock_flash;
+
+pub mod api;
+pub mod backoff;
+pub mod cli;
+pub mod config_store;
+pub mod dhcp_server;
+pub mod dht11;
+pub mod dns_server;
+pub mod duty_cycle;
+pub mod home_assistant;
+pub mod http;
+pub mod json;
+pub mod metrics;
+pub mod mqtt;
+pub mod schedule;
+pub mod schedule_store;
+pub mod sector_store;
+pub mod setup;
+pub mod sntp;
+pub mod temp_controller;
+pub mod units;
+pub mod wall_clock;
+pub mod wifi;
+pub mod wifi_store;
@@ -0,0 +1,399 @@
+//! Prometheus text exposition of the controller state.
+//!
+//! Temperatures are always exported in °C regardless of the display unit, so
+//! dashboards don't break when someone switches units on the CLI.
+
+use core::fmt::{self, Display, Write};
+
+use embassy_time::Duration;
+
+use crate::dht11::{Dht11Error, Reading};
+use crate::temp_controller::{
+    CompressorStats, ControllerState, RuntimeLimit, TempControllerConfig,
+};
+use crate::wifi::{LinkState, WifiStatus};
+
+pub const CONTENT_TYPE_METRICS: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Failed sensor reads since boot, by kind
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SensorErrorCounts {
+    pub checksum: u32,
+    pub out_of_range: u32,
+    pub timeout: u32,
+}
+
+impl SensorErrorCounts {
+    pub fn record(&mut self, err: &Dht11Error) {
+        let count = match err {
+            Dht11Error::Checksum { .. } => &mut self.checksum,
+            Dht11Error::OutOfRange { .. } => &mut self.out_of_range,
+            Dht11Error::Timeout => &mut self.timeout,
+        };
+        *count = count.wrapping_add(1);
+    }
+}
+
+/// Everything exported on `/metrics`, values that aren't known yet are left out
+#[derive(Debug, Clone, Copy)]
+pub struct Metrics {
+    pub reading: Option<Reading>,
+    pub status: Option<(ControllerState, TempControllerConfig)>,
+    pub sensor_errors: SensorErrorCounts,
+    pub compressor: CompressorStats,
+    pub wifi: WifiStatus,
+    pub uptime: Duration,
+}
+
+/// Value of `aircon_controller_state`, documented in its HELP text
+fn state_code(state: &ControllerState) -> u8 {
+    match state {
+        ControllerState::Idle => 0,
+        ControllerState::Running { .. } => 1,
+        ControllerState::Cooldown { .. } => 2,
+        ControllerState::Fault { .. } => 3,
+        ControllerState::Rest { .. } => 4,
+    }
+}
+
+/// Value of `aircon_wifi_state`, documented in its HELP text
+fn link_state_code(state: LinkState) -> u8 {
+    match state {
+        LinkState::Disconnected => 0,
+        LinkState::Joining => 1,
+        LinkState::Dhcp => 2,
+        LinkState::Up => 3,
+        LinkState::Setup => 4,
+    }
+}
+
+fn header(out: &mut impl Write, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} {}", name, kind)
+}
+
+fn gauge(out: &mut impl Write, name: &str, help: &str, value: impl Display) -> fmt::Result {
+    header(out, name, "gauge", help)?;
+    writeln!(out, "{} {}", name, value)
+}
+
+fn counter(out: &mut impl Write, name: &str, help: &str, value: impl Display) -> fmt::Result {
+    header(out, name, "counter", help)?;
+    writeln!(out, "{} {}", name, value)
+}
+
+/// Writes one sample of a metric with a single label, escaping the label value as the
+/// text format requires
+fn sample(
+    out: &mut impl Write,
+    name: &str,
+    label: &str,
+    label_value: &str,
+    value: impl Display,
+) -> fmt::Result {
+    write!(out, "{}{{{}=\"", name, label)?;
+    for c in label_value.chars() {
+        match c {
+            '\\' => out.write_str("\\\\")?,
+            '"' => out.write_str("\\\"")?,
+            '\n' => out.write_str("\\n")?,
+            c => out.write_char(c)?,
+        }
+    }
+    writeln!(out, "\"}} {}", value)
+}
+
+/// Seconds with millisecond resolution
+struct Seconds(Duration);
+
+impl Display for Seconds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.0.as_millis();
+        write!(f, "{}.{:03}", millis / 1000, millis % 1000)
+    }
+}
+
+pub fn render_metrics(out: &mut impl Write, metrics: &Metrics) -> fmt::Result {
+    if let Some(reading) = &metrics.reading {
+        gauge(
+            out,
+            "aircon_temperature_celsius",
+            "Last temperature read from the sensor.",
+            reading.temperature,
+        )?;
+        gauge(
+            out,
+            "aircon_humidity_percent",
+            "Last relative humidity read from the sensor.",
+            reading.humidity,
+        )?;
+        // Instants count from boot, same as the uptime
+        let taken_at = Duration::from_ticks(reading.taken_at.as_ticks());
+        gauge(
+            out,
+            "aircon_sensor_reading_age_seconds",
+            "Time since the last valid reading, the controller faults once it exceeds the sensor timeout.",
+            Seconds(metrics.uptime.checked_sub(taken_at).unwrap_or_default()),
+        )?;
+    }
+
+    if let Some((state, config)) = &metrics.status {
+        gauge(
+            out,
+            "aircon_relay_on",
+            "1 while the compressor relay is closed.",
+            state.is_relay_on() as u8,
+        )?;
+        gauge(
+            out,
+            "aircon_controller_state",
+            "Controller state, 0 idle, 1 running, 2 cooldown, 3 fault for a stale sensor, 4 rest forced by a runtime limit.",
+            state_code(state),
+        )?;
+        gauge(
+            out,
+            "aircon_threshold_temperature_celsius",
+            "Temperature above which the compressor starts.",
+            config.threshold_temperature,
+        )?;
+        gauge(
+            out,
+            "aircon_hysteresis_celsius",
+            "How far below the threshold the compressor keeps running.",
+            config.hysteresis,
+        )?;
+        gauge(
+            out,
+            "aircon_minimum_runtime_seconds",
+            "Shortest time the compressor runs once started.",
+            config.minimum_runtime.as_secs(),
+        )?;
+        gauge(
+            out,
+            "aircon_cooldown_time_seconds",
+            "Shortest time the compressor rests between runs.",
+            config.cooldown_time.as_secs(),
+        )?;
+    }
+
+    let name = "aircon_sensor_errors_total";
+    header(out, name, "counter", "Failed sensor reads by kind.")?;
+    let errors = &metrics.sensor_errors;
+    sample(out, name, "kind", "checksum", errors.checksum)?;
+    sample(out, name, "kind", "out_of_range", errors.out_of_range)?;
+    sample(out, name, "kind", "timeout", errors.timeout)?;
+
+    gauge(
+        out,
+        "aircon_wifi_state",
+        "Wi-Fi link, 0 disconnected, 1 joining, 2 waiting for DHCP, 3 up, 4 setup access point.",
+        link_state_code(metrics.wifi.state),
+    )?;
+    gauge(
+        out,
+        "aircon_wifi_retries",
+        "Failed join or DHCP attempts since the link was last up.",
+        metrics.wifi.retries,
+    )?;
+    if let Some(rssi) = metrics.wifi.rssi_dbm {
+        gauge(
+            out,
+            "aircon_wifi_rssi_dbm",
+            "Signal strength of the Wi-Fi connection.",
+            rssi,
+        )?;
+    }
+    gauge(
+        out,
+        "aircon_uptime_seconds",
+        "Time since boot.",
+        Seconds(metrics.uptime),
+    )?;
+    counter(
+        out,
+        "aircon_compressor_starts_total",
+        "Compressor starts since boot.",
+        metrics.compressor.starts,
+    )?;
+    counter(
+        out,
+        "aircon_compressor_runtime_seconds_total",
+        "Time the compressor has been running since boot.",
+        Seconds(metrics.compressor.runtime),
+    )?;
+
+    let name = "aircon_compressor_forced_rests_total";
+    header(
+        out,
+        name,
+        "counter",
+        "Rests forced by a runtime limit, by limit.",
+    )?;
+    let compressor = &metrics.compressor;
+    sample(
+        out,
+        name,
+        "limit",
+        RuntimeLimit::MaximumRuntime.name(),
+        compressor.maximum_runtime_rests,
+    )?;
+    sample(
+        out,
+        name,
+        "limit",
+        RuntimeLimit::DutyCycle.name(),
+        compressor.duty_cycle_rests,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::string::String;
+    use std::vec::Vec;
+
+    use embassy_time::Instant;
+
+    use crate::units::{Humidity, Temperature};
+
+    fn metrics() -> Metrics {
+        Metrics {
+            reading: Some(Reading {
+                temperature: Temperature::from_tenths(-35),
+                humidity: Humidity::from_tenths(412),
+                taken_at: Instant::from_millis(10_000),
+            }),
+            status: Some((
+                ControllerState::Running {
+                    starttime: Instant::from_secs(5),
+                },
+                TempControllerConfig {
+                    threshold_temperature: Temperature::from_tenths(225),
+                    hysteresis: Temperature::from_tenths(5),
+                    ..TempControllerConfig::default()
+                },
+            )),
+            sensor_errors: SensorErrorCounts {
+                checksum: 3,
+                out_of_range: 0,
+                timeout: 1,
+            },
+            compressor: CompressorStats {
+                starts: 2,
+                runtime: Duration::from_millis(61_250),
+                maximum_runtime_rests: 1,
+                duty_cycle_rests: 0,
+            },
+            wifi: WifiStatus {
+                state: LinkState::Up,
+                rssi_dbm: Some(-58),
+                retries: 0,
+            },
+            uptime: Duration::from_millis(12_345),
+        }
+    }
+
+    fn render(metrics: &Metrics) -> String {
+        let mut out = String::new();
+        render_metrics(&mut out, metrics).unwrap();
+        out
+    }
+
+    #[test]
+    fn renders_values_in_tenths_and_seconds() {
+        let out = render(&metrics());
+        for line in [
+            "aircon_temperature_celsius -3.5",
+            "aircon_humidity_percent 41.2",
+            "aircon_sensor_reading_age_seconds 2.345",
+            "aircon_relay_on 1",
+            "aircon_controller_state 1",
+            "aircon_threshold_temperature_celsius 22.5",
+            "aircon_hysteresis_celsius 0.5",
+            "aircon_sensor_errors_total{kind=\"checksum\"} 3",
+            "aircon_sensor_errors_total{kind=\"out_of_range\"} 0",
+            "aircon_sensor_errors_total{kind=\"timeout\"} 1",
+            "aircon_wifi_state 3",
+            "aircon_wifi_rssi_dbm -58",
+            "aircon_uptime_seconds 12.345",
+            "aircon_compressor_starts_total 2",
+            "aircon_compressor_runtime_seconds_total 61.250",
+            "aircon_compressor_forced_rests_total{limit=\"max_runtime\"} 1",
+            "aircon_compressor_forced_rests_total{limit=\"duty_cycle\"} 0",
+        ] {
+            assert!(out.lines().any(|rendered| rendered == line), "{line}");
+        }
+    }
+
+    #[test]
+    fn every_sample_follows_its_help_and_type() {
+        let out = render(&metrics());
+        let lines: Vec<&str> = out.lines().collect();
+        let mut described = None;
+        for (index, line) in lines.iter().enumerate() {
+            if let Some(help) = line.strip_prefix("# HELP ") {
+                let (name, text) = help.split_once(' ').unwrap();
+                assert!(!text.is_empty());
+                let kind = lines[index + 1]
+                    .strip_prefix("# TYPE ")
+                    .and_then(|rest| rest.strip_prefix(name))
+                    .unwrap();
+                assert!(matches!(kind, " gauge" | " counter"), "{line}");
+                if kind == " counter" {
+                    assert!(name.ends_with("_total"), "{name}");
+                }
+                described = Some(name);
+            } else if !line.starts_with("# TYPE ") {
+                let name = line.split(['{', ' ']).next().unwrap();
+                assert_eq!(Some(name), described, "{line}");
+            }
+        }
+        assert!(out.ends_with('\n'));
+    }
+
+    #[test]
+    fn leaves_out_what_is_not_known_yet() {
+        let out = render(&Metrics {
+            reading: None,
+            status: None,
+            wifi: WifiStatus {
+                state: LinkState::Joining,
+                rssi_dbm: None,
+                retries: 2,
+            },
+            ..metrics()
+        });
+        assert!(!out.contains("aircon_temperature_celsius"));
+        assert!(!out.contains("aircon_relay_on"));
+        assert!(!out.contains("aircon_wifi_rssi_dbm"));
+        assert!(out.contains("\naircon_wifi_retries 2\n"));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        let mut out = String::new();
+        sample(&mut out, "aircon_test", "ssid", "a \"b\" c\\d\ne", 1).unwrap();
+        assert_eq!(out, "aircon_test{ssid=\"a \\\"b\\\" c\\\\d\\ne\"} 1\n");
+    }
+
+    #[test]
+    fn counts_sensor_errors_by_kind() {
+        let mut counts = SensorErrorCounts::default();
+        counts.record(&Dht11Error::Timeout);
+        counts.record(&Dht11Error::Timeout);
+        counts.record(&Dht11Error::Checksum {
+            expected: 1,
+            actual: 2,
+        });
+        assert_eq!(
+            counts,
+            SensorErrorCounts {
+                checksum: 1,
+                out_of_range: 0,
+                timeout: 2,
+            }
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! NOR flash in RAM for the store tests.
+//!
+//! Behaves like the real thing where the stores rely on it: erasing sets whole
+//! sectors to 0xFF, and programming a byte that isn't erased is a bug in the store,
+//! so it panics instead of silently ANDing the bits.
+
+use std::vec;
+use std::vec::Vec;
+
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash,
+};
+
+pub const SECTOR_SIZE: usize = 4096;
+
+pub struct MockFlash {
+    pub data: Vec<u8>,
+    pub erases: usize,
+}
+
+impl MockFlash {
+    /// Blank flash of `sectors` erase sectors
+    pub fn new(sectors: usize) -> Self {
+        MockFlash {
+            data: vec![0xFF; sectors * SECTOR_SIZE],
+            erases: 0,
+        }
+    }
+}
+
+impl ErrorType for MockFlash {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for MockFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for MockFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        self.data[from as usize..to as usize].fill(0xFF);
+        self.erases += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        let target = &mut self.data[offset..offset + bytes.len()];
+        assert!(
+            target.iter().all(|byte| *byte == 0xFF),
+            "programming flash at {offset:#x} that wasn't erased"
+        );
+        target.copy_from_slice(bytes);
+        Ok(())
+    }
+}
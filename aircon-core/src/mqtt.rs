@@ -0,0 +1,649 @@
+//! Encoder and decoder for the subset of MQTT 3.1.1 the device uses.
+//!
+//! Outgoing messages are always QoS 0. Incoming publishes are decoded at any QoS
+//! so a QoS 1 message from the broker can still be acknowledged.
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EncodeError {
+    BufferTooSmall,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DecodeError {
+    /// More bytes are needed for a complete packet
+    Incomplete,
+    Malformed,
+}
+
+/// Message the broker publishes on our behalf when the connection drops
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub retain: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Connect<'a> {
+    pub client_id: &'a str,
+    pub keep_alive_secs: u16,
+    pub will: Option<Will<'a>>,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Packet<'a> {
+    ConnAck {
+        session_present: bool,
+        /// 0 means the connection was accepted
+        return_code: u8,
+    },
+    Publish {
+        topic: &'a str,
+        payload: &'a [u8],
+        qos: u8,
+        /// Only present for QoS 1 and 2
+        packet_id: Option<u16>,
+    },
+    SubAck {
+        packet_id: u16,
+        /// Granted QoS, 0x80 means the subscription was refused
+        return_code: u8,
+    },
+    PingResp,
+    /// Any other packet type, identified by the upper nibble of its first byte
+    Other(u8),
+}
+
+struct Encoder<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Encoder<'_> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), EncodeError> {
+        let end = self.len + bytes.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(EncodeError::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn u8(&mut self, value: u8) -> Result<(), EncodeError> {
+        self.bytes(&[value])
+    }
+
+    fn u16(&mut self, value: u16) -> Result<(), EncodeError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// Length prefixed string or binary data
+    fn data(&mut self, data: &[u8]) -> Result<(), EncodeError> {
+        let len = u16::try_from(data.len()).map_err(|_| EncodeError::BufferTooSmall)?;
+        self.u16(len)?;
+        self.bytes(data)
+    }
+
+    fn fixed_header(&mut self, first_byte: u8, remaining_length: usize) -> Result<(), EncodeError> {
+        self.u8(first_byte)?;
+        let mut remaining = remaining_length;
+        loop {
+            let mut byte = (remaining % 128) as u8;
+            remaining /= 128;
+            if remaining > 0 {
+                byte |= 0x80;
+            }
+            self.u8(byte)?;
+            if remaining == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Encodes a CONNECT packet with a clean session, returns its length
+pub fn encode_connect(buf: &mut [u8], connect: &Connect<'_>) -> Result<usize, EncodeError> {
+    let data_len = |data: &[u8]| 2 + data.len();
+    let mut flags = 0x02;
+    let mut remaining = 10 + data_len(connect.client_id.as_bytes());
+    if let Some(will) = &connect.will {
+        flags |= 0x04;
+        if will.retain {
+            flags |= 0x20;
+        }
+        remaining += data_len(will.topic.as_bytes()) + data_len(will.payload);
+    }
+    if let Some(username) = connect.username {
+        flags |= 0x80;
+        remaining += data_len(username.as_bytes());
+    }
+    if let Some(password) = connect.password {
+        flags |= 0x40;
+        remaining += data_len(password.as_bytes());
+    }
+
+    let mut encoder = Encoder { buf, len: 0 };
+    encoder.fixed_header(0x10, remaining)?;
+    encoder.data(b"MQTT")?;
+    encoder.u8(4)?;
+    encoder.u8(flags)?;
+    encoder.u16(connect.keep_alive_secs)?;
+    encoder.data(connect.client_id.as_bytes())?;
+    if let Some(will) = &connect.will {
+        encoder.data(will.topic.as_bytes())?;
+        encoder.data(will.payload)?;
+    }
+    if let Some(username) = connect.username {
+        encoder.data(username.as_bytes())?;
+    }
+    if let Some(password) = connect.password {
+        encoder.data(password.as_bytes())?;
+    }
+    Ok(encoder.len)
+}
+
+/// Encodes a QoS 0 PUBLISH packet, returns its length
+pub fn encode_publish(
+    buf: &mut [u8],
+    topic: &str,
+    payload: &[u8],
+    retain: bool,
+) -> Result<usize, EncodeError> {
+    let mut encoder = Encoder { buf, len: 0 };
+    encoder.fixed_header(0x30 | retain as u8, 2 + topic.len() + payload.len())?;
+    encoder.data(topic.as_bytes())?;
+    encoder.bytes(payload)?;
+    Ok(encoder.len)
+}
+
+/// Encodes a SUBSCRIBE packet for a single topic filter at QoS 0, returns its length
+pub fn encode_subscribe(buf: &mut [u8], packet_id: u16, topic: &str) -> Result<usize, EncodeError> {
+    let mut encoder = Encoder { buf, len: 0 };
+    encoder.fixed_header(0x82, 2 + 2 + topic.len() + 1)?;
+    encoder.u16(packet_id)?;
+    encoder.data(topic.as_bytes())?;
+    encoder.u8(0)?;
+    Ok(encoder.len)
+}
+
+pub fn encode_puback(buf: &mut [u8], packet_id: u16) -> Result<usize, EncodeError> {
+    let mut encoder = Encoder { buf, len: 0 };
+    encoder.fixed_header(0x40, 2)?;
+    encoder.u16(packet_id)?;
+    Ok(encoder.len)
+}
+
+pub fn encode_pingreq(buf: &mut [u8]) -> Result<usize, EncodeError> {
+    let mut encoder = Encoder { buf, len: 0 };
+    encoder.fixed_header(0xC0, 0)?;
+    Ok(encoder.len)
+}
+
+pub fn encode_disconnect(buf: &mut [u8]) -> Result<usize, EncodeError> {
+    let mut encoder = Encoder { buf, len: 0 };
+    encoder.fixed_header(0xE0, 0)?;
+    Ok(encoder.len)
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> Result<u16, DecodeError> {
+    match buf.get(offset..offset + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None => Err(DecodeError::Malformed),
+    }
+}
+
+/// Decodes the first packet in `buf`, returning it and the number of bytes it used
+pub fn decode_packet(buf: &[u8]) -> Result<(Packet<'_>, usize), DecodeError> {
+    let first_byte = *buf.first().ok_or(DecodeError::Incomplete)?;
+
+    let mut remaining_length = 0usize;
+    let mut header_length = 1;
+    loop {
+        let byte = *buf.get(header_length).ok_or(DecodeError::Incomplete)?;
+        remaining_length |= ((byte & 0x7F) as usize) << (7 * (header_length - 1));
+        header_length += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if header_length > 4 {
+            return Err(DecodeError::Malformed);
+        }
+    }
+    let total_length = header_length + remaining_length;
+    let body = buf
+        .get(header_length..total_length)
+        .ok_or(DecodeError::Incomplete)?;
+
+    let packet = match first_byte >> 4 {
+        2 => {
+            if body.len() != 2 {
+                return Err(DecodeError::Malformed);
+            }
+            Packet::ConnAck {
+                session_present: body[0] & 0x01 != 0,
+                return_code: body[1],
+            }
+        }
+        3 => {
+            let qos = (first_byte >> 1) & 0x03;
+            let topic_length = u16_at(body, 0)? as usize;
+            let topic = body
+                .get(2..2 + topic_length)
+                .ok_or(DecodeError::Malformed)?;
+            let topic = core::str::from_utf8(topic).map_err(|_| DecodeError::Malformed)?;
+            let mut offset = 2 + topic_length;
+            let packet_id = if qos > 0 {
+                offset += 2;
+                Some(u16_at(body, offset - 2)?)
+            } else {
+                None
+            };
+            Packet::Publish {
+                topic,
+                payload: body.get(offset..).ok_or(DecodeError::Malformed)?,
+                qos,
+                packet_id,
+            }
+        }
+        9 => Packet::SubAck {
+            packet_id: u16_at(body, 0)?,
+            return_code: *body.get(2).ok_or(DecodeError::Malformed)?,
+        },
+        13 => Packet::PingResp,
+        packet_type => Packet::Other(packet_type),
+    };
+    Ok((packet, total_length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::string::String;
+    use std::vec::Vec;
+
+    /// Broker end of a connection, checks what the client sends and answers like a
+    /// broker would
+    #[derive(Default)]
+    struct FakeBroker {
+        /// Return code of the CONNACK, 0 accepts the connection
+        connack_code: u8,
+        refuse_subscriptions: bool,
+        client_id: String,
+        will: Option<(String, Vec<u8>, bool)>,
+        credentials: Option<(String, String)>,
+        subscriptions: Vec<String>,
+        published: Vec<(String, Vec<u8>, bool)>,
+        acked: Vec<u16>,
+        /// Bytes on their way to the client
+        outbox: Vec<u8>,
+    }
+
+    /// Reads what `Encoder::data` wrote at the start of `buf`, returns it and the rest
+    fn split_data(buf: &[u8]) -> (&[u8], &[u8]) {
+        let length = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+        buf[2..].split_at(length)
+    }
+
+    fn text(bytes: &[u8]) -> String {
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    impl FakeBroker {
+        /// Takes one complete packet from the client
+        fn receive(&mut self, packet: &[u8]) {
+            let mut remaining_length = 0;
+            let mut header_length = 1;
+            loop {
+                let byte = packet[header_length];
+                remaining_length |= ((byte & 0x7F) as usize) << (7 * (header_length - 1));
+                header_length += 1;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            let body = &packet[header_length..];
+            assert_eq!(body.len(), remaining_length, "remaining length");
+
+            match packet[0] {
+                0x10 => self.connect(body),
+                0x82 => {
+                    let packet_id = u16::from_be_bytes([body[0], body[1]]);
+                    let (topic, rest) = split_data(&body[2..]);
+                    assert_eq!(rest, [0], "requested QoS");
+                    self.subscriptions.push(text(topic));
+                    let granted = if self.refuse_subscriptions { 0x80 } else { 0 };
+                    let [id_high, id_low] = packet_id.to_be_bytes();
+                    self.outbox.extend([0x90, 3, id_high, id_low, granted]);
+                }
+                first_byte @ (0x30 | 0x31) => {
+                    let (topic, payload) = split_data(body);
+                    self.published
+                        .push((text(topic), payload.to_vec(), first_byte & 0x01 != 0));
+                }
+                0x40 => self.acked.push(u16::from_be_bytes([body[0], body[1]])),
+                0xC0 => self.outbox.extend([0xD0, 0]),
+                0xE0 => {}
+                first_byte => panic!("unexpected packet {first_byte:#x}"),
+            }
+        }
+
+        fn connect(&mut self, body: &[u8]) {
+            let (protocol, rest) = split_data(body);
+            assert_eq!(protocol, b"MQTT");
+            assert_eq!(rest[0], 4, "protocol level");
+            let flags = rest[1];
+            assert_eq!(flags & 0x03, 0x02, "clean session, reserved bit clear");
+
+            let (client_id, mut rest) = split_data(&rest[4..]);
+            self.client_id = text(client_id);
+            if flags & 0x04 != 0 {
+                let (topic, after_topic) = split_data(rest);
+                let (payload, after_payload) = split_data(after_topic);
+                self.will = Some((text(topic), payload.to_vec(), flags & 0x20 != 0));
+                rest = after_payload;
+            }
+            if flags & 0x80 != 0 {
+                let (username, after_username) = split_data(rest);
+                let (password, after_password) = split_data(after_username);
+                assert_ne!(flags & 0x40, 0, "password flag");
+                self.credentials = Some((text(username), text(password)));
+                rest = after_password;
+            }
+            assert!(rest.is_empty(), "trailing bytes in CONNECT");
+            self.outbox.extend([0x20, 2, 0, self.connack_code]);
+        }
+
+        /// Sends the client a QoS 1 publish, written out by hand
+        fn deliver(&mut self, topic: &str, payload: &[u8], packet_id: u16) {
+            let mut remaining = 2 + topic.len() + 2 + payload.len();
+            self.outbox.push(0x32);
+            loop {
+                let byte = (remaining % 128) as u8;
+                remaining /= 128;
+                self.outbox
+                    .push(if remaining > 0 { byte | 0x80 } else { byte });
+                if remaining == 0 {
+                    break;
+                }
+            }
+            self.outbox.extend((topic.len() as u16).to_be_bytes());
+            self.outbox.extend(topic.as_bytes());
+            self.outbox.extend(packet_id.to_be_bytes());
+            self.outbox.extend(payload);
+        }
+    }
+
+    /// Client end, receives from the broker `chunk` bytes at a time like a socket
+    /// handing over whatever arrived so far
+    struct Client {
+        received: Vec<u8>,
+        chunk: usize,
+        /// Reads from the socket so far
+        reads: usize,
+    }
+
+    impl Client {
+        fn new(chunk: usize) -> Self {
+            Client {
+                received: Vec::new(),
+                chunk,
+                reads: 0,
+            }
+        }
+
+        fn send(
+            &self,
+            broker: &mut FakeBroker,
+            encode: impl FnOnce(&mut [u8]) -> Result<usize, EncodeError>,
+        ) {
+            let mut buf = [0; 512];
+            let length = encode(&mut buf).unwrap();
+            broker.receive(&buf[..length]);
+        }
+
+        /// Reads until a whole packet is in, returns its bytes
+        fn next_packet(&mut self, broker: &mut FakeBroker) -> Vec<u8> {
+            loop {
+                match decode_packet(&self.received) {
+                    Ok((_, length)) => return self.received.drain(..length).collect(),
+                    Err(DecodeError::Incomplete) => {
+                        assert!(
+                            !broker.outbox.is_empty(),
+                            "waiting for bytes that never come"
+                        );
+                        let chunk = self.chunk.min(broker.outbox.len());
+                        self.received.extend(broker.outbox.drain(..chunk));
+                        self.reads += 1;
+                    }
+                    Err(err) => panic!("decode error {err:?}"),
+                }
+            }
+        }
+    }
+
+    fn decoded(bytes: &[u8]) -> Packet<'_> {
+        decode_packet(bytes).unwrap().0
+    }
+
+    fn connect() -> Connect<'static> {
+        Connect {
+            client_id: "aircon-1a2b",
+            keep_alive_secs: 60,
+            will: Some(Will {
+                topic: "aircon/1a2b/availability",
+                payload: b"offline",
+                retain: true,
+            }),
+            username: Some("aircon"),
+            password: Some("hunter2"),
+        }
+    }
+
+    #[test]
+    fn runs_a_session_against_the_broker() {
+        let mut broker = FakeBroker::default();
+        let mut client = Client::new(1);
+
+        client.send(&mut broker, |out| encode_connect(out, &connect()));
+        assert_eq!(broker.client_id, "aircon-1a2b");
+        assert_eq!(
+            broker.will,
+            Some((
+                String::from("aircon/1a2b/availability"),
+                b"offline".to_vec(),
+                true
+            ))
+        );
+        assert_eq!(
+            broker.credentials,
+            Some((String::from("aircon"), String::from("hunter2")))
+        );
+        assert_eq!(
+            decode_packet(&client.next_packet(&mut broker)),
+            Ok((
+                Packet::ConnAck {
+                    session_present: false,
+                    return_code: 0
+                },
+                4
+            ))
+        );
+        // One byte per read, the first three were partial packets
+        assert_eq!(client.reads, 4);
+
+        client.send(&mut broker, |out| {
+            encode_subscribe(out, 7, "aircon/1a2b/config/set")
+        });
+        assert_eq!(broker.subscriptions, ["aircon/1a2b/config/set"]);
+        assert_eq!(
+            decoded(&client.next_packet(&mut broker)),
+            Packet::SubAck {
+                packet_id: 7,
+                return_code: 0
+            }
+        );
+
+        client.send(&mut broker, |out| {
+            encode_publish(out, "aircon/1a2b/availability", b"online", true)
+        });
+        client.send(&mut broker, |out| {
+            encode_publish(out, "aircon/1a2b/sensor", b"{\"temperature\":23.5}", false)
+        });
+        assert_eq!(
+            broker.published,
+            [
+                (
+                    String::from("aircon/1a2b/availability"),
+                    b"online".to_vec(),
+                    true
+                ),
+                (
+                    String::from("aircon/1a2b/sensor"),
+                    b"{\"temperature\":23.5}".to_vec(),
+                    false
+                ),
+            ]
+        );
+
+        client.send(&mut broker, encode_pingreq);
+        assert_eq!(decoded(&client.next_packet(&mut broker)), Packet::PingResp);
+        client.send(&mut broker, encode_disconnect);
+    }
+
+    #[test]
+    fn receives_a_long_publish_in_pieces() {
+        let mut broker = FakeBroker::default();
+        let mut client = Client::new(5);
+        let payload = "{\"threshold_temperature\":22.5,\"hysteresis\":0.5}".repeat(4);
+        // Long enough for a two byte remaining length
+        assert!(payload.len() > 127);
+        broker.deliver("aircon/1a2b/config/set", payload.as_bytes(), 300);
+        broker.deliver("aircon/1a2b/config/set", b"{}", 301);
+
+        let bytes = client.next_packet(&mut broker);
+        let (packet, length) = decode_packet(&bytes).unwrap();
+        assert_eq!(
+            packet,
+            Packet::Publish {
+                topic: "aircon/1a2b/config/set",
+                payload: payload.as_bytes(),
+                qos: 1,
+                packet_id: Some(300),
+            }
+        );
+        assert_eq!(length, 3 + 2 + 22 + 2 + payload.len());
+        assert!(client.reads > 1);
+        client.send(&mut broker, |out| encode_puback(out, 300));
+
+        // The second packet started in the last read of the first one
+        assert!(!client.received.is_empty());
+        let bytes = client.next_packet(&mut broker);
+        let packet = decoded(&bytes);
+        assert!(matches!(
+            packet,
+            Packet::Publish {
+                packet_id: Some(301),
+                ..
+            }
+        ));
+        client.send(&mut broker, |out| encode_puback(out, 301));
+        assert_eq!(broker.acked, [300, 301]);
+    }
+
+    #[test]
+    fn reports_a_rejected_connection() {
+        // 5 is "not authorised"
+        let mut broker = FakeBroker {
+            connack_code: 5,
+            ..FakeBroker::default()
+        };
+        let mut client = Client::new(3);
+        client.send(&mut broker, |out| encode_connect(out, &connect()));
+        assert_eq!(
+            decoded(&client.next_packet(&mut broker)),
+            Packet::ConnAck {
+                session_present: false,
+                return_code: 5
+            }
+        );
+    }
+
+    #[test]
+    fn reports_a_refused_subscription() {
+        let mut broker = FakeBroker {
+            refuse_subscriptions: true,
+            ..FakeBroker::default()
+        };
+        let mut client = Client::new(64);
+        client.send(&mut broker, |out| encode_subscribe(out, 1, "aircon/#"));
+        assert_eq!(
+            decoded(&client.next_packet(&mut broker)),
+            Packet::SubAck {
+                packet_id: 1,
+                return_code: 0x80
+            }
+        );
+    }
+
+    #[test]
+    fn encodes_a_minimal_connect() {
+        let mut buf = [0; 32];
+        let connect = Connect {
+            client_id: "ac",
+            keep_alive_secs: 60,
+            will: None,
+            username: None,
+            password: None,
+        };
+        let length = encode_connect(&mut buf, &connect).unwrap();
+        assert_eq!(
+            buf[..length],
+            [0x10, 14, 0, 4, b'M', b'Q', b'T', b'T', 4, 0x02, 0, 60, 0, 2, b'a', b'c']
+        );
+    }
+
+    #[test]
+    fn refuses_to_overrun_the_buffer() {
+        let mut buf = [0; 16];
+        assert_eq!(
+            encode_connect(&mut buf, &connect()),
+            Err(EncodeError::BufferTooSmall)
+        );
+        assert_eq!(
+            encode_publish(&mut buf, "aircon/1a2b/sensor", b"", false),
+            Err(EncodeError::BufferTooSmall)
+        );
+        assert_eq!(
+            encode_pingreq(&mut buf[..1]),
+            Err(EncodeError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_packets() {
+        // Remaining length longer than four bytes
+        assert_eq!(
+            decode_packet(&[0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]),
+            Err(DecodeError::Malformed)
+        );
+        assert_eq!(
+            decode_packet(&[0x20, 3, 0, 0, 0]),
+            Err(DecodeError::Malformed)
+        );
+        // Topic length past the end of the packet
+        assert_eq!(
+            decode_packet(&[0x30, 4, 0, 9, b'a', b'b']),
+            Err(DecodeError::Malformed)
+        );
+        assert_eq!(decode_packet(&[]), Err(DecodeError::Incomplete));
+        assert_eq!(decode_packet(&[0xB0, 2, 0]), Err(DecodeError::Incomplete));
+        assert_eq!(decode_packet(&[0xB0, 2, 0, 1]), Ok((Packet::Other(11), 4)));
+    }
+}
@@ -0,0 +1,668 @@
+//! Weekly setpoint schedule.
+//!
+//! A schedule is a list of blocks, each starting at a time of day on some days of
+//! the week. A block stays in effect until the next one starts, wrapping around
+//! from Sunday night to Monday, so a single block applies all week. Each block
+//! either sets the setpoint or turns the compressor off.
+//!
+//! The [`Scheduler`] tracks which block was applied last. A manual setpoint change
+//! overrides the schedule until the next block starts.
+
+use core::fmt;
+use core::str::FromStr;
+
+use heapless::Vec;
+
+use crate::temp_controller::SETPOINT_RANGE;
+use crate::units::{Temperature, TemperatureInput, TemperatureUnit};
+use crate::wall_clock::{DateTime, Weekday};
+
+/// Blocks a schedule can hold
+pub const MAX_BLOCKS: usize = 16;
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// Error returned when days, a time of day or an action can't be parsed
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ParseScheduleError;
+
+/// A set of weekdays
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Days(u8);
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+impl Days {
+    pub const ALL: Days = Days(0x7F);
+    pub const WEEKDAYS: Days = Days(0x1F);
+    pub const WEEKEND: Days = Days(0x60);
+
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        (bits != 0 && bits & !Days::ALL.0 == 0).then_some(Days(bits))
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(&self, day: Weekday) -> bool {
+        self.0 & (1 << day.index()) != 0
+    }
+
+    fn intersects(&self, other: Days) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+/// Lists runs of days, e.g. `mon-fri,sun`
+impl fmt::Display for Days {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        let mut day = 0;
+        while day < 7 {
+            if !self.contains(Weekday::ALL[day]) {
+                day += 1;
+                continue;
+            }
+            let mut last = day;
+            while last + 1 < 7 && self.contains(Weekday::ALL[last + 1]) {
+                last += 1;
+            }
+            if !first {
+                f.write_str(",")?;
+            }
+            first = false;
+            match last - day {
+                0 => f.write_str(DAY_NAMES[day])?,
+                1 => write!(f, "{},{}", DAY_NAMES[day], DAY_NAMES[last])?,
+                _ => write!(f, "{}-{}", DAY_NAMES[day], DAY_NAMES[last])?,
+            }
+            day = last + 1;
+        }
+        Ok(())
+    }
+}
+
+fn day_index(name: &str) -> Result<usize, ParseScheduleError> {
+    DAY_NAMES
+        .iter()
+        .position(|day| name.eq_ignore_ascii_case(day))
+        .ok_or(ParseScheduleError)
+}
+
+/// Parses `daily`, `weekdays`, `weekend` or a comma separated list of days and ranges
+/// such as `mon-fri,sun`
+impl FromStr for Days {
+    type Err = ParseScheduleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("daily") {
+            return Ok(Days::ALL);
+        }
+        if s.eq_ignore_ascii_case("weekdays") {
+            return Ok(Days::WEEKDAYS);
+        }
+        if s.eq_ignore_ascii_case("weekend") {
+            return Ok(Days::WEEKEND);
+        }
+        let mut bits = 0u8;
+        for part in s.split(',') {
+            let (first, last) = match part.split_once('-') {
+                Some((first, last)) => (day_index(first)?, day_index(last)?),
+                None => (day_index(part)?, day_index(part)?),
+            };
+            if last < first {
+                return Err(ParseScheduleError);
+            }
+            for day in first..=last {
+                bits |= 1 << day;
+            }
+        }
+        Days::from_bits(bits).ok_or(ParseScheduleError)
+    }
+}
+
+/// Minutes since midnight, printed and parsed as `HH:MM`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimeOfDay(u16);
+
+impl TimeOfDay {
+    pub fn from_minutes(minutes: u16) -> Option<Self> {
+        (minutes < MINUTES_PER_DAY).then_some(TimeOfDay(minutes))
+    }
+
+    pub fn minutes(&self) -> u16 {
+        self.0
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = ParseScheduleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hours, minutes) = s.split_once(':').ok_or(ParseScheduleError)?;
+        let number = |digits: &str| {
+            if digits.is_empty() || digits.len() > 2 || !digits.bytes().all(|b| b.is_ascii_digit())
+            {
+                return Err(ParseScheduleError);
+            }
+            digits.parse::<u16>().map_err(|_| ParseScheduleError)
+        };
+        let (hours, minutes) = (number(hours)?, number(minutes)?);
+        if hours >= 24 || minutes >= 60 {
+            return Err(ParseScheduleError);
+        }
+        Ok(TimeOfDay(hours * 60 + minutes))
+    }
+}
+
+/// What a block does while it is in effect
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Action {
+    Setpoint(Temperature),
+    /// Keeps the compressor off
+    Off,
+}
+
+impl Action {
+    /// Writes the action in `unit`, e.g. `22.0°C` or `off`
+    pub fn display(&self, unit: TemperatureUnit) -> impl fmt::Display + '_ {
+        struct Shown(Action, TemperatureUnit);
+        impl fmt::Display for Shown {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self.0 {
+                    Action::Setpoint(setpoint) => {
+                        write!(f, "{}{}", setpoint.in_unit(self.1), self.1)
+                    }
+                    Action::Off => f.write_str("off"),
+                }
+            }
+        }
+        Shown(*self, unit)
+    }
+}
+
+/// An action as typed by the user, a setpoint in the display unit unless it has a suffix
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ActionInput {
+    Setpoint(TemperatureInput),
+    Off,
+}
+
+impl ActionInput {
+    pub fn action(&self, default_unit: TemperatureUnit) -> Action {
+        match self {
+            ActionInput::Setpoint(input) => Action::Setpoint(input.temperature(default_unit)),
+            ActionInput::Off => Action::Off,
+        }
+    }
+}
+
+/// Parses `off` or a temperature
+impl FromStr for ActionInput {
+    type Err = ParseScheduleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("off") {
+            return Ok(ActionInput::Off);
+        }
+        s.parse()
+            .map(ActionInput::Setpoint)
+            .map_err(|_| ParseScheduleError)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Block {
+    pub days: Days,
+    pub start: TimeOfDay,
+    pub action: Action,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScheduleError {
+    /// Every slot is taken, remove a block first
+    Full,
+    /// Another block already starts at the same time on one of the days
+    Conflict,
+    /// The setpoint is outside `SETPOINT_RANGE`
+    Setpoint,
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleError::Full => write!(f, "at most {} blocks can be scheduled", MAX_BLOCKS),
+            ScheduleError::Conflict => {
+                f.write_str("another block already starts then, remove it first")
+            }
+            ScheduleError::Setpoint => write!(
+                f,
+                "setpoint must be {} to {}°C",
+                SETPOINT_RANGE.start(),
+                SETPOINT_RANGE.end()
+            ),
+        }
+    }
+}
+
+/// Minutes since Monday 00:00
+fn minute_of_week(day: usize, time: TimeOfDay) -> u16 {
+    day as u16 * MINUTES_PER_DAY + time.0
+}
+
+/// The block in effect at some time, and when in the week it started
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Active {
+    pub block: Block,
+    /// Minutes since Monday 00:00 of the start that is in effect
+    pub started: u16,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Schedule {
+    blocks: Vec<Block, MAX_BLOCKS>,
+}
+
+impl Schedule {
+    pub const fn new() -> Self {
+        Schedule { blocks: Vec::new() }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Adds a block, keeping them ordered by start time
+    pub fn add(&mut self, block: Block) -> Result<(), ScheduleError> {
+        if let Action::Setpoint(setpoint) = block.action {
+            if !SETPOINT_RANGE.contains(&setpoint) {
+                return Err(ScheduleError::Setpoint);
+            }
+        }
+        if self
+            .blocks
+            .iter()
+            .any(|other| other.start == block.start && other.days.intersects(block.days))
+        {
+            return Err(ScheduleError::Conflict);
+        }
+        let index = self
+            .blocks
+            .iter()
+            .position(|other| other.start > block.start)
+            .unwrap_or(self.blocks.len());
+        self.blocks
+            .insert(index, block)
+            .map_err(|_| ScheduleError::Full)
+    }
+
+    /// Removes the block at `index` as listed by `iter`, returns it if there was one
+    pub fn remove(&mut self, index: usize) -> Option<Block> {
+        (index < self.blocks.len()).then(|| self.blocks.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+    }
+
+    /// Every start in the week, as minutes since Monday 00:00
+    fn starts(&self) -> impl Iterator<Item = (u16, &Block)> {
+        self.blocks.iter().flat_map(|block| {
+            (0..7)
+                .filter(|day| block.days.contains(Weekday::ALL[*day]))
+                .map(move |day| (minute_of_week(day, block.start), block))
+        })
+    }
+
+    /// The block in effect at local time `now`, `None` for an empty schedule
+    pub fn active_at(&self, now: &DateTime) -> Option<Active> {
+        let now = minute_of_week(now.weekday.index(), TimeOfDay(now.minute_of_day()));
+        // The latest start so far this week, or else the last one of the week before
+        let latest = self
+            .starts()
+            .filter(|(started, _)| *started <= now)
+            .max_by_key(|(started, _)| *started)
+            .or_else(|| self.starts().max_by_key(|(started, _)| *started))?;
+        Some(Active {
+            block: *latest.1,
+            started: latest.0,
+        })
+    }
+}
+
+/// What the controller should change, returned when the schedule moves on
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Change {
+    Apply(Action),
+    /// Nothing is scheduled anymore, the controller goes back to its own setpoint
+    Release,
+}
+
+/// Applies a schedule as time passes
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Scheduler {
+    /// Start of the block applied last, `None` while nothing was applied
+    applied: Option<u16>,
+    overridden: bool,
+}
+
+impl Scheduler {
+    pub const fn new() -> Self {
+        Scheduler {
+            applied: None,
+            overridden: false,
+        }
+    }
+
+    /// Returns what to change when a new block started since the last call, at local
+    /// time `now`. Without a time nothing is scheduled.
+    pub fn poll(&mut self, schedule: &Schedule, now: Option<&DateTime>) -> Option<Change> {
+        let active = now.and_then(|now| schedule.active_at(now));
+        let started = active.map(|active| active.started);
+        if started == self.applied {
+            return None;
+        }
+        let released = self.applied.is_some();
+        self.applied = started;
+        self.overridden = false;
+        match active {
+            Some(active) => Some(Change::Apply(active.block.action)),
+            None if released => Some(Change::Release),
+            None => None,
+        }
+    }
+
+    /// Keeps a manual change until the next block starts
+    pub fn override_block(&mut self) {
+        if self.applied.is_some() {
+            self.overridden = true;
+        }
+    }
+
+    /// Whether a manual change currently overrides the schedule
+    pub fn is_overridden(&self) -> bool {
+        self.overridden
+    }
+
+    /// Applies the block in effect again on the next poll, e.g. after the schedule was edited
+    pub fn reset(&mut self) {
+        if self.applied.is_some() {
+            self.applied = Some(u16::MAX);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(weekday: Weekday, time: &str) -> DateTime {
+        let time: TimeOfDay = time.parse().unwrap();
+        DateTime {
+            year: 2024,
+            month: 1,
+            day: 1 + weekday.index() as u8,
+            hour: (time.minutes() / 60) as u8,
+            minute: (time.minutes() % 60) as u8,
+            second: 30,
+            weekday,
+        }
+    }
+
+    fn block(days: &str, start: &str, action: &str) -> Block {
+        Block {
+            days: days.parse().unwrap(),
+            start: start.parse().unwrap(),
+            action: action
+                .parse::<ActionInput>()
+                .unwrap()
+                .action(TemperatureUnit::Celsius),
+        }
+    }
+
+    fn schedule(blocks: &[Block]) -> Schedule {
+        let mut schedule = Schedule::new();
+        for block in blocks {
+            schedule.add(*block).unwrap();
+        }
+        schedule
+    }
+
+    /// The block in effect and the day and time it started
+    fn active(schedule: &Schedule, now: DateTime) -> (Action, Weekday, TimeOfDay) {
+        let active = schedule.active_at(&now).unwrap();
+        (
+            active.block.action,
+            Weekday::ALL[(active.started / MINUTES_PER_DAY) as usize],
+            TimeOfDay(active.started % MINUTES_PER_DAY),
+        )
+    }
+
+    fn setpoint(celsius: i16) -> Action {
+        Action::Setpoint(Temperature::from_celsius(celsius))
+    }
+
+    #[test]
+    fn block_runs_past_midnight() {
+        let night = block("daily", "23:00", "26");
+        let off = block("daily", "02:00", "off");
+        let schedule = schedule(&[night, off]);
+        let time = |time: &str| time.parse::<TimeOfDay>().unwrap();
+
+        assert_eq!(
+            active(&schedule, at(Weekday::Tuesday, "22:59")),
+            (Action::Off, Weekday::Tuesday, time("02:00"))
+        );
+        assert_eq!(
+            active(&schedule, at(Weekday::Tuesday, "23:00")),
+            (setpoint(26), Weekday::Tuesday, time("23:00"))
+        );
+        // Still Tuesday's block after midnight
+        assert_eq!(
+            active(&schedule, at(Weekday::Wednesday, "00:00")),
+            (setpoint(26), Weekday::Tuesday, time("23:00"))
+        );
+        assert_eq!(
+            active(&schedule, at(Weekday::Wednesday, "01:59")),
+            (setpoint(26), Weekday::Tuesday, time("23:00"))
+        );
+        assert_eq!(
+            active(&schedule, at(Weekday::Wednesday, "02:00")),
+            (Action::Off, Weekday::Wednesday, time("02:00"))
+        );
+    }
+
+    #[test]
+    fn sunday_night_runs_into_monday() {
+        let schedule = schedule(&[block("sun", "23:00", "26"), block("mon", "07:00", "off")]);
+        let time = |time: &str| time.parse::<TimeOfDay>().unwrap();
+
+        assert_eq!(
+            active(&schedule, at(Weekday::Sunday, "23:59")),
+            (setpoint(26), Weekday::Sunday, time("23:00"))
+        );
+        // Before Monday's first start the week before is still in effect
+        assert_eq!(
+            active(&schedule, at(Weekday::Monday, "00:00")),
+            (setpoint(26), Weekday::Sunday, time("23:00"))
+        );
+        assert_eq!(
+            active(&schedule, at(Weekday::Monday, "06:59")),
+            (setpoint(26), Weekday::Sunday, time("23:00"))
+        );
+        assert_eq!(
+            active(&schedule, at(Weekday::Monday, "07:00")),
+            (Action::Off, Weekday::Monday, time("07:00"))
+        );
+        // Monday's block holds all week, until Sunday night
+        assert_eq!(
+            active(&schedule, at(Weekday::Sunday, "22:59")),
+            (Action::Off, Weekday::Monday, time("07:00"))
+        );
+    }
+
+    #[test]
+    fn single_block_applies_all_week() {
+        let schedule = schedule(&[block("fri", "18:00", "24")]);
+        for weekday in Weekday::ALL {
+            for time in ["00:00", "12:00", "23:59"] {
+                assert_eq!(active(&schedule, at(weekday, time)).1, Weekday::Friday);
+            }
+        }
+        assert_eq!(
+            Schedule::new().active_at(&at(Weekday::Monday, "12:00")),
+            None
+        );
+    }
+
+    #[test]
+    fn overlapping_blocks_give_way_to_the_latest_start() {
+        let schedule = schedule(&[
+            block("mon-fri", "07:00", "22"),
+            block("weekend", "07:00", "24"),
+            block("daily", "09:00", "off"),
+            block("wed", "08:00", "20"),
+        ]);
+        let time = |time: &str| time.parse::<TimeOfDay>().unwrap();
+
+        assert_eq!(
+            active(&schedule, at(Weekday::Tuesday, "08:30")),
+            (setpoint(22), Weekday::Tuesday, time("07:00"))
+        );
+        assert_eq!(
+            active(&schedule, at(Weekday::Saturday, "08:30")),
+            (setpoint(24), Weekday::Saturday, time("07:00"))
+        );
+        assert_eq!(
+            active(&schedule, at(Weekday::Wednesday, "08:30")),
+            (setpoint(20), Weekday::Wednesday, time("08:00"))
+        );
+        assert_eq!(
+            active(&schedule, at(Weekday::Wednesday, "09:00")),
+            (Action::Off, Weekday::Wednesday, time("09:00"))
+        );
+        // Listed by start time whatever order they were added in
+        let starts: std::vec::Vec<_> = schedule.iter().map(|block| block.start.0).collect();
+        assert_eq!(starts, [420, 420, 480, 540]);
+    }
+
+    #[test]
+    fn rejects_blocks_that_cannot_be_added() {
+        let mut schedule = schedule(&[block("mon-fri", "07:00", "22")]);
+        assert_eq!(
+            schedule.add(block("fri,sat", "07:00", "off")),
+            Err(ScheduleError::Conflict)
+        );
+        assert_eq!(
+            schedule.add(block("sat", "07:00", "51")),
+            Err(ScheduleError::Setpoint)
+        );
+        for minute in 0..MAX_BLOCKS as u16 - 1 {
+            let start = TimeOfDay::from_minutes(600 + minute).unwrap();
+            schedule
+                .add(Block {
+                    start,
+                    ..block("sun", "00:00", "off")
+                })
+                .unwrap();
+        }
+        assert_eq!(
+            schedule.add(block("sun", "23:00", "off")),
+            Err(ScheduleError::Full)
+        );
+        assert_eq!(schedule.remove(MAX_BLOCKS), None);
+        assert_eq!(schedule.remove(0), Some(block("mon-fri", "07:00", "22")));
+    }
+
+    #[test]
+    fn scheduler_applies_each_block_once() {
+        let schedule = schedule(&[
+            block("daily", "07:00", "22"),
+            block("daily", "23:00", "off"),
+        ]);
+        let mut scheduler = Scheduler::new();
+        assert_eq!(scheduler.poll(&schedule, None), None);
+
+        let morning = at(Weekday::Monday, "07:00");
+        assert_eq!(
+            scheduler.poll(&schedule, Some(&morning)),
+            Some(Change::Apply(setpoint(22)))
+        );
+        assert_eq!(
+            scheduler.poll(&schedule, Some(&at(Weekday::Monday, "22:59"))),
+            None
+        );
+
+        // A manual change holds until the next start, across midnight too
+        scheduler.override_block();
+        assert!(scheduler.is_overridden());
+        assert_eq!(
+            scheduler.poll(&schedule, Some(&at(Weekday::Monday, "23:00"))),
+            Some(Change::Apply(Action::Off))
+        );
+        assert!(!scheduler.is_overridden());
+        assert_eq!(
+            scheduler.poll(&schedule, Some(&at(Weekday::Tuesday, "06:59"))),
+            None
+        );
+
+        // Editing the schedule applies the block in effect again
+        scheduler.reset();
+        assert_eq!(
+            scheduler.poll(&schedule, Some(&at(Weekday::Tuesday, "06:59"))),
+            Some(Change::Apply(Action::Off))
+        );
+
+        assert_eq!(
+            scheduler.poll(&Schedule::new(), Some(&morning)),
+            Some(Change::Release)
+        );
+        assert_eq!(scheduler.poll(&Schedule::new(), Some(&morning)), None);
+        // Nothing was applied, so there is nothing to override
+        scheduler.override_block();
+        assert!(!scheduler.is_overridden());
+    }
+
+    #[test]
+    fn days_and_times_round_trip() {
+        for (text, shown) in [
+            ("daily", "mon-sun"),
+            ("weekdays", "mon-fri"),
+            ("Sat,SUN", "sat,sun"),
+            ("mon-wed,fri", "mon-wed,fri"),
+        ] {
+            let days: Days = text.parse().unwrap();
+            assert_eq!(days.to_string(), shown);
+            assert_eq!(shown.parse(), Ok(days));
+        }
+        for bad in ["", "fri-mon", "mon,", "funday"] {
+            assert_eq!(bad.parse::<Days>(), Err(ParseScheduleError));
+        }
+
+        assert_eq!("7:05".parse::<TimeOfDay>().unwrap().to_string(), "07:05");
+        for bad in ["24:00", "12:60", "12", "+1:00", "123:00"] {
+            assert_eq!(bad.parse::<TimeOfDay>(), Err(ParseScheduleError));
+        }
+    }
+}
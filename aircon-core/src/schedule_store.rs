@@ -0,0 +1,130 @@
+//! Weekly schedule in two sectors of NOR flash.
+//!
+//! Like the Wi-Fi networks a schedule doesn't fit in a settings slot, so it lives in
+//! a [`SectorStore`] of its own with the magic `ASCH`.
+//!
+//! The payload is a block count followed by six bytes per block: the days as a
+//! bitmask with Monday in bit 0, the start in minutes since midnight as a `u16`,
+//! the action (0 off, 1 setpoint) and the setpoint in tenths of °C as an `i16`.
+
+use crate::config_store::RecordError;
+use crate::schedule::{Action, Block, Days, Schedule, TimeOfDay, MAX_BLOCKS};
+use crate::sector_store::{RecordCodec, SectorStore, RECORD_OVERHEAD};
+use crate::units::Temperature;
+
+/// Largest possible record, header, every block and CRC
+pub const MAX_RECORD_SIZE: usize = RECORD_OVERHEAD + 1 + MAX_BLOCKS * BLOCK_LENGTH;
+
+const BLOCK_LENGTH: usize = 6;
+/// Records are read and written through a buffer rounded up from `MAX_RECORD_SIZE`
+const BUFFER_SIZE: usize = 128;
+
+const ACTION_OFF: u8 = 0;
+const ACTION_SETPOINT: u8 = 1;
+
+/// The schedule in the two erase sectors starting at `start`
+pub type ScheduleStore<F> = SectorStore<F, ScheduleRecord, BUFFER_SIZE>;
+
+pub struct ScheduleRecord;
+
+impl RecordCodec for ScheduleRecord {
+    type Value = Schedule;
+
+    const MAGIC: [u8; 4] = *b"ASCH";
+    const VERSION: u16 = 1;
+
+    fn encode(schedule: &Schedule, out: &mut [u8]) -> usize {
+        let mut length = 0;
+        let mut push = |bytes: &[u8]| {
+            out[length..length + bytes.len()].copy_from_slice(bytes);
+            length += bytes.len();
+        };
+        push(&[schedule.iter().count() as u8]);
+        for block in schedule.iter() {
+            let (action, tenths) = match block.action {
+                Action::Off => (ACTION_OFF, 0),
+                Action::Setpoint(setpoint) => (ACTION_SETPOINT, setpoint.to_tenths()),
+            };
+            push(&[block.days.bits()]);
+            push(&block.start.minutes().to_le_bytes());
+            push(&[action]);
+            push(&tenths.to_le_bytes());
+        }
+        length
+    }
+
+    fn decode(payload: &[u8]) -> Result<Schedule, RecordError> {
+        let (count, blocks) = payload.split_first().ok_or(RecordError::InvalidField)?;
+        if blocks.len() != *count as usize * BLOCK_LENGTH {
+            return Err(RecordError::InvalidField);
+        }
+        let mut schedule = Schedule::new();
+        for block in blocks.chunks_exact(BLOCK_LENGTH) {
+            let days = Days::from_bits(block[0]).ok_or(RecordError::InvalidField)?;
+            let start = TimeOfDay::from_minutes(u16::from_le_bytes([block[1], block[2]]))
+                .ok_or(RecordError::InvalidField)?;
+            let action = match block[3] {
+                ACTION_OFF => Action::Off,
+                ACTION_SETPOINT => {
+                    Action::Setpoint(Temperature::from_tenths(i16::from_le_bytes([
+                        block[4], block[5],
+                    ])))
+                }
+                _ => return Err(RecordError::InvalidField),
+            };
+            schedule
+                .add(Block {
+                    days,
+                    start,
+                    action,
+                })
+                .map_err(|_| RecordError::InvalidField)?;
+        }
+        Ok(schedule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mock_flash::MockFlash;
+
+    #[test]
+    fn schedule_round_trips() {
+        let mut schedule = Schedule::new();
+        for (days, start, action) in [
+            (
+                "weekdays",
+                "06:30",
+                Action::Setpoint(Temperature::from_tenths(215)),
+            ),
+            ("weekdays", "23:00", Action::Off),
+            (
+                "weekend",
+                "09:15",
+                Action::Setpoint(Temperature::from_celsius(24)),
+            ),
+        ] {
+            schedule
+                .add(Block {
+                    days: days.parse().unwrap(),
+                    start: start.parse().unwrap(),
+                    action,
+                })
+                .unwrap();
+        }
+
+        let mut store = ScheduleStore::new(MockFlash::new(2), 0);
+        store.save(&schedule).unwrap();
+        assert_eq!(store.load(), Ok(Some(schedule)));
+    }
+
+    #[test]
+    fn rejects_an_unknown_action() {
+        assert_eq!(
+            ScheduleRecord::decode(&[1, 0x01, 0x00, 0x00, 9, 0, 0]),
+            Err(RecordError::InvalidField)
+        );
+    }
+}
@@ -0,0 +1,280 @@
+//! A value in two sectors of NOR flash, for records too large for a settings slot.
+//!
+//! Every save writes one whole record into the sector not holding the current one, so
+//! a torn write leaves the previous record in the other sector intact. Loading picks
+//! the valid record with the newer sequence number.
+//!
+//! Record layout at the start of a sector, little-endian:
+//!
+//! | offset | size | field                                    |
+//! |--------|------|------------------------------------------|
+//! | 0      | 4    | magic, one per kind of record            |
+//! | 4      | 2    | record version                           |
+//! | 6      | 2    | payload length `n`                       |
+//! | 8      | 4    | sequence number                          |
+//! | 12     | n    | payload, see the [`RecordCodec`]         |
+//! | 12 + n | 4    | CRC-32 of bytes 0..12 + n                |
+
+use core::marker::PhantomData;
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::config_store::{crc32, is_newer, RecordError};
+
+/// Header and CRC around the payload
+pub const RECORD_OVERHEAD: usize = HEADER_LENGTH + 4;
+
+const HEADER_LENGTH: usize = 12;
+
+/// How one kind of value is stored in a record
+pub trait RecordCodec {
+    type Value;
+
+    const MAGIC: [u8; 4];
+    /// Version written by this firmware, records with another version are ignored
+    const VERSION: u16;
+
+    /// Writes the payload for `value` into `out`, returns its length
+    fn encode(value: &Self::Value, out: &mut [u8]) -> usize;
+
+    fn decode(payload: &[u8]) -> Result<Self::Value, RecordError>;
+}
+
+/// Encodes `value` into `buf`, returns the record length
+pub fn encode_record<C: RecordCodec>(value: &C::Value, sequence: u32, buf: &mut [u8]) -> usize {
+    let payload_length = C::encode(value, &mut buf[HEADER_LENGTH..]);
+    let length = HEADER_LENGTH + payload_length;
+    buf[0..4].copy_from_slice(&C::MAGIC);
+    buf[4..6].copy_from_slice(&C::VERSION.to_le_bytes());
+    buf[6..8].copy_from_slice(&(payload_length as u16).to_le_bytes());
+    buf[8..12].copy_from_slice(&sequence.to_le_bytes());
+    let crc = crc32(&buf[..length]);
+    buf[length..length + 4].copy_from_slice(&crc.to_le_bytes());
+    length + 4
+}
+
+/// Decodes a record into its sequence number and value
+pub fn decode_record<C: RecordCodec>(buf: &[u8]) -> Result<(u32, C::Value), RecordError> {
+    if buf.len() < RECORD_OVERHEAD {
+        return Err(RecordError::InvalidField);
+    }
+    let u16_at = |offset: usize| u16::from_le_bytes([buf[offset], buf[offset + 1]]);
+    let u32_at = |offset: usize| {
+        u32::from_le_bytes([
+            buf[offset],
+            buf[offset + 1],
+            buf[offset + 2],
+            buf[offset + 3],
+        ])
+    };
+
+    if buf[..HEADER_LENGTH].iter().all(|byte| *byte == 0xFF) {
+        return Err(RecordError::Blank);
+    }
+    if buf[0..4] != C::MAGIC {
+        return Err(RecordError::BadMagic);
+    }
+    let version = u16_at(4);
+    if version != C::VERSION {
+        return Err(RecordError::UnsupportedVersion(version));
+    }
+    let crc_offset = HEADER_LENGTH + u16_at(6) as usize;
+    if crc_offset + 4 > buf.len() {
+        return Err(RecordError::InvalidField);
+    }
+    if u32_at(crc_offset) != crc32(&buf[..crc_offset]) {
+        return Err(RecordError::BadCrc);
+    }
+
+    let value = C::decode(&buf[HEADER_LENGTH..crc_offset])?;
+    Ok((u32_at(8), value))
+}
+
+/// A value in the two erase sectors starting at `start`, read and written through a
+/// buffer of `N` bytes that fits the largest record
+pub struct SectorStore<F: NorFlash, C: RecordCodec, const N: usize> {
+    flash: F,
+    start: u32,
+    /// Sector the next save goes to, 0 or 1
+    next_sector: u32,
+    next_sequence: u32,
+    codec: PhantomData<C>,
+}
+
+impl<F: NorFlash, C: RecordCodec, const N: usize> SectorStore<F, C, N> {
+    pub fn new(flash: F, start: u32) -> Self {
+        assert!((start as usize).is_multiple_of(F::ERASE_SIZE));
+        assert!(N <= F::ERASE_SIZE && N.is_multiple_of(F::WRITE_SIZE));
+        SectorStore {
+            flash,
+            start,
+            next_sector: 0,
+            next_sequence: 0,
+            codec: PhantomData,
+        }
+    }
+
+    fn sector_offset(&self, sector: u32) -> u32 {
+        self.start + sector * F::ERASE_SIZE as u32
+    }
+
+    /// Finds the newest valid record, `None` if neither sector holds one
+    pub fn load(&mut self) -> Result<Option<C::Value>, F::Error> {
+        let mut newest: Option<(u32, u32, C::Value)> = None;
+        for sector in 0..2 {
+            let mut buf = [0u8; N];
+            self.flash.read(self.sector_offset(sector), &mut buf)?;
+            if let Ok((sequence, value)) = decode_record::<C>(&buf) {
+                if newest
+                    .as_ref()
+                    .is_none_or(|(newest_sequence, _, _)| is_newer(sequence, *newest_sequence))
+                {
+                    newest = Some((sequence, sector, value));
+                }
+            }
+        }
+
+        Ok(newest.map(|(sequence, sector, value)| {
+            self.next_sequence = sequence.wrapping_add(1);
+            self.next_sector = 1 - sector;
+            value
+        }))
+    }
+
+    /// Writes `value` into the sector not holding the current record
+    pub fn save(&mut self, value: &C::Value) -> Result<(), F::Error> {
+        let offset = self.sector_offset(self.next_sector);
+        let mut buf = [0xFFu8; N];
+        encode_record::<C>(value, self.next_sequence, &mut buf);
+
+        self.flash.erase(offset, offset + F::ERASE_SIZE as u32)?;
+        self.flash.write(offset, &buf)?;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        self.next_sector = 1 - self.next_sector;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mock_flash::{MockFlash, SECTOR_SIZE};
+
+    /// A `u32` and nothing else
+    struct Counter;
+
+    impl RecordCodec for Counter {
+        type Value = u32;
+
+        const MAGIC: [u8; 4] = *b"TEST";
+        const VERSION: u16 = 3;
+
+        fn encode(value: &u32, out: &mut [u8]) -> usize {
+            out[..4].copy_from_slice(&value.to_le_bytes());
+            4
+        }
+
+        fn decode(payload: &[u8]) -> Result<u32, RecordError> {
+            let bytes = payload.try_into().map_err(|_| RecordError::InvalidField)?;
+            Ok(u32::from_le_bytes(bytes))
+        }
+    }
+
+    type CounterStore = SectorStore<MockFlash, Counter, 32>;
+
+    fn store(flash: MockFlash) -> CounterStore {
+        SectorStore::new(flash, SECTOR_SIZE as u32)
+    }
+
+    fn put(flash: &mut MockFlash, sector: usize, value: u32, sequence: u32) {
+        let offset = (sector + 1) * SECTOR_SIZE;
+        encode_record::<Counter>(&value, sequence, &mut flash.data[offset..offset + 32]);
+    }
+
+    #[test]
+    fn record_round_trips() {
+        let mut buf = [0xFF; 32];
+        assert_eq!(
+            encode_record::<Counter>(&1234, 9, &mut buf),
+            RECORD_OVERHEAD + 4
+        );
+        assert_eq!(decode_record::<Counter>(&buf), Ok((9, 1234)));
+
+        buf[12] ^= 0x01;
+        assert_eq!(decode_record::<Counter>(&buf), Err(RecordError::BadCrc));
+        assert_eq!(
+            decode_record::<Counter>(&[0xFF; 32]),
+            Err(RecordError::Blank)
+        );
+    }
+
+    #[test]
+    fn record_rejects_a_length_past_the_buffer() {
+        let mut buf = [0xFF; 32];
+        encode_record::<Counter>(&1, 0, &mut buf);
+        buf[6..8].copy_from_slice(&100u16.to_le_bytes());
+        assert_eq!(
+            decode_record::<Counter>(&buf),
+            Err(RecordError::InvalidField)
+        );
+    }
+
+    #[test]
+    fn alternates_between_the_sectors() {
+        let mut store = store(MockFlash::new(3));
+        assert_eq!(store.load(), Ok(None));
+        store.save(&1).unwrap();
+        store.save(&2).unwrap();
+        store.save(&3).unwrap();
+        assert_eq!(store.flash.erases, 3);
+
+        let mut reopened = self::store(store.flash);
+        assert_eq!(reopened.load(), Ok(Some(3)));
+        // The record before the newest one is still in the other sector
+        let old = &reopened.flash.data[2 * SECTOR_SIZE..2 * SECTOR_SIZE + 32];
+        assert_eq!(decode_record::<Counter>(old), Ok((1, 2)));
+        // Nothing outside the two sectors is touched
+        assert!(reopened.flash.data[..SECTOR_SIZE]
+            .iter()
+            .all(|byte| *byte == 0xFF));
+    }
+
+    #[test]
+    fn falls_back_to_the_other_sector() {
+        let mut store = store(MockFlash::new(3));
+        store.save(&1).unwrap();
+        store.save(&2).unwrap();
+        // A torn write of the newest record, only its header made it
+        store.flash.data[2 * SECTOR_SIZE + 12..2 * SECTOR_SIZE + 32].fill(0xFF);
+
+        let mut reopened = self::store(store.flash);
+        assert_eq!(reopened.load(), Ok(Some(1)));
+        // The next save replaces the torn record, not the good one
+        reopened.save(&3).unwrap();
+        let good = &reopened.flash.data[SECTOR_SIZE..SECTOR_SIZE + 32];
+        assert_eq!(decode_record::<Counter>(good), Ok((0, 1)));
+    }
+
+    #[test]
+    fn picks_the_newest_sequence_across_wrap_around() {
+        let mut flash = MockFlash::new(3);
+        put(&mut flash, 0, 10, 0);
+        put(&mut flash, 1, 20, u32::MAX);
+
+        let mut store = store(flash);
+        assert_eq!(store.load(), Ok(Some(10)));
+        store.save(&30).unwrap();
+
+        let mut reopened = self::store(store.flash);
+        assert_eq!(reopened.load(), Ok(Some(30)));
+    }
+
+    #[test]
+    fn ignores_records_of_another_kind() {
+        let mut flash = MockFlash::new(3);
+        put(&mut flash, 0, 10, 0);
+        flash.data[SECTOR_SIZE..SECTOR_SIZE + 4].copy_from_slice(b"AWIF");
+        assert_eq!(store(flash).load(), Ok(None));
+    }
+}
@@ -0,0 +1,347 @@
+//! Setup page served while the unit runs its own access point because it couldn't
+//! join any network.
+//!
+//! | method | path     | body                                                |
+//! |--------|----------|-----------------------------------------------------|
+//! | GET    | any      | the setup form, so captive portal checks land on it |
+//! | POST   | `/setup` | urlencoded `ssid`, `passphrase` and `setpoint`      |
+//!
+//! An empty `passphrase` means an open network, an empty `setpoint` keeps the
+//! current one. The setpoint is in the display unit unless it has a suffix.
+
+use core::fmt::{self, Write};
+
+use heapless::{String, Vec};
+
+use crate::http::{Method, Request};
+use crate::temp_controller::SETPOINT_RANGE;
+use crate::units::{ParseUnitError, Temperature, TemperatureInput, TemperatureUnit};
+use crate::wifi::{CredentialsError, WifiCredentials, MAX_SSID_LENGTH};
+
+pub const CONTENT_TYPE_HTML: &str = "text/html; charset=utf-8";
+pub const SETUP_PATH: &str = "/setup";
+
+/// Longest decoded form value, enough for a passphrase with room to spot overlong ones
+const MAX_VALUE_LENGTH: usize = 64;
+
+const PAGE_START: &str = "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+<meta name=\"viewport\" content=\"width=device-width,initial-scale=1\">\
+<title>Aircon setup</title><style>\
+body{font-family:sans-serif;max-width:24em;margin:2em auto;padding:0 1em}\
+label,input,button{display:block;width:100%;box-sizing:border-box;margin-top:.5em}\
+label{margin-top:1em}button{padding:.6em;margin-top:1.5em}.error{color:#b00}\
+</style></head><body><h1>Aircon setup</h1>";
+const PAGE_END: &str = "</body></html>";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SetupRoute {
+    Page,
+    Submit,
+    MethodNotAllowed,
+}
+
+pub fn route(request: &Request<'_>) -> SetupRoute {
+    match (request.method, request.path) {
+        (Method::Post, SETUP_PATH) => SetupRoute::Submit,
+        (Method::Get | Method::Head, _) => SetupRoute::Page,
+        _ => SetupRoute::MethodNotAllowed,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FormError {
+    /// Not valid UTF-8 or a broken `%` escape
+    Encoding,
+    FieldTooLong,
+    Credentials(CredentialsError),
+    Setpoint,
+}
+
+impl From<CredentialsError> for FormError {
+    fn from(err: CredentialsError) -> Self {
+        FormError::Credentials(err)
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormError::Encoding => f.write_str("the form could not be read"),
+            FormError::FieldTooLong => f.write_str("a field is too long"),
+            FormError::Credentials(err) => err.fmt(f),
+            FormError::Setpoint => write!(
+                f,
+                "setpoint must be a temperature such as 22.5, from {} to {}°C",
+                SETPOINT_RANGE.start(),
+                SETPOINT_RANGE.end()
+            ),
+        }
+    }
+}
+
+/// What the setup form was filled in with
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SetupForm {
+    pub credentials: WifiCredentials,
+    pub setpoint: Option<Temperature>,
+}
+
+fn hex_digit(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+/// Undoes `application/x-www-form-urlencoded` escaping of one name or value
+fn decode(encoded: &str) -> Result<String<MAX_VALUE_LENGTH>, FormError> {
+    let mut decoded = Vec::<u8, MAX_VALUE_LENGTH>::new();
+    let mut bytes = encoded.bytes();
+    while let Some(byte) = bytes.next() {
+        let byte = match byte {
+            b'+' => b' ',
+            b'%' => {
+                let high = bytes.next().and_then(hex_digit);
+                let low = bytes.next().and_then(hex_digit);
+                match (high, low) {
+                    (Some(high), Some(low)) => high << 4 | low,
+                    _ => return Err(FormError::Encoding),
+                }
+            }
+            byte => byte,
+        };
+        decoded.push(byte).map_err(|_| FormError::FieldTooLong)?;
+    }
+    String::from_utf8(decoded).map_err(|_| FormError::Encoding)
+}
+
+/// Parses a POST `/setup` body, `unit` is the display unit for a setpoint without suffix
+pub fn parse_setup_form(body: &[u8], unit: TemperatureUnit) -> Result<SetupForm, FormError> {
+    let body = core::str::from_utf8(body).map_err(|_| FormError::Encoding)?;
+
+    let mut ssid = String::<MAX_VALUE_LENGTH>::new();
+    let mut passphrase = String::<MAX_VALUE_LENGTH>::new();
+    let mut setpoint = None;
+    for pair in body.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = decode(value)?;
+        // Anything else, such as a named submit button, is ignored
+        match decode(name)?.as_str() {
+            "ssid" => ssid = value,
+            "passphrase" => passphrase = value,
+            "setpoint" if !value.trim().is_empty() => {
+                let input: TemperatureInput = value
+                    .trim()
+                    .parse()
+                    .map_err(|_: ParseUnitError| FormError::Setpoint)?;
+                let temperature = input.temperature(unit);
+                if !SETPOINT_RANGE.contains(&temperature) {
+                    return Err(FormError::Setpoint);
+                }
+                setpoint = Some(temperature);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(SetupForm {
+        credentials: WifiCredentials::new(ssid.trim(), Some(&passphrase))?,
+        setpoint,
+    })
+}
+
+/// Writes `text` with the characters that mean something in HTML escaped
+fn write_escaped(out: &mut impl Write, text: &str) -> fmt::Result {
+    for c in text.chars() {
+        match c {
+            '&' => out.write_str("&amp;")?,
+            '<' => out.write_str("&lt;")?,
+            '>' => out.write_str("&gt;")?,
+            '"' => out.write_str("&quot;")?,
+            '\'' => out.write_str("&#39;")?,
+            c => out.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+/// The setup form, offering the scanned `networks` and prefilled with the current setpoint
+pub fn render_setup_page(
+    out: &mut impl Write,
+    networks: &[String<MAX_SSID_LENGTH>],
+    setpoint: Temperature,
+    unit: TemperatureUnit,
+    error: Option<&FormError>,
+) -> fmt::Result {
+    out.write_str(PAGE_START)?;
+    if let Some(error) = error {
+        write!(out, "<p class=\"error\">Not saved, {}.</p>", error)?;
+    }
+    write!(
+        out,
+        "<form method=\"post\" action=\"{}\">\
+         <label for=\"ssid\">Network</label>\
+         <input id=\"ssid\" name=\"ssid\" list=\"networks\" maxlength=\"{}\" required>\
+         <datalist id=\"networks\">",
+        SETUP_PATH, MAX_SSID_LENGTH
+    )?;
+    for network in networks {
+        out.write_str("<option value=\"")?;
+        write_escaped(out, network)?;
+        out.write_str("\">")?;
+    }
+    write!(
+        out,
+        "</datalist>\
+         <label for=\"passphrase\">Password</label>\
+         <input id=\"passphrase\" name=\"passphrase\" type=\"password\" \
+         placeholder=\"empty for an open network\">\
+         <label for=\"setpoint\">Setpoint ({})</label>\
+         <input id=\"setpoint\" name=\"setpoint\" inputmode=\"decimal\" value=\"{}\">\
+         <button>Save and connect</button></form>",
+        unit,
+        setpoint.in_unit(unit)
+    )?;
+    if networks.is_empty() {
+        out.write_str("<p>No networks were found nearby, type the name of yours.</p>")?;
+    }
+    out.write_str(PAGE_END)
+}
+
+/// Confirmation once the form was saved
+pub fn render_setup_done(out: &mut impl Write, ssid: &str) -> fmt::Result {
+    out.write_str(PAGE_START)?;
+    out.write_str("<p>Saved. The unit now joins <b>")?;
+    write_escaped(out, ssid)?;
+    out.write_str("</b>, reconnect this device to that network.</p>")?;
+    out.write_str(PAGE_END)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(body: &str) -> Result<SetupForm, FormError> {
+        parse_setup_form(body.as_bytes(), TemperatureUnit::Celsius)
+    }
+
+    #[test]
+    fn parses_a_browser_submission() {
+        let form = parse("ssid=Home+Net&passphrase=p%40ss%26word%3D1&setpoint=23.5").unwrap();
+        assert_eq!(form.credentials.ssid.as_str(), "Home Net");
+        assert_eq!(form.credentials.passphrase.as_deref(), Some("p@ss&word=1"));
+        assert_eq!(form.setpoint, Some(Temperature::from_tenths(235)));
+    }
+
+    #[test]
+    fn decodes_multibyte_characters() {
+        let form = parse("ssid=Caf%C3%A9&passphrase=").unwrap();
+        assert_eq!(form.credentials.ssid.as_str(), "Café");
+    }
+
+    #[test]
+    fn empty_fields_mean_an_open_network_and_the_current_setpoint() {
+        let form = parse("ssid=Guest&passphrase=&setpoint=+&submit=").unwrap();
+        assert_eq!(form.credentials.passphrase, None);
+        assert_eq!(form.setpoint, None);
+    }
+
+    #[test]
+    fn setpoint_without_suffix_is_in_the_display_unit() {
+        let form = parse_setup_form(b"ssid=Home&setpoint=77", TemperatureUnit::Fahrenheit);
+        assert_eq!(form.unwrap().setpoint, Some(Temperature::from_celsius(25)));
+        let form = parse("ssid=Home&setpoint=77F").unwrap();
+        assert_eq!(form.setpoint, Some(Temperature::from_celsius(25)));
+    }
+
+    #[test]
+    fn rejects_a_bad_setpoint() {
+        assert_eq!(parse("ssid=Home&setpoint=warm"), Err(FormError::Setpoint));
+        assert_eq!(parse("ssid=Home&setpoint=22K"), Err(FormError::Setpoint));
+        assert_eq!(parse("ssid=Home&setpoint=51"), Err(FormError::Setpoint));
+        assert_eq!(parse("ssid=Home&setpoint=-1"), Err(FormError::Setpoint));
+    }
+
+    #[test]
+    fn rejects_broken_escapes() {
+        assert_eq!(parse("ssid=100%"), Err(FormError::Encoding));
+        assert_eq!(parse("ssid=%4"), Err(FormError::Encoding));
+        assert_eq!(parse("ssid=%zz"), Err(FormError::Encoding));
+        // Decodes to a lone continuation byte
+        assert_eq!(parse("ssid=%A9"), Err(FormError::Encoding));
+        assert_eq!(
+            parse_setup_form(b"ssid=\xFF", TemperatureUnit::Celsius),
+            Err(FormError::Encoding)
+        );
+    }
+
+    #[test]
+    fn rejects_overlong_credentials() {
+        let ssid = "s".repeat(MAX_SSID_LENGTH);
+        assert!(parse(&format!("ssid={}", ssid)).is_ok());
+        assert_eq!(
+            parse(&format!("ssid={}s", ssid)),
+            Err(FormError::Credentials(CredentialsError::SsidLength))
+        );
+
+        let passphrase = "p".repeat(63);
+        assert!(parse(&format!("ssid=Home&passphrase={}", passphrase)).is_ok());
+        assert_eq!(
+            parse(&format!("ssid=Home&passphrase={}p", passphrase)),
+            Err(FormError::Credentials(CredentialsError::PassphraseLength))
+        );
+        assert_eq!(
+            parse("ssid=Home&passphrase=short"),
+            Err(FormError::Credentials(CredentialsError::PassphraseLength))
+        );
+        // Past what the decoder holds, escapes count once decoded
+        assert_eq!(
+            parse(&format!("ssid=Home&passphrase={}", "%41".repeat(65))),
+            Err(FormError::FieldTooLong)
+        );
+        assert_eq!(
+            parse("passphrase=secret123&ssid="),
+            Err(FormError::Credentials(CredentialsError::SsidLength))
+        );
+    }
+
+    #[test]
+    fn routes_every_get_to_the_form() {
+        let request = |method, path| Request {
+            method,
+            path,
+            if_none_match: None,
+            body: &[],
+        };
+        assert_eq!(
+            route(&request(Method::Get, "/generate_204")),
+            SetupRoute::Page
+        );
+        assert_eq!(
+            route(&request(Method::Post, SETUP_PATH)),
+            SetupRoute::Submit
+        );
+        assert_eq!(
+            route(&request(Method::Post, "/api/config")),
+            SetupRoute::MethodNotAllowed
+        );
+    }
+
+    #[test]
+    fn escapes_network_names() {
+        let mut page = std::string::String::new();
+        let networks = [String::try_from("<b>\"Bob's\" & co</b>").unwrap()];
+        render_setup_page(
+            &mut page,
+            &networks,
+            Temperature::from_celsius(22),
+            TemperatureUnit::Celsius,
+            None,
+        )
+        .unwrap();
+        assert!(
+            page.contains("<option value=\"&lt;b&gt;&quot;Bob&#39;s&quot; &amp; co&lt;/b&gt;\">")
+        );
+        assert!(page.contains("value=\"22.0\""));
+    }
+}
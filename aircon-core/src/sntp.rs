@@ -0,0 +1,315 @@
+//! SNTPv4 client packets, see RFC 4330.
+//!
+//! Only the unicast client side: one request out, one reply back, and the Unix time
+//! the reply stands for. Packets are 48 bytes, big-endian:
+//!
+//! | offset | size | field                                          |
+//! |--------|------|------------------------------------------------|
+//! | 0      | 1    | leap indicator (2 bits), version (3), mode (3) |
+//! | 1      | 1    | stratum, 0 = kiss-o'-death                     |
+//! | 12     | 4    | reference ID, the kiss code at stratum 0       |
+//! | 24     | 8    | originate timestamp, the request's transmit    |
+//! | 32     | 8    | receive timestamp                              |
+//! | 40     | 8    | transmit timestamp                             |
+
+use embassy_time::Duration;
+
+pub const NTP_PORT: u16 = 123;
+pub const PACKET_SIZE: usize = 48;
+
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+const LEAP_ALARM: u8 = 3;
+/// Seconds from the NTP epoch, 1900-01-01, to the Unix epoch
+const UNIX_EPOCH_NTP_SECS: u64 = 2_208_988_800;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SntpError {
+    TooShort,
+    /// Not a server reply, e.g. a broadcast or another client's request
+    NotServerReply,
+    /// Answers a request other than the last one sent
+    OriginMismatch,
+    /// The server has no usable time itself
+    Unsynchronised,
+    /// The server wants us to back off or go away, with its four letter reason
+    KissOfDeath([u8; 4]),
+}
+
+/// Seconds and binary fraction since 1900, wrapping every 136 years
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NtpTimestamp {
+    pub seconds: u32,
+    pub fraction: u32,
+}
+
+impl NtpTimestamp {
+    pub fn from_unix_micros(micros: u64) -> Self {
+        let seconds = micros / 1_000_000 + UNIX_EPOCH_NTP_SECS;
+        let fraction = ((micros % 1_000_000) << 32) / 1_000_000;
+        NtpTimestamp {
+            seconds: seconds as u32,
+            fraction: fraction as u32,
+        }
+    }
+
+    /// Seconds below the Unix epoch offset are taken to be from after the 2036 wrap
+    pub fn to_unix_micros(self) -> u64 {
+        let seconds = match self.seconds as u64 {
+            seconds if seconds >= UNIX_EPOCH_NTP_SECS => seconds - UNIX_EPOCH_NTP_SECS,
+            seconds => seconds + (1 << 32) - UNIX_EPOCH_NTP_SECS,
+        };
+        seconds * 1_000_000 + ((self.fraction as u64 * 1_000_000) >> 32)
+    }
+
+    fn read(buf: &[u8], offset: usize) -> Self {
+        let word = |offset: usize| {
+            u32::from_be_bytes([
+                buf[offset],
+                buf[offset + 1],
+                buf[offset + 2],
+                buf[offset + 3],
+            ])
+        };
+        NtpTimestamp {
+            seconds: word(offset),
+            fraction: word(offset + 4),
+        }
+    }
+
+    fn write(&self, buf: &mut [u8], offset: usize) {
+        buf[offset..offset + 4].copy_from_slice(&self.seconds.to_be_bytes());
+        buf[offset + 4..offset + 8].copy_from_slice(&self.fraction.to_be_bytes());
+    }
+}
+
+/// A request carrying `transmit`, which the server echoes back as the originate timestamp.
+/// It only has to be unique, so uptime does when the real time isn't known yet.
+pub fn encode_request(transmit: NtpTimestamp) -> [u8; PACKET_SIZE] {
+    let mut packet = [0; PACKET_SIZE];
+    packet[0] = VERSION << 3 | MODE_CLIENT;
+    transmit.write(&mut packet, 40);
+    packet
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Reply {
+    pub stratum: u8,
+    /// When the server got the request, by its clock
+    pub receive: NtpTimestamp,
+    /// When the server sent the reply, by its clock
+    pub transmit: NtpTimestamp,
+}
+
+impl Reply {
+    /// Unix time in µs at the moment the reply arrived, `round_trip` being the time
+    /// since the request went out. Assumes both directions took equally long.
+    pub fn unix_micros_at_arrival(&self, round_trip: Duration) -> u64 {
+        let transmit = self.transmit.to_unix_micros();
+        let processing = transmit.saturating_sub(self.receive.to_unix_micros());
+        let network = round_trip.as_micros().saturating_sub(processing);
+        transmit + network / 2
+    }
+}
+
+/// Checks a reply to the request sent with `request_transmit`
+pub fn decode_reply(buf: &[u8], request_transmit: NtpTimestamp) -> Result<Reply, SntpError> {
+    if buf.len() < PACKET_SIZE {
+        return Err(SntpError::TooShort);
+    }
+    if buf[0] & 0x07 != MODE_SERVER {
+        return Err(SntpError::NotServerReply);
+    }
+    if NtpTimestamp::read(buf, 24) != request_transmit {
+        return Err(SntpError::OriginMismatch);
+    }
+    let stratum = buf[1];
+    if stratum == 0 {
+        return Err(SntpError::KissOfDeath([buf[12], buf[13], buf[14], buf[15]]));
+    }
+    let transmit = NtpTimestamp::read(buf, 40);
+    if buf[0] >> 6 == LEAP_ALARM || stratum > 15 || transmit == NtpTimestamp::default() {
+        return Err(SntpError::Unsynchronised);
+    }
+    Ok(Reply {
+        stratum,
+        receive: NtpTimestamp::read(buf, 32),
+        transmit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-01T00:00:00Z
+    const NEW_YEAR_UNIX_SECS: u64 = 1_704_067_200;
+    const NEW_YEAR_MICROS: u64 = NEW_YEAR_UNIX_SECS * 1_000_000;
+
+    /// What a stratum 2 server answers to `request`, receiving and sending at the
+    /// given Unix times
+    fn serve(request: &[u8], receive_micros: u64, transmit_micros: u64) -> [u8; PACKET_SIZE] {
+        assert_eq!(request[0] & 0x07, MODE_CLIENT);
+        let mut reply = [0; PACKET_SIZE];
+        reply[0] = request[0] & 0x38 | MODE_SERVER;
+        reply[1] = 2;
+        reply[12..16].copy_from_slice(&[192, 0, 2, 1]);
+        reply[24..32].copy_from_slice(&request[40..48]);
+        NtpTimestamp::from_unix_micros(receive_micros).write(&mut reply, 32);
+        NtpTimestamp::from_unix_micros(transmit_micros).write(&mut reply, 40);
+        reply
+    }
+
+    #[test]
+    fn encodes_a_client_request() {
+        let transmit = NtpTimestamp {
+            seconds: 0x0102_0304,
+            fraction: 0x0506_0708,
+        };
+        let request = encode_request(transmit);
+        // No leap indicator, version 4, client mode
+        assert_eq!(request[0], 0x23);
+        assert!(request[1..40].iter().all(|byte| *byte == 0));
+        assert_eq!(request[40..48], [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn converts_between_unix_time_and_timestamps() {
+        let timestamp = NtpTimestamp::from_unix_micros(NEW_YEAR_MICROS + 500_000);
+        assert_eq!(
+            timestamp,
+            NtpTimestamp {
+                seconds: 3_913_056_000,
+                fraction: 0x8000_0000,
+            }
+        );
+        assert_eq!(timestamp.to_unix_micros(), NEW_YEAR_MICROS + 500_000);
+
+        // The fraction is finer than a µs, converting back truncates by at most one
+        let micros = NEW_YEAR_MICROS + 123_456;
+        let back = NtpTimestamp::from_unix_micros(micros).to_unix_micros();
+        assert!(micros - back <= 1);
+    }
+
+    #[test]
+    fn timestamps_after_2036_are_in_the_next_era() {
+        // 2036-02-07T06:28:16Z, where the seconds wrap
+        let wrap = NtpTimestamp {
+            seconds: 0,
+            fraction: 0,
+        };
+        assert_eq!(wrap.to_unix_micros(), 2_085_978_496 * 1_000_000);
+        let before = NtpTimestamp {
+            seconds: u32::MAX,
+            fraction: 0,
+        };
+        assert_eq!(before.to_unix_micros(), 2_085_978_495 * 1_000_000);
+        assert_eq!(
+            NtpTimestamp::from_unix_micros(2_085_978_496 * 1_000_000),
+            wrap
+        );
+    }
+
+    #[test]
+    fn decodes_a_reply() {
+        let transmit = NtpTimestamp::from_unix_micros(42_000);
+        let request = encode_request(transmit);
+        let reply = serve(&request, NEW_YEAR_MICROS, NEW_YEAR_MICROS + 15_625);
+
+        let reply = decode_reply(&reply, transmit).unwrap();
+        assert_eq!(reply.stratum, 2);
+        assert_eq!(reply.receive.to_unix_micros(), NEW_YEAR_MICROS);
+        assert_eq!(reply.transmit.to_unix_micros(), NEW_YEAR_MICROS + 15_625);
+    }
+
+    #[test]
+    fn rejects_replies_that_are_not_for_us() {
+        let transmit = NtpTimestamp::from_unix_micros(42_000);
+        let request = encode_request(transmit);
+        let reply = serve(&request, NEW_YEAR_MICROS, NEW_YEAR_MICROS);
+
+        assert_eq!(
+            decode_reply(&reply[..47], transmit),
+            Err(SntpError::TooShort)
+        );
+        assert_eq!(
+            decode_reply(&request, transmit),
+            Err(SntpError::NotServerReply)
+        );
+        let mut broadcast = reply;
+        broadcast[0] = broadcast[0] & !0x07 | 5;
+        assert_eq!(
+            decode_reply(&broadcast, transmit),
+            Err(SntpError::NotServerReply)
+        );
+        // A late reply to an earlier request
+        let earlier = NtpTimestamp::from_unix_micros(41_000);
+        assert_eq!(
+            decode_reply(&reply, earlier),
+            Err(SntpError::OriginMismatch)
+        );
+    }
+
+    #[test]
+    fn rejects_unsynchronised_servers() {
+        let transmit = NtpTimestamp::from_unix_micros(42_000);
+        let reply = serve(&encode_request(transmit), NEW_YEAR_MICROS, NEW_YEAR_MICROS);
+
+        let mut alarm = reply;
+        alarm[0] |= LEAP_ALARM << 6;
+        assert_eq!(
+            decode_reply(&alarm, transmit),
+            Err(SntpError::Unsynchronised)
+        );
+        let mut stratum_16 = reply;
+        stratum_16[1] = 16;
+        assert_eq!(
+            decode_reply(&stratum_16, transmit),
+            Err(SntpError::Unsynchronised)
+        );
+        let mut no_transmit = reply;
+        no_transmit[40..48].fill(0);
+        assert_eq!(
+            decode_reply(&no_transmit, transmit),
+            Err(SntpError::Unsynchronised)
+        );
+    }
+
+    #[test]
+    fn reports_a_kiss_of_death() {
+        let transmit = NtpTimestamp::from_unix_micros(42_000);
+        let mut reply = serve(&encode_request(transmit), 0, 0);
+        reply[1] = 0;
+        reply[12..16].copy_from_slice(b"RATE");
+        reply[32..48].fill(0);
+        assert_eq!(
+            decode_reply(&reply, transmit),
+            Err(SntpError::KissOfDeath(*b"RATE"))
+        );
+    }
+
+    #[test]
+    fn splits_the_network_delay_evenly() {
+        let transmit = NtpTimestamp::from_unix_micros(42_000);
+        // 1/64 s in the server, exact in both units, and 50 ms on the network
+        let reply = serve(
+            &encode_request(transmit),
+            NEW_YEAR_MICROS,
+            NEW_YEAR_MICROS + 15_625,
+        );
+        let reply = decode_reply(&reply, transmit).unwrap();
+        assert_eq!(
+            reply.unix_micros_at_arrival(Duration::from_micros(65_625)),
+            NEW_YEAR_MICROS + 15_625 + 25_000
+        );
+        // A round trip shorter than the server claims to have taken adds nothing
+        assert_eq!(
+            reply.unix_micros_at_arrival(Duration::from_millis(1)),
+            NEW_YEAR_MICROS + 15_625
+        );
+    }
+}
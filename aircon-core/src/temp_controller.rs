@@ -0,0 +1,1239 @@
+use core::fmt;
+use core::ops::RangeInclusive;
+use core::str::FromStr;
+
+use embassy_time::{Duration, Instant};
+use embedded_hal::digital::OutputPin;
+
+use crate::dht11::Reading;
+use crate::duty_cycle::DutyCycleTracker;
+use crate::units::Temperature;
+
+/// How long a boost lasts unless asked for another length
+pub const DEFAULT_BOOST: Duration = Duration::from_secs(30 * 60);
+/// Failsafe duty cycle period unless asked for another length
+pub const DEFAULT_FAILSAFE_PERIOD: Duration = Duration::from_secs(10 * 60);
+/// Duty limit window unless asked for another length
+pub const DEFAULT_DUTY_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// Longest duration users may set, keeps the time arithmetic far from overflowing
+pub const MAX_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// `secs` as a duration users may set, `None` past `MAX_DURATION`. Check before
+/// converting, `Duration::from_secs` overflows on huge values.
+pub fn user_duration(secs: u64) -> Option<Duration> {
+    (secs <= MAX_DURATION.as_secs()).then(|| Duration::from_secs(secs))
+}
+
+/// Time from `start` to `now`, zero if the clock reads earlier
+fn elapsed(now: Instant, start: Instant) -> Duration {
+    now.checked_duration_since(start).unwrap_or_default()
+}
+
+/// Source of the current time, lets the controller run against a fake clock
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ControllerState {
+    Idle,
+    Running {
+        starttime: Instant,
+    },
+    Cooldown {
+        starttime: Instant,
+    },
+    /// No fresh reading within `sensor_timeout`, the relay follows the failsafe
+    Fault {
+        /// When the failsafe cycle started, or starts once the cooldown or rest the fault
+        /// interrupted is over
+        since: Instant,
+        relay: bool,
+    },
+    /// Stopped for `forced_rest` after running into `limit`
+    Rest {
+        starttime: Instant,
+        limit: RuntimeLimit,
+    },
+}
+
+impl ControllerState {
+    /// Time until the minimum runtime or cooldown is over, zero when nothing is pending
+    pub fn time_remaining(&self, config: &TempControllerConfig, now: Instant) -> Duration {
+        let (starttime, length) = match *self {
+            ControllerState::Idle | ControllerState::Fault { .. } => {
+                return Duration::from_ticks(0)
+            }
+            ControllerState::Running { starttime } => (starttime, config.minimum_runtime),
+            ControllerState::Cooldown { starttime } => (starttime, config.cooldown_time),
+            ControllerState::Rest { starttime, .. } => (starttime, config.forced_rest),
+        };
+        let elapsed = now.checked_duration_since(starttime).unwrap_or_default();
+        length.checked_sub(elapsed).unwrap_or_default()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ControllerState::Idle => "idle",
+            ControllerState::Running { .. } => "running",
+            ControllerState::Cooldown { .. } => "cooldown",
+            ControllerState::Fault { .. } => "fault",
+            ControllerState::Rest { .. } => "rest",
+        }
+    }
+
+    /// Whether the compressor relay is closed
+    pub fn is_relay_on(&self) -> bool {
+        matches!(
+            self,
+            ControllerState::Running { .. } | ControllerState::Fault { relay: true, .. }
+        )
+    }
+}
+
+/// Limit on compressor use that forced a rest, reported as a warning
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RuntimeLimit {
+    /// Ran for `maximum_runtime` without reaching the setpoint
+    MaximumRuntime,
+    /// Ran for more than the `duty_limit` share of its window
+    DutyCycle,
+}
+
+impl RuntimeLimit {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RuntimeLimit::MaximumRuntime => "max_runtime",
+            RuntimeLimit::DutyCycle => "duty_cycle",
+        }
+    }
+}
+
+/// Longest share of a rolling window the compressor may run for
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DutyLimit {
+    pub percent: u8,
+    pub window: Duration,
+}
+
+/// What the relay does while there is no fresh reading
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Failsafe {
+    /// Keeps the compressor off
+    #[default]
+    Off,
+    /// Runs the compressor for `percent` of every `period`, on time first
+    DutyCycle { percent: u8, period: Duration },
+}
+
+impl Failsafe {
+    /// Whether the relay is closed `elapsed` into a fault. The on time lasts at least
+    /// `minimum_on` and the off time at least `minimum_off`, stretching the period if
+    /// either is shorter.
+    pub fn relay_on(&self, elapsed: Duration, minimum_on: Duration, minimum_off: Duration) -> bool {
+        match *self {
+            Failsafe::Off | Failsafe::DutyCycle { percent: 0, .. } => false,
+            Failsafe::DutyCycle { percent: 100.., .. } => true,
+            Failsafe::DutyCycle { percent, period } => {
+                let on = period.as_ticks() * percent as u64 / 100;
+                let off = period.as_ticks() - on;
+                let on = on.max(minimum_on.as_ticks()).max(1);
+                let off = off.max(minimum_off.as_ticks()).max(1);
+                elapsed.as_ticks() % (on + off) < on
+            }
+        }
+    }
+}
+
+/// Whether the controller follows the temperature or is told what to do
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ControllerMode {
+    /// Runs the compressor by the setpoint and the schedule
+    #[default]
+    Auto,
+    /// Keeps the compressor off, stopping it straight away without waiting for the minimum runtime
+    ForceOff,
+    /// Keeps the compressor on whatever the temperature, it still rests for the cooldown time
+    ForceOn,
+    /// Like `ForceOn` until the given time, then back to `Auto`
+    Boost { until: Instant },
+}
+
+impl ControllerMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ControllerMode::Auto => "auto",
+            ControllerMode::ForceOff => "off",
+            ControllerMode::ForceOn => "on",
+            ControllerMode::Boost { .. } => "boost",
+        }
+    }
+
+    /// Time until a boost is over, zero in every other mode
+    pub fn time_remaining(&self, now: Instant) -> Duration {
+        match *self {
+            ControllerMode::Boost { until } => {
+                until.checked_duration_since(now).unwrap_or_default()
+            }
+            _ => Duration::from_ticks(0),
+        }
+    }
+}
+
+/// Error returned when a mode name is none of `auto`, `off`, `on` or `boost`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ParseModeError;
+
+/// A mode as named by the user, a boost only gets its end once it is applied
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ModeInput {
+    Auto,
+    Off,
+    On,
+    Boost,
+}
+
+impl ModeInput {
+    /// The mode, a boost lasting `boost` from `now`
+    pub fn mode(&self, now: Instant, boost: Duration) -> ControllerMode {
+        match self {
+            ModeInput::Auto => ControllerMode::Auto,
+            ModeInput::Off => ControllerMode::ForceOff,
+            ModeInput::On => ControllerMode::ForceOn,
+            ModeInput::Boost => ControllerMode::Boost {
+                until: now.checked_add(boost).unwrap_or(Instant::MAX),
+            },
+        }
+    }
+}
+
+/// Parses the mode names, `auto`, `off`, `on` or `boost`
+impl FromStr for ModeInput {
+    type Err = ParseModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            ("auto", ModeInput::Auto),
+            ("off", ModeInput::Off),
+            ("on", ModeInput::On),
+            ("boost", ModeInput::Boost),
+        ]
+        .into_iter()
+        .find(|(name, _)| s.eq_ignore_ascii_case(name))
+        .map(|(_, input)| input)
+        .ok_or(ParseModeError)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TempControllerConfig {
+    /// Setpoint, the compressor is started once the temperature rises above this
+    pub threshold_temperature: Temperature,
+    /// Deadband below the setpoint, the compressor keeps running until the temperature drops below `threshold_temperature - hysteresis`
+    pub hysteresis: Temperature,
+    /// Shortest time the compressor is allowed to run once started
+    pub minimum_runtime: Duration,
+    /// Shortest time the compressor has to rest before it may be started again
+    pub cooldown_time: Duration,
+    /// Age at which the last reading is too old to act on, the controller then faults
+    pub sensor_timeout: Duration,
+    /// Relay behaviour while faulted
+    pub failsafe: Failsafe,
+    /// Longest time the compressor may run in one go, `None` for no limit
+    pub maximum_runtime: Option<Duration>,
+    /// Longest share of a rolling window the compressor may run for, `None` for no limit
+    pub duty_limit: Option<DutyLimit>,
+    /// Rest after running into either limit, instead of the cooldown time
+    pub forced_rest: Duration,
+}
+
+impl Default for TempControllerConfig {
+    fn default() -> Self {
+        TempControllerConfig {
+            threshold_temperature: Temperature::from_celsius(20),
+            hysteresis: Temperature::from_celsius(1),
+            minimum_runtime: Duration::from_secs(10),
+            cooldown_time: Duration::from_secs(10),
+            sensor_timeout: Duration::from_secs(60),
+            failsafe: Failsafe::Off,
+            maximum_runtime: None,
+            duty_limit: None,
+            forced_rest: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+impl TempControllerConfig {
+    /// Temperature below which a running compressor is allowed to stop
+    pub fn lower_temperature(&self) -> Temperature {
+        self.threshold_temperature.saturating_sub(self.hysteresis)
+    }
+
+    /// Checks the values users can set, every config from outside goes through this
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !SETPOINT_RANGE.contains(&self.threshold_temperature) {
+            return Err(ConfigError::Setpoint);
+        }
+        if self.hysteresis <= Temperature::from_celsius(0) || self.hysteresis > MAX_HYSTERESIS {
+            return Err(ConfigError::Hysteresis);
+        }
+        let (failsafe_percent, failsafe_period) = match self.failsafe {
+            Failsafe::Off => (0, None),
+            Failsafe::DutyCycle { percent, period } => (percent, Some(period)),
+        };
+        let durations = [
+            Some(self.minimum_runtime),
+            Some(self.cooldown_time),
+            Some(self.sensor_timeout),
+            Some(self.forced_rest),
+            failsafe_period,
+            self.maximum_runtime,
+            self.duty_limit.map(|limit| limit.window),
+        ];
+        if durations
+            .into_iter()
+            .flatten()
+            .any(|length| length > MAX_DURATION)
+        {
+            return Err(ConfigError::Duration);
+        }
+        // A zero window or period would count every run as over the limit
+        let periods = [failsafe_period, self.duty_limit.map(|limit| limit.window)];
+        if periods
+            .into_iter()
+            .flatten()
+            .any(|length| length < Duration::from_secs(1))
+        {
+            return Err(ConfigError::Period);
+        }
+        // A limit of 100% never stops the compressor, turning it off is the way to do that
+        let duty_percent = self.duty_limit.map(|limit| limit.percent);
+        if failsafe_percent > 100
+            || duty_percent.is_some_and(|percent| !(1..=99).contains(&percent))
+        {
+            return Err(ConfigError::Percent);
+        }
+        Ok(())
+    }
+}
+
+/// Setpoints users may choose, anything outside is a typo and would overflow when shown in °F
+pub const SETPOINT_RANGE: RangeInclusive<Temperature> =
+    Temperature::from_celsius(0)..=Temperature::from_celsius(50);
+/// Widest hysteresis users may choose
+pub const MAX_HYSTERESIS: Temperature = Temperature::from_celsius(10);
+
+/// Why a config from outside was rejected
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    /// Outside `SETPOINT_RANGE`
+    Setpoint,
+    /// Not above zero or wider than `MAX_HYSTERESIS`, the compressor would never stop
+    Hysteresis,
+    /// Longer than `MAX_DURATION`
+    Duration,
+    /// A failsafe period or duty limit window shorter than a second
+    Period,
+    /// A failsafe duty past 100%, or a duty limit outside 1 to 99%
+    Percent,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Setpoint => write!(
+                f,
+                "setpoint must be {} to {}°C",
+                SETPOINT_RANGE.start(),
+                SETPOINT_RANGE.end()
+            ),
+            ConfigError::Hysteresis => write!(
+                f,
+                "hysteresis must be above 0.0 and at most {}°C",
+                MAX_HYSTERESIS
+            ),
+            ConfigError::Duration => {
+                write!(f, "durations must be at most {}s", MAX_DURATION.as_secs())
+            }
+            ConfigError::Period => f.write_str("period and window must be at least 1s"),
+            ConfigError::Percent => {
+                f.write_str("failsafe duty must be 0 to 100%, duty limit 0 to 99%")
+            }
+        }
+    }
+}
+
+/// Totals of compressor use since boot
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CompressorStats {
+    pub starts: u32,
+    pub runtime: Duration,
+    pub maximum_runtime_rests: u32,
+    pub duty_cycle_rests: u32,
+}
+
+pub struct TempController<R: OutputPin, C: Clock> {
+    state: ControllerState,
+    relay_output: R,
+    clock: C,
+    config: TempControllerConfig,
+    /// Only counts finished runs, see `compressor_stats`
+    stats: CompressorStats,
+    /// When the relay closed, while it is closed
+    relay_since: Option<Instant>,
+    /// When the controller was created, readings are missing from then on until the first one
+    created: Instant,
+    /// Keeps the compressor off, e.g. while the schedule says so
+    standby: bool,
+    mode: ControllerMode,
+    /// Only kept while a duty limit is configured
+    duty_cycle: Option<DutyCycleTracker>,
+    /// Limit run into since the last `take_warning`
+    warning: Option<RuntimeLimit>,
+}
+
+impl<R: OutputPin, C: Clock> TempController<R, C> {
+    /// Creates a new temperature controller, starts off in Cooldown mode
+    pub fn new(config: TempControllerConfig, relay_output: R, clock: C) -> TempController<R, C> {
+        let now = clock.now();
+        TempController {
+            state: ControllerState::Cooldown { starttime: now },
+            relay_output,
+            clock,
+            config,
+            stats: CompressorStats::default(),
+            relay_since: None,
+            created: now,
+            standby: false,
+            mode: ControllerMode::Auto,
+            duty_cycle: None,
+            warning: None,
+        }
+    }
+
+    /// Whether the compressor should be started at `current_temperature`
+    fn wants_start(&self, current_temperature: Option<Temperature>) -> bool {
+        match self.mode {
+            ControllerMode::Auto => {
+                !self.standby
+                    && current_temperature
+                        .is_some_and(|temperature| temperature > self.config.threshold_temperature)
+            }
+            ControllerMode::ForceOff => false,
+            ControllerMode::ForceOn | ControllerMode::Boost { .. } => true,
+        }
+    }
+
+    /// Whether the compressor should stop at `current_temperature` once it ran long enough
+    fn wants_stop(&self, current_temperature: Option<Temperature>) -> bool {
+        match self.mode {
+            ControllerMode::Auto => {
+                self.standby
+                    || current_temperature
+                        .is_some_and(|temperature| temperature < self.config.lower_temperature())
+            }
+            ControllerMode::ForceOff => true,
+            ControllerMode::ForceOn | ControllerMode::Boost { .. } => false,
+        }
+    }
+
+    /// Relay state at `now` in a fault whose failsafe cycle starts at `since`, forced off
+    /// still wins over the failsafe
+    fn failsafe_relay(&self, now: Instant, since: Instant) -> bool {
+        self.mode != ControllerMode::ForceOff
+            && now >= since
+            && self.config.failsafe.relay_on(
+                elapsed(now, since),
+                self.config.minimum_runtime,
+                self.config.cooldown_time,
+            )
+    }
+
+    /// Whether the compressor ran for its whole duty limit share, also tracks the on time
+    fn duty_limit_reached(&mut self, now: Instant) -> bool {
+        let Some(limit) = self.config.duty_limit else {
+            self.duty_cycle = None;
+            return false;
+        };
+        let relay_on = self.state.is_relay_on();
+        let tracker = match &mut self.duty_cycle {
+            Some(tracker) if tracker.window() == limit.window => tracker,
+            tracker => tracker.insert(DutyCycleTracker::new(limit.window, now)),
+        };
+        tracker.update(now, relay_on);
+        tracker.percent() >= limit.percent
+    }
+
+    fn rest(&mut self, now: Instant, limit: RuntimeLimit) {
+        warn!("Compressor hit its {} limit, forcing a rest", limit.name());
+        self.state = ControllerState::Rest {
+            starttime: now,
+            limit,
+        };
+        let rests = match limit {
+            RuntimeLimit::MaximumRuntime => &mut self.stats.maximum_runtime_rests,
+            RuntimeLimit::DutyCycle => &mut self.stats.duty_cycle_rests,
+        };
+        *rests = rests.wrapping_add(1);
+        self.warning = Some(limit);
+    }
+
+    /// Steps the state machine with the latest valid reading, `None` while there has been none
+    pub fn update(&mut self, reading: Option<Reading>) {
+        let current_time = self.clock.now();
+        let last_reading_at = reading.map_or(self.created, |reading| reading.taken_at);
+        let stale = current_time
+            .checked_duration_since(last_reading_at)
+            .is_some_and(|age| age > self.config.sensor_timeout);
+        let current_temperature = reading.map(|reading| reading.temperature);
+
+        if let ControllerMode::Boost { until } = self.mode {
+            if current_time >= until {
+                self.mode = ControllerMode::Auto;
+            }
+        }
+        let duty_limit_reached = self.duty_limit_reached(current_time);
+
+        let controller_state_change = match self.state {
+            ControllerState::Fault { since, relay } if stale => {
+                let relay_now = self.failsafe_relay(current_time, since);
+                self.state = ControllerState::Fault {
+                    since,
+                    relay: relay_now,
+                };
+                relay_now != relay
+            }
+            _ if stale => {
+                warn!("No fresh sensor reading, controller faulted");
+                // The compressor still gets the whole cooldown or rest it was in
+                let hold = match self.state {
+                    ControllerState::Cooldown { .. } | ControllerState::Rest { .. } => {
+                        self.state.time_remaining(&self.config, current_time)
+                    }
+                    _ => Duration::from_ticks(0),
+                };
+                let since = current_time.checked_add(hold).unwrap_or(Instant::MAX);
+                self.state = ControllerState::Fault {
+                    since,
+                    relay: self.failsafe_relay(current_time, since),
+                };
+                true
+            }
+            // The failsafe may have run the compressor, let it rest before regulating again
+            ControllerState::Fault { .. } => {
+                info!("Sensor readings are back, controller recovered");
+                self.state = ControllerState::Cooldown {
+                    starttime: current_time,
+                };
+                true
+            }
+            ControllerState::Idle => {
+                if self.wants_start(current_temperature) && !duty_limit_reached {
+                    self.state = ControllerState::Running {
+                        starttime: current_time,
+                    };
+                    true
+                } else {
+                    false
+                }
+            }
+            ControllerState::Running { starttime } => {
+                let run_time = elapsed(current_time, starttime);
+                let ran_long_enough = run_time > self.config.minimum_runtime;
+                if (ran_long_enough || self.mode == ControllerMode::ForceOff)
+                    && self.wants_stop(current_temperature)
+                {
+                    self.state = ControllerState::Cooldown {
+                        starttime: current_time,
+                    };
+                    true
+                } else if self
+                    .config
+                    .maximum_runtime
+                    .is_some_and(|maximum| run_time >= maximum)
+                {
+                    self.rest(current_time, RuntimeLimit::MaximumRuntime);
+                    true
+                } else if ran_long_enough && duty_limit_reached {
+                    self.rest(current_time, RuntimeLimit::DutyCycle);
+                    true
+                } else {
+                    false
+                }
+            }
+            ControllerState::Rest { starttime, .. } => {
+                if elapsed(current_time, starttime) > self.config.forced_rest {
+                    self.state = ControllerState::Idle;
+                    true
+                } else {
+                    false
+                }
+            }
+            ControllerState::Cooldown { starttime } => {
+                if elapsed(current_time, starttime) > self.config.cooldown_time {
+                    self.state = ControllerState::Idle;
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+
+        // Counted by the relay so runs of the failsafe are included
+        let relay_on = self.state.is_relay_on();
+        match (controller_state_change, self.relay_since) {
+            (true, None) if relay_on => {
+                self.relay_since = Some(current_time);
+                self.stats.starts += 1;
+            }
+            (true, Some(since)) if !relay_on => {
+                self.relay_since = None;
+                self.stats.runtime += elapsed(current_time, since);
+            }
+            _ => {}
+        }
+
+        if controller_state_change && relay_on {
+            debug!("Setting Controller Relay");
+            if self.relay_output.set_high().is_err() {
+                error!("Failed to set Controller Relay");
+            }
+        } else if controller_state_change {
+            debug!("Unsetting Controller Relay");
+            if self.relay_output.set_low().is_err() {
+                error!("Failed to unset Controller Relay");
+            }
+        };
+    }
+
+    pub fn update_config(&mut self, config: TempControllerConfig) {
+        self.config = config;
+    }
+
+    /// In standby the compressor isn't started, a running one stops once it ran its minimum runtime
+    pub fn set_standby(&mut self, standby: bool) {
+        self.standby = standby;
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.standby
+    }
+
+    /// Takes effect on the next `update`
+    pub fn set_mode(&mut self, mode: ControllerMode) {
+        self.mode = mode;
+    }
+
+    pub fn get_mode(&self) -> ControllerMode {
+        self.mode
+    }
+
+    pub fn get_config(&self) -> TempControllerConfig {
+        self.config
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self.state, ControllerState::Running { starttime: _ })
+    }
+
+    pub fn _is_idle(&self) -> bool {
+        self.state == ControllerState::Idle
+    }
+
+    pub fn is_cooldown(&self) -> bool {
+        matches!(self.state, ControllerState::Cooldown { starttime: _ })
+    }
+
+    pub fn is_fault(&self) -> bool {
+        matches!(self.state, ControllerState::Fault { .. })
+    }
+
+    /// The limit run into since the last call, for raising a warning once
+    pub fn take_warning(&mut self) -> Option<RuntimeLimit> {
+        self.warning.take()
+    }
+
+    pub fn get_state(&self) -> ControllerState {
+        self.state
+    }
+
+    /// Compressor starts and runtime since boot, including the current run
+    pub fn compressor_stats(&self) -> CompressorStats {
+        let mut stats = self.stats;
+        if let Some(since) = self.relay_since {
+            stats.runtime += elapsed(self.clock.now(), since);
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+    use std::convert::Infallible;
+    use std::rc::Rc;
+
+    use crate::units::Humidity;
+
+    /// Clock the test moves by hand, shared with the controller
+    #[derive(Clone, Default)]
+    struct FakeClock(Rc<Cell<u64>>);
+
+    impl FakeClock {
+        fn set_secs(&self, secs: u64) {
+            self.0.set(secs);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            Instant::from_secs(self.0.get())
+        }
+    }
+
+    /// Relay that remembers its level, shared with the controller
+    #[derive(Clone, Default)]
+    struct MockRelay(Rc<Cell<bool>>);
+
+    impl MockRelay {
+        fn is_on(&self) -> bool {
+            self.0.get()
+        }
+    }
+
+    impl embedded_hal::digital::ErrorType for MockRelay {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for MockRelay {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.set(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.set(true);
+            Ok(())
+        }
+    }
+
+    struct Harness {
+        controller: TempController<MockRelay, FakeClock>,
+        clock: FakeClock,
+        relay: MockRelay,
+    }
+
+    impl Harness {
+        fn new(config: TempControllerConfig) -> Self {
+            let clock = FakeClock::default();
+            let relay = MockRelay::default();
+            Harness {
+                controller: TempController::new(config, relay.clone(), clock.clone()),
+                clock,
+                relay,
+            }
+        }
+
+        /// Steps the controller at `secs` with a fresh reading of `tenths` °C
+        fn step(&mut self, secs: u64, tenths: i16) -> ControllerState {
+            self.clock.set_secs(secs);
+            self.controller.update(Some(Reading {
+                temperature: Temperature::from_tenths(tenths),
+                humidity: Humidity::from_tenths(500),
+                taken_at: Instant::from_secs(secs),
+            }));
+            self.controller.get_state()
+        }
+
+        /// Steps the controller at `secs` with the last reading taken at `taken_secs`
+        fn step_stale(&mut self, secs: u64, taken_secs: u64) -> ControllerState {
+            self.clock.set_secs(secs);
+            self.controller.update(Some(Reading {
+                temperature: Temperature::from_celsius(25),
+                humidity: Humidity::from_tenths(500),
+                taken_at: Instant::from_secs(taken_secs),
+            }));
+            self.controller.get_state()
+        }
+    }
+
+    fn config() -> TempControllerConfig {
+        TempControllerConfig {
+            threshold_temperature: Temperature::from_celsius(20),
+            hysteresis: Temperature::from_celsius(1),
+            minimum_runtime: Duration::from_secs(60),
+            cooldown_time: Duration::from_secs(120),
+            sensor_timeout: Duration::from_secs(30),
+            ..TempControllerConfig::default()
+        }
+    }
+
+    fn running(secs: u64) -> ControllerState {
+        ControllerState::Running {
+            starttime: Instant::from_secs(secs),
+        }
+    }
+
+    fn cooldown(secs: u64) -> ControllerState {
+        ControllerState::Cooldown {
+            starttime: Instant::from_secs(secs),
+        }
+    }
+
+    #[test]
+    fn cycles_idle_running_cooldown_idle() {
+        let mut harness = Harness::new(config());
+        assert_eq!(harness.controller.get_state(), cooldown(0));
+
+        assert_eq!(harness.step(121, 250), ControllerState::Idle);
+        assert!(!harness.relay.is_on());
+        assert_eq!(harness.step(122, 250), running(122));
+        assert!(harness.relay.is_on());
+
+        assert_eq!(harness.step(183, 180), cooldown(183));
+        assert!(!harness.relay.is_on());
+        assert_eq!(harness.step(304, 180), ControllerState::Idle);
+    }
+
+    #[test]
+    fn starts_only_above_the_setpoint() {
+        let mut harness = Harness::new(config());
+        assert_eq!(harness.step(121, 200), ControllerState::Idle);
+        assert_eq!(harness.step(122, 200), ControllerState::Idle);
+        assert_eq!(harness.step(123, 201), running(123));
+    }
+
+    #[test]
+    fn keeps_running_within_the_hysteresis_band() {
+        let mut harness = Harness::new(config());
+        harness.step(121, 250);
+        harness.step(122, 250);
+
+        assert_eq!(harness.step(200, 195), running(122));
+        assert_eq!(harness.step(201, 190), running(122));
+        assert_eq!(harness.step(202, 189), cooldown(202));
+    }
+
+    #[test]
+    fn holds_for_the_minimum_runtime() {
+        let mut harness = Harness::new(config());
+        harness.step(121, 250);
+        harness.step(122, 250);
+
+        assert_eq!(harness.step(130, 150), running(122));
+        assert_eq!(harness.step(182, 150), running(122));
+        assert!(harness.relay.is_on());
+        assert_eq!(harness.step(183, 150), cooldown(183));
+    }
+
+    #[test]
+    fn blocks_restarts_during_cooldown() {
+        let mut harness = Harness::new(config());
+        harness.step(121, 250);
+        harness.step(122, 250);
+        harness.step(183, 150);
+
+        for secs in [184, 250, 303] {
+            assert_eq!(harness.step(secs, 300), cooldown(183));
+            assert!(!harness.relay.is_on());
+        }
+        assert_eq!(harness.step(304, 300), ControllerState::Idle);
+        assert_eq!(harness.step(305, 300), running(305));
+    }
+
+    #[test]
+    fn standby_stops_after_the_minimum_runtime() {
+        let mut harness = Harness::new(config());
+        harness.step(121, 250);
+        harness.step(122, 250);
+        harness.controller.set_standby(true);
+
+        assert_eq!(harness.step(150, 250), running(122));
+        assert_eq!(harness.step(183, 250), cooldown(183));
+        harness.step(304, 250);
+        assert_eq!(harness.step(305, 250), ControllerState::Idle);
+    }
+
+    #[test]
+    fn forced_off_stops_straight_away() {
+        let mut harness = Harness::new(config());
+        harness.step(121, 250);
+        harness.step(122, 250);
+        harness.controller.set_mode(ControllerMode::ForceOff);
+
+        assert_eq!(harness.step(123, 250), cooldown(123));
+        assert!(!harness.relay.is_on());
+    }
+
+    #[test]
+    fn boost_runs_until_it_ends() {
+        let mut harness = Harness::new(config());
+        harness.step(121, 150);
+        harness.controller.set_mode(ControllerMode::Boost {
+            until: Instant::from_secs(300),
+        });
+
+        assert_eq!(harness.step(122, 150), running(122));
+        assert_eq!(harness.step(299, 150), running(122));
+        assert_eq!(harness.step(300, 150), cooldown(300));
+        assert_eq!(harness.controller.get_mode(), ControllerMode::Auto);
+    }
+
+    #[test]
+    fn faults_without_fresh_readings_and_recovers() {
+        let mut harness = Harness::new(config());
+        harness.step(121, 250);
+        harness.step(122, 250);
+
+        let state = harness.step_stale(160, 122);
+        assert_eq!(
+            state,
+            ControllerState::Fault {
+                since: Instant::from_secs(160),
+                relay: false,
+            }
+        );
+        assert!(!harness.relay.is_on());
+
+        assert_eq!(harness.step(170, 250), cooldown(170));
+    }
+
+    #[test]
+    fn faults_when_no_reading_ever_arrives() {
+        let mut harness = Harness::new(config());
+        harness.clock.set_secs(31);
+        harness.controller.update(None);
+        assert!(harness.controller.is_fault());
+    }
+
+    #[test]
+    fn failsafe_cycles_the_relay_and_counts_its_runs() {
+        let mut harness = Harness::new(TempControllerConfig {
+            failsafe: Failsafe::DutyCycle {
+                percent: 50,
+                period: Duration::from_secs(600),
+            },
+            ..config()
+        });
+        harness.step(0, 180);
+        harness.step(121, 180);
+
+        assert!(harness.step_stale(152, 121).is_relay_on());
+        assert!(harness.relay.is_on());
+        assert!(harness.step_stale(451, 121).is_relay_on());
+        assert!(!harness.step_stale(452, 121).is_relay_on());
+        assert!(!harness.relay.is_on());
+        assert!(harness.step_stale(752, 121).is_relay_on());
+
+        let stats = harness.controller.compressor_stats();
+        assert_eq!(stats.starts, 2);
+        assert_eq!(stats.runtime, Duration::from_secs(300));
+    }
+
+    #[test]
+    fn failsafe_waits_out_the_cooldown() {
+        let mut harness = Harness::new(TempControllerConfig {
+            failsafe: Failsafe::DutyCycle {
+                percent: 50,
+                period: Duration::from_secs(600),
+            },
+            ..config()
+        });
+        harness.step(121, 250);
+        harness.step(122, 250);
+        assert_eq!(harness.step(200, 150), cooldown(200));
+
+        // Stale 40 s into the 120 s cooldown
+        let state = harness.step_stale(240, 200);
+        assert_eq!(
+            state,
+            ControllerState::Fault {
+                since: Instant::from_secs(320),
+                relay: false,
+            }
+        );
+        assert!(!harness.relay.is_on());
+        assert!(!harness.step_stale(319, 200).is_relay_on());
+        assert!(harness.step_stale(320, 200).is_relay_on());
+        assert!(harness.relay.is_on());
+        assert_eq!(harness.controller.compressor_stats().starts, 2);
+    }
+
+    #[test]
+    fn faults_from_a_forced_rest_and_from_a_run() {
+        let config = TempControllerConfig {
+            failsafe: Failsafe::DutyCycle {
+                percent: 50,
+                period: Duration::from_secs(600),
+            },
+            maximum_runtime: Some(Duration::from_secs(600)),
+            forced_rest: Duration::from_secs(300),
+            ..config()
+        };
+        let mut harness = Harness::new(config);
+        harness.step(121, 250);
+        harness.step(122, 250);
+        assert!(matches!(
+            harness.step(722, 250),
+            ControllerState::Rest { .. }
+        ));
+
+        // The forced rest is sat out before the failsafe runs the compressor again
+        let state = harness.step_stale(800, 722);
+        assert_eq!(
+            state,
+            ControllerState::Fault {
+                since: Instant::from_secs(1022),
+                relay: false,
+            }
+        );
+        assert!(!harness.step_stale(1021, 722).is_relay_on());
+        assert!(harness.step_stale(1022, 722).is_relay_on());
+        assert_eq!(harness.step(1030, 250), cooldown(1030));
+
+        // A run that faults keeps the relay closed, the failsafe starts with its on time
+        let mut harness = Harness::new(config);
+        harness.step(121, 250);
+        harness.step(122, 250);
+        let state = harness.step_stale(200, 122);
+        assert_eq!(
+            state,
+            ControllerState::Fault {
+                since: Instant::from_secs(200),
+                relay: true,
+            }
+        );
+        assert!(harness.relay.is_on());
+        assert_eq!(harness.controller.compressor_stats().starts, 1);
+    }
+
+    #[test]
+    fn failsafe_phases_last_at_least_the_runtime_limits() {
+        let failsafe = Failsafe::DutyCycle {
+            percent: 10,
+            period: Duration::from_secs(100),
+        };
+        let minimum_on = Duration::from_secs(30);
+        let minimum_off = Duration::from_secs(60);
+        let relay_at = |secs| failsafe.relay_on(Duration::from_secs(secs), minimum_on, minimum_off);
+
+        assert!(relay_at(0));
+        assert!(relay_at(29));
+        assert!(!relay_at(30));
+        assert!(!relay_at(119));
+        assert!(relay_at(120));
+        assert!(!Failsafe::Off.relay_on(Duration::from_secs(0), minimum_on, minimum_off));
+    }
+
+    #[test]
+    fn rests_after_the_maximum_runtime() {
+        let mut harness = Harness::new(TempControllerConfig {
+            maximum_runtime: Some(Duration::from_secs(600)),
+            forced_rest: Duration::from_secs(300),
+            ..config()
+        });
+        harness.step(121, 250);
+        harness.step(122, 250);
+
+        assert_eq!(harness.step(721, 250), running(122));
+        let state = harness.step(722, 250);
+        assert_eq!(
+            state,
+            ControllerState::Rest {
+                starttime: Instant::from_secs(722),
+                limit: RuntimeLimit::MaximumRuntime,
+            }
+        );
+        assert!(!harness.relay.is_on());
+        assert_eq!(
+            harness.controller.take_warning(),
+            Some(RuntimeLimit::MaximumRuntime)
+        );
+        assert_eq!(harness.controller.take_warning(), None);
+
+        assert!(matches!(
+            harness.step(1022, 250),
+            ControllerState::Rest { .. }
+        ));
+        assert_eq!(harness.step(1023, 250), ControllerState::Idle);
+        assert_eq!(
+            harness.controller.compressor_stats().maximum_runtime_rests,
+            1
+        );
+    }
+
+    #[test]
+    fn rests_after_the_duty_limit() {
+        let mut harness = Harness::new(TempControllerConfig {
+            duty_limit: Some(DutyLimit {
+                percent: 50,
+                window: Duration::from_secs(600),
+            }),
+            forced_rest: Duration::from_secs(60),
+            ..config()
+        });
+        harness.step(121, 250);
+        harness.step(122, 250);
+
+        // 300 s of the 600 s window
+        assert_eq!(harness.step(421, 250), running(122));
+        let state = harness.step(422, 250);
+        assert_eq!(
+            state,
+            ControllerState::Rest {
+                starttime: Instant::from_secs(422),
+                limit: RuntimeLimit::DutyCycle,
+            }
+        );
+        assert!(!harness.relay.is_on());
+        assert_eq!(
+            harness.controller.take_warning(),
+            Some(RuntimeLimit::DutyCycle)
+        );
+
+        // The rest is over but the run is still within the window
+        assert_eq!(harness.step(483, 250), ControllerState::Idle);
+        assert_eq!(harness.step(600, 250), ControllerState::Idle);
+        assert!(!harness.relay.is_on());
+        // Once the start of the run dropped out of the window
+        assert_eq!(harness.step(723, 250), running(723));
+
+        let stats = harness.controller.compressor_stats();
+        assert_eq!(stats.duty_cycle_rests, 1);
+        assert_eq!(stats.maximum_runtime_rests, 0);
+    }
+
+    #[test]
+    fn duty_limit_waits_for_the_minimum_runtime() {
+        let mut harness = Harness::new(TempControllerConfig {
+            duty_limit: Some(DutyLimit {
+                percent: 1,
+                window: Duration::from_secs(600),
+            }),
+            ..config()
+        });
+        harness.step(121, 250);
+        harness.step(122, 250);
+        assert_eq!(harness.step(182, 250), running(122));
+        assert!(matches!(
+            harness.step(183, 250),
+            ControllerState::Rest {
+                limit: RuntimeLimit::DutyCycle,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn counts_starts_and_runtime() {
+        let mut harness = Harness::new(config());
+        harness.step(121, 250);
+        harness.step(122, 250);
+        harness.step(200, 250);
+        let stats = harness.controller.compressor_stats();
+        assert_eq!(stats.starts, 1);
+        assert_eq!(stats.runtime, Duration::from_secs(78));
+
+        harness.step(222, 150);
+        harness.clock.set_secs(1000);
+        let stats = harness.controller.compressor_stats();
+        assert_eq!(stats.starts, 1);
+        assert_eq!(stats.runtime, Duration::from_secs(100));
+    }
+
+    #[test]
+    fn survives_a_clock_near_its_end() {
+        let mut harness = Harness::new(TempControllerConfig {
+            maximum_runtime: Some(MAX_DURATION),
+            ..config()
+        });
+        let end = Instant::MAX.as_secs() - 10;
+        harness.step(end, 250);
+        harness.step(end + 1, 250);
+        assert_eq!(harness.step(end + 5, 250), running(end + 1));
+    }
+
+    #[test]
+    fn validate_rejects_unsafe_values() {
+        assert_eq!(config().validate(), Ok(()));
+        let setpoint = TempControllerConfig {
+            threshold_temperature: Temperature::from_celsius(51),
+            ..config()
+        };
+        assert_eq!(setpoint.validate(), Err(ConfigError::Setpoint));
+        let hysteresis = TempControllerConfig {
+            hysteresis: Temperature::from_tenths(-5),
+            ..config()
+        };
+        assert_eq!(hysteresis.validate(), Err(ConfigError::Hysteresis));
+        let zero_hysteresis = TempControllerConfig {
+            hysteresis: Temperature::from_celsius(0),
+            ..config()
+        };
+        assert_eq!(zero_hysteresis.validate(), Err(ConfigError::Hysteresis));
+        let duration = TempControllerConfig {
+            cooldown_time: MAX_DURATION + Duration::from_secs(1),
+            ..config()
+        };
+        assert_eq!(duration.validate(), Err(ConfigError::Duration));
+        let percent = TempControllerConfig {
+            duty_limit: Some(DutyLimit {
+                percent: 101,
+                window: DEFAULT_DUTY_WINDOW,
+            }),
+            ..config()
+        };
+        assert_eq!(percent.validate(), Err(ConfigError::Percent));
+        let no_limit = TempControllerConfig {
+            duty_limit: Some(DutyLimit {
+                percent: 100,
+                window: DEFAULT_DUTY_WINDOW,
+            }),
+            ..config()
+        };
+        assert_eq!(no_limit.validate(), Err(ConfigError::Percent));
+        let window = TempControllerConfig {
+            duty_limit: Some(DutyLimit {
+                percent: 50,
+                window: Duration::from_millis(999),
+            }),
+            ..config()
+        };
+        assert_eq!(window.validate(), Err(ConfigError::Period));
+        let period = TempControllerConfig {
+            failsafe: Failsafe::DutyCycle {
+                percent: 50,
+                period: Duration::from_ticks(0),
+            },
+            ..config()
+        };
+        assert_eq!(period.validate(), Err(ConfigError::Period));
+    }
+
+    #[test]
+    fn user_duration_stops_at_the_maximum() {
+        assert_eq!(user_duration(60), Some(Duration::from_secs(60)));
+        assert_eq!(user_duration(MAX_DURATION.as_secs()), Some(MAX_DURATION));
+        assert_eq!(user_duration(MAX_DURATION.as_secs() + 1), None);
+        assert_eq!(user_duration(u64::MAX), None);
+    }
+
+    #[test]
+    fn parses_mode_names() {
+        assert_eq!("Boost".parse(), Ok(ModeInput::Boost));
+        assert_eq!("off".parse(), Ok(ModeInput::Off));
+        assert_eq!("cool".parse::<ModeInput>(), Err(ParseModeError));
+    }
+}
@@ -0,0 +1,493 @@
+//! Fixed-point measurement types shared by the sensor driver, controller and user interfaces.
+//!
+//! Values are stored as `I16F16` and rendered with one decimal. Temperatures are
+//! always kept in °C, [`TemperatureUnit`] only affects how they are shown to and
+//! entered by users.
+
+use core::fmt;
+use core::str::FromStr;
+
+use fixed::types::I16F16;
+
+/// Error returned when parsing a decimal number with at most one useful decimal digit fails
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ParseUnitError;
+
+/// Parses a decimal such as `22`, `-3.5` or `22.25` into tenths, rounding half away from zero
+fn parse_tenths(s: &str) -> Result<i16, ParseUnitError> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if integer.is_empty() && fraction.is_empty() {
+        return Err(ParseUnitError);
+    }
+    if !integer
+        .bytes()
+        .chain(fraction.bytes())
+        .all(|b| b.is_ascii_digit())
+    {
+        return Err(ParseUnitError);
+    }
+
+    let mut tenths: i32 = 0;
+    for b in integer.bytes() {
+        tenths = tenths * 10 + (b - b'0') as i32;
+        if tenths > i16::MAX as i32 {
+            return Err(ParseUnitError);
+        }
+    }
+    let mut fraction = fraction.bytes().map(|b| (b - b'0') as i32);
+    tenths = tenths * 10 + fraction.next().unwrap_or(0);
+    if fraction.next().unwrap_or(0) >= 5 {
+        tenths += 1;
+    }
+    if negative {
+        tenths = -tenths;
+    }
+    i16::try_from(tenths).map_err(|_| ParseUnitError)
+}
+
+/// Rounds to the nearest tenth
+fn to_tenths(value: I16F16) -> i16 {
+    (value * 10).round().to_num()
+}
+
+fn from_tenths(tenths: i16) -> I16F16 {
+    I16F16::from_num(tenths) / 10
+}
+
+/// Writes tenths as a decimal with exactly one decimal digit
+fn fmt_tenths(tenths: i16, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let sign = if tenths < 0 { "-" } else { "" };
+    let magnitude = tenths.unsigned_abs();
+    write!(f, "{}{}.{}", sign, magnitude / 10, magnitude % 10)
+}
+
+#[cfg(feature = "defmt")]
+fn defmt_tenths(tenths: i16, f: defmt::Formatter<'_>) {
+    let sign = if tenths < 0 { "-" } else { "" };
+    let magnitude = tenths.unsigned_abs();
+    defmt::write!(f, "{=str}{}.{}", sign, magnitude / 10, magnitude % 10)
+}
+
+/// A temperature in °C
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub struct Temperature(I16F16);
+
+impl Temperature {
+    /// A whole number of degrees
+    pub const fn from_celsius(celsius: i16) -> Self {
+        Temperature(I16F16::from_bits((celsius as i32) << 16))
+    }
+
+    pub fn from_tenths(tenths: i16) -> Self {
+        Temperature(from_tenths(tenths))
+    }
+
+    /// Rounded to the nearest tenth of a degree
+    pub fn to_tenths(self) -> i16 {
+        to_tenths(self.0)
+    }
+
+    /// This temperature converted for display in `unit`
+    pub fn in_unit(self, unit: TemperatureUnit) -> UnitValue {
+        UnitValue(unit.convert_from_celsius(self.0))
+    }
+
+    /// Whether both read the same when shown in `unit`, to a tenth
+    pub fn same_in_unit(self, other: Temperature, unit: TemperatureUnit) -> bool {
+        to_tenths(unit.convert_from_celsius(self.0))
+            == to_tenths(unit.convert_from_celsius(other.0))
+    }
+
+    /// This temperature difference, e.g. a hysteresis, converted for display in `unit`
+    pub fn difference_in_unit(self, unit: TemperatureUnit) -> UnitValue {
+        UnitValue(unit.scale_from_celsius(self.0))
+    }
+
+    pub fn saturating_sub(self, other: Temperature) -> Temperature {
+        Temperature(self.0.saturating_sub(other.0))
+    }
+
+    pub fn saturating_add(self, other: Temperature) -> Temperature {
+        Temperature(self.0.saturating_add(other.0))
+    }
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_tenths(self.to_tenths(), f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Temperature {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt_tenths(self.to_tenths(), f)
+    }
+}
+
+impl FromStr for Temperature {
+    type Err = ParseUnitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_tenths(s).map(Temperature::from_tenths)
+    }
+}
+
+/// A relative humidity in %
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub struct Humidity(I16F16);
+
+impl Humidity {
+    pub fn from_tenths(tenths: u16) -> Self {
+        Humidity(I16F16::from_num(tenths) / 10)
+    }
+
+    /// Rounded to the nearest tenth of a percent
+    pub fn to_tenths(self) -> u16 {
+        (self.0 * 10).round().to_num()
+    }
+}
+
+impl fmt::Display for Humidity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_tenths(self.to_tenths() as i16, f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Humidity {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt_tenths(self.to_tenths() as i16, f)
+    }
+}
+
+/// Unit temperatures are shown in and entered with
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+impl TemperatureUnit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => "°C",
+            TemperatureUnit::Fahrenheit => "°F",
+        }
+    }
+
+    fn convert_from_celsius(&self, celsius: I16F16) -> I16F16 {
+        match self {
+            TemperatureUnit::Celsius => celsius,
+            TemperatureUnit::Fahrenheit => self.scale_from_celsius(celsius) + I16F16::from_num(32),
+        }
+    }
+
+    fn convert_to_celsius(&self, value: I16F16) -> I16F16 {
+        match self {
+            TemperatureUnit::Celsius => value,
+            TemperatureUnit::Fahrenheit => self.scale_to_celsius(value - I16F16::from_num(32)),
+        }
+    }
+
+    /// Converts a difference, which unlike an absolute temperature has no offset
+    fn scale_from_celsius(&self, difference: I16F16) -> I16F16 {
+        match self {
+            TemperatureUnit::Celsius => difference,
+            TemperatureUnit::Fahrenheit => difference.saturating_mul_int(9) / 5,
+        }
+    }
+
+    fn scale_to_celsius(&self, difference: I16F16) -> I16F16 {
+        match self {
+            TemperatureUnit::Celsius => difference,
+            TemperatureUnit::Fahrenheit => difference.saturating_mul_int(5) / 9,
+        }
+    }
+}
+
+impl fmt::Display for TemperatureUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+impl FromStr for TemperatureUnit {
+    type Err = ParseUnitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix('°').unwrap_or(s);
+        if s.eq_ignore_ascii_case("c") || s.eq_ignore_ascii_case("celsius") {
+            Ok(TemperatureUnit::Celsius)
+        } else if s.eq_ignore_ascii_case("f") || s.eq_ignore_ascii_case("fahrenheit") {
+            Ok(TemperatureUnit::Fahrenheit)
+        } else {
+            Err(ParseUnitError)
+        }
+    }
+}
+
+/// A temperature converted into a display unit, printed with one decimal
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct UnitValue(I16F16);
+
+impl fmt::Display for UnitValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_tenths(to_tenths(self.0), f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for UnitValue {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt_tenths(to_tenths(self.0), f)
+    }
+}
+
+/// A temperature as entered by a user, e.g. `22.5`, `72F` or `22.5°C`.
+///
+/// Values without a unit suffix are in whatever unit the user currently has selected.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TemperatureInput {
+    value: I16F16,
+    unit: Option<TemperatureUnit>,
+}
+
+impl TemperatureInput {
+    /// Interprets the input as an absolute temperature
+    pub fn temperature(&self, default_unit: TemperatureUnit) -> Temperature {
+        Temperature(
+            self.unit
+                .unwrap_or(default_unit)
+                .convert_to_celsius(self.value),
+        )
+    }
+
+    /// Interprets the input as a temperature difference, e.g. a hysteresis
+    pub fn difference(&self, default_unit: TemperatureUnit) -> Temperature {
+        Temperature(
+            self.unit
+                .unwrap_or(default_unit)
+                .scale_to_celsius(self.value),
+        )
+    }
+}
+
+impl FromStr for TemperatureInput {
+    type Err = ParseUnitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number_end = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+            .unwrap_or(s.len());
+        let (number, suffix) = s.split_at(number_end);
+        let unit = match suffix {
+            "" => None,
+            suffix => Some(suffix.parse()?),
+        };
+        Ok(TemperatureInput {
+            value: from_tenths(parse_tenths(number)?),
+            unit,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tenths() {
+        for (text, tenths) in [
+            ("22", 220),
+            ("22.5", 225),
+            ("+22.5", 225),
+            ("-3.5", -35),
+            ("-0.5", -5),
+            (".5", 5),
+            ("5.", 50),
+            ("0", 0),
+            ("007.10", 71),
+        ] {
+            assert_eq!(parse_tenths(text), Ok(tenths), "{text}");
+        }
+    }
+
+    #[test]
+    fn rounds_half_away_from_zero() {
+        assert_eq!(parse_tenths("22.25"), Ok(223));
+        assert_eq!(parse_tenths("22.249"), Ok(222));
+        assert_eq!(parse_tenths("-22.25"), Ok(-223));
+        assert_eq!(parse_tenths("-22.24"), Ok(-222));
+        assert_eq!(parse_tenths("22.95"), Ok(230));
+    }
+
+    #[test]
+    fn rejects_malformed_and_out_of_range_numbers() {
+        for text in [
+            "",
+            "-",
+            "+",
+            ".",
+            "-.",
+            "+-2",
+            "--2",
+            "2-",
+            "1.2.3",
+            "2a",
+            " 22",
+            "22 ",
+            "1e3",
+            "3276.8",
+            "-3276.9",
+            "3276.75",
+            "99999999999",
+        ] {
+            assert_eq!(parse_tenths(text), Err(ParseUnitError), "{text}");
+        }
+        assert_eq!(parse_tenths("3276.7"), Ok(i16::MAX));
+        assert_eq!(parse_tenths("-3276.8"), Ok(i16::MIN));
+    }
+
+    #[test]
+    fn formats_with_one_decimal() {
+        for (tenths, text) in [
+            (225, "22.5"),
+            (220, "22.0"),
+            (0, "0.0"),
+            (-5, "-0.5"),
+            (-35, "-3.5"),
+            (i16::MIN, "-3276.8"),
+        ] {
+            assert_eq!(Temperature::from_tenths(tenths).to_string(), text);
+        }
+        assert_eq!(Temperature::from_celsius(-12).to_string(), "-12.0");
+        assert_eq!(Humidity::from_tenths(555).to_string(), "55.5");
+        assert_eq!(Humidity::from_tenths(1000).to_string(), "100.0");
+    }
+
+    #[test]
+    fn tenths_round_trip() {
+        for tenths in [-400, -1, 0, 1, 3, 7, 225, 999, 5000] {
+            assert_eq!(Temperature::from_tenths(tenths).to_tenths(), tenths);
+            let text = Temperature::from_tenths(tenths).to_string();
+            assert_eq!(text.parse(), Ok(Temperature::from_tenths(tenths)));
+        }
+        for tenths in [0, 1, 333, 999, 1000] {
+            assert_eq!(Humidity::from_tenths(tenths).to_tenths(), tenths);
+        }
+        // Orders like the numbers, for comparing against the setpoint
+        assert!(Temperature::from_tenths(-5) < Temperature::from_tenths(0));
+        assert!(Temperature::from_tenths(225) > Temperature::from_celsius(22));
+    }
+
+    fn input(text: &str) -> TemperatureInput {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn converts_between_celsius_and_fahrenheit() {
+        let celsius = Temperature::from_tenths(225);
+        assert_eq!(
+            celsius.in_unit(TemperatureUnit::Fahrenheit).to_string(),
+            "72.5"
+        );
+        assert_eq!(
+            celsius.in_unit(TemperatureUnit::Celsius).to_string(),
+            "22.5"
+        );
+        assert_eq!(
+            input("72.5").temperature(TemperatureUnit::Fahrenheit),
+            celsius
+        );
+
+        for (celsius, fahrenheit) in [(-40, "-40.0"), (-10, "14.0"), (0, "32.0"), (50, "122.0")] {
+            let temperature = Temperature::from_celsius(celsius);
+            let shown = temperature.in_unit(TemperatureUnit::Fahrenheit).to_string();
+            assert_eq!(shown, fahrenheit);
+            assert_eq!(
+                input(&shown).temperature(TemperatureUnit::Fahrenheit),
+                temperature
+            );
+        }
+
+        // Not a whole tenth in °C, shown rounded and still the same once converted back
+        let seventy = input("70").temperature(TemperatureUnit::Fahrenheit);
+        assert_eq!(seventy.to_string(), "21.1");
+        assert_eq!(
+            seventy.in_unit(TemperatureUnit::Fahrenheit).to_string(),
+            "70.0"
+        );
+        assert!(seventy.same_in_unit(Temperature::from_tenths(211), TemperatureUnit::Celsius));
+        assert!(seventy.same_in_unit(Temperature::from_tenths(211), TemperatureUnit::Fahrenheit));
+        assert!(!seventy.same_in_unit(Temperature::from_tenths(212), TemperatureUnit::Fahrenheit));
+    }
+
+    #[test]
+    fn converts_differences_without_the_offset() {
+        let hysteresis = Temperature::from_celsius(1);
+        assert_eq!(
+            hysteresis
+                .difference_in_unit(TemperatureUnit::Fahrenheit)
+                .to_string(),
+            "1.8"
+        );
+        assert_eq!(
+            input("1.8")
+                .difference(TemperatureUnit::Fahrenheit)
+                .to_tenths(),
+            10
+        );
+        assert_eq!(
+            input("-9F").difference(TemperatureUnit::Celsius),
+            Temperature::from_celsius(-5)
+        );
+    }
+
+    #[test]
+    fn suffix_overrides_the_default_unit() {
+        let setpoint = Temperature::from_tenths(225);
+        // Stored in °C whatever it was entered in
+        for (text, default_unit) in [
+            ("22.5", TemperatureUnit::Celsius),
+            ("72.5", TemperatureUnit::Fahrenheit),
+            ("72.5F", TemperatureUnit::Celsius),
+            ("72.5°F", TemperatureUnit::Celsius),
+            ("72.5fahrenheit", TemperatureUnit::Celsius),
+            ("22.5C", TemperatureUnit::Fahrenheit),
+            ("22.5°c", TemperatureUnit::Fahrenheit),
+        ] {
+            assert_eq!(input(text).temperature(default_unit), setpoint, "{text}");
+        }
+        assert_eq!(
+            input("-4F").temperature(TemperatureUnit::Celsius),
+            Temperature::from_celsius(-20)
+        );
+        assert_eq!(
+            input("-20").temperature(TemperatureUnit::Celsius),
+            Temperature::from_celsius(-20)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_units() {
+        for text in ["22K", "22 C", "F", "°C", "22CC", "22°", ""] {
+            assert_eq!(
+                text.parse::<TemperatureInput>(),
+                Err(ParseUnitError),
+                "{text}"
+            );
+        }
+        assert_eq!("°F".parse(), Ok(TemperatureUnit::Fahrenheit));
+        assert_eq!("Celsius".parse(), Ok(TemperatureUnit::Celsius));
+        assert_eq!("kelvin".parse::<TemperatureUnit>(), Err(ParseUnitError));
+        assert_eq!(TemperatureUnit::Fahrenheit.to_string(), "°F");
+    }
+}
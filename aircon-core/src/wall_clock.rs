@@ -0,0 +1,546 @@
+//! Calendar time on top of uptime.
+//!
+//! The firmware only has `embassy_time::Instant`, which starts at zero on every boot.
+//! A [`WallClock`] remembers which Unix time some instant corresponded to, as learnt
+//! over SNTP, and turns later instants into local time using a [`TimeZone`]: a fixed
+//! standard offset plus an optional daylight saving rule.
+
+use core::fmt;
+use core::str::FromStr;
+
+use embassy_time::Instant;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+/// Offsets past ±14h don't exist anywhere
+const MAX_OFFSET_MINUTES: i16 = 14 * 60;
+
+/// Error returned when a UTC offset or DST rule can't be parsed
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ParseTimeZoneError;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    pub const ALL: [Weekday; 7] = [
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+        Weekday::Sunday,
+    ];
+
+    /// Days since Monday
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    fn from_days_since_epoch(days: u64) -> Self {
+        // 1970-01-01 was a Thursday
+        Weekday::ALL[((days + 3) % 7) as usize]
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: u16, month: u8, day: u8) -> u64 {
+    let year = year as i64 - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    (era * 146_097 + day_of_era - 719_468) as u64
+}
+
+/// Inverse of `days_from_civil`
+fn civil_from_days(days: u64) -> (u16, u8, u8) {
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year as u16, month as u8, day as u8)
+}
+
+/// A date and time of day, without any notion of which zone it is in
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub weekday: Weekday,
+}
+
+impl DateTime {
+    pub fn from_unix_secs(secs: u64) -> Self {
+        let days = secs / SECS_PER_DAY;
+        let time_of_day = secs % SECS_PER_DAY;
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (time_of_day / 3600) as u8,
+            minute: (time_of_day / 60 % 60) as u8,
+            second: (time_of_day % 60) as u8,
+            weekday: Weekday::from_days_since_epoch(days),
+        }
+    }
+
+    /// Minutes since midnight
+    pub fn minute_of_day(&self) -> u16 {
+        self.hour as u16 * 60 + self.minute as u16
+    }
+}
+
+/// Offset from UTC, whole minutes east of Greenwich
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UtcOffset(i16);
+
+impl UtcOffset {
+    pub const UTC: UtcOffset = UtcOffset(0);
+
+    pub fn from_minutes(minutes: i16) -> Option<Self> {
+        (-MAX_OFFSET_MINUTES..=MAX_OFFSET_MINUTES)
+            .contains(&minutes)
+            .then_some(UtcOffset(minutes))
+    }
+
+    pub fn minutes(&self) -> i16 {
+        self.0
+    }
+
+    fn secs(&self) -> i64 {
+        self.0 as i64 * 60
+    }
+}
+
+impl fmt::Display for UtcOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { '-' } else { '+' };
+        let minutes = self.0.unsigned_abs();
+        write!(f, "{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+    }
+}
+
+/// Parses `+2`, `-05:00`, `+0530`, `Z` or any of them prefixed with `UTC`, e.g. `UTC-5`
+impl FromStr for UtcOffset {
+    type Err = ParseTimeZoneError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = match s.get(..3) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("utc") => &s[3..],
+            _ => s,
+        };
+        if s.is_empty() || s.eq_ignore_ascii_case("z") {
+            return Ok(UtcOffset::UTC);
+        }
+        let (negative, rest) = match s.as_bytes().first() {
+            Some(b'+') => (false, &s[1..]),
+            Some(b'-') => (true, &s[1..]),
+            _ => (false, s),
+        };
+        let (hours, minutes) = match rest.split_once(':') {
+            Some((hours, minutes)) => (hours, minutes),
+            None if rest.len() == 4 => rest.split_at(2),
+            None => (rest, "0"),
+        };
+        let number = |digits: &str| {
+            if digits.is_empty() || digits.len() > 2 || !digits.bytes().all(|b| b.is_ascii_digit())
+            {
+                return Err(ParseTimeZoneError);
+            }
+            digits.parse::<i16>().map_err(|_| ParseTimeZoneError)
+        };
+        let (hours, minutes) = (number(hours)?, number(minutes)?);
+        if minutes >= 60 {
+            return Err(ParseTimeZoneError);
+        }
+        let total = hours * 60 + minutes;
+        UtcOffset::from_minutes(if negative { -total } else { total }).ok_or(ParseTimeZoneError)
+    }
+}
+
+/// When daylight saving time applies, it always moves clocks one hour forward
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DstRule {
+    #[default]
+    None,
+    /// Last Sunday of March to last Sunday of October, switching at 01:00 UTC
+    Eu,
+    /// Second Sunday of March to first Sunday of November, switching at 02:00 local time
+    Us,
+}
+
+impl DstRule {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DstRule::None => "none",
+            DstRule::Eu => "eu",
+            DstRule::Us => "us",
+        }
+    }
+
+    /// Whether daylight saving time is in effect at `unix_secs` in a zone with `standard` time
+    fn in_effect(&self, unix_secs: u64, standard: UtcOffset) -> bool {
+        let year = DateTime::from_unix_secs(unix_secs).year;
+        let (start, end) = match self {
+            DstRule::None => return false,
+            DstRule::Eu => (
+                last_sunday(year, 3) * SECS_PER_DAY + 3600,
+                last_sunday(year, 10) * SECS_PER_DAY + 3600,
+            ),
+            DstRule::Us => {
+                // 02:00 standard time in March, 02:00 daylight time in November
+                let start = nth_sunday(year, 3, 2) * SECS_PER_DAY + 2 * 3600;
+                let end = nth_sunday(year, 11, 1) * SECS_PER_DAY + 3600;
+                (
+                    start.saturating_add_signed(-standard.secs()),
+                    end.saturating_add_signed(-standard.secs()),
+                )
+            }
+        };
+        (start..end).contains(&unix_secs)
+    }
+}
+
+impl FromStr for DstRule {
+    type Err = ParseTimeZoneError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [DstRule::None, DstRule::Eu, DstRule::Us]
+            .into_iter()
+            .find(|rule| s.eq_ignore_ascii_case(rule.name()))
+            .ok_or(ParseTimeZoneError)
+    }
+}
+
+/// Day since the epoch of the `n`th Sunday of a month
+fn nth_sunday(year: u16, month: u8, n: u64) -> u64 {
+    let first = days_from_civil(year, month, 1);
+    let until_sunday =
+        (7 + Weekday::Sunday.index() - Weekday::from_days_since_epoch(first).index()) % 7;
+    first + until_sunday as u64 + (n - 1) * 7
+}
+
+fn last_sunday(year: u16, month: u8) -> u64 {
+    let next_month = match month {
+        12 => days_from_civil(year + 1, 1, 1),
+        month => days_from_civil(year, month + 1, 1),
+    };
+    let last = next_month - 1;
+    // Monday is 0, so this is how many days the last day is past a Sunday
+    last - ((Weekday::from_days_since_epoch(last).index() + 1) % 7) as u64
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimeZone {
+    /// Offset outside daylight saving time
+    pub standard: UtcOffset,
+    pub dst: DstRule,
+}
+
+impl TimeZone {
+    pub fn offset_at(&self, unix_secs: u64) -> UtcOffset {
+        if self.dst.in_effect(unix_secs, self.standard) {
+            // Not even daylight saving time goes past +14h
+            UtcOffset::from_minutes(self.standard.0 + 60).unwrap_or(UtcOffset(MAX_OFFSET_MINUTES))
+        } else {
+            self.standard
+        }
+    }
+}
+
+/// A moment in some zone, printed as RFC 3339, e.g. `2024-03-31T03:00:00+02:00`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LocalTime {
+    pub datetime: DateTime,
+    pub offset: UtcOffset,
+}
+
+impl LocalTime {
+    pub fn from_unix_secs(unix_secs: u64, zone: &TimeZone) -> Self {
+        let offset = zone.offset_at(unix_secs);
+        LocalTime {
+            datetime: DateTime::from_unix_secs(unix_secs.saturating_add_signed(offset.secs())),
+            offset,
+        }
+    }
+}
+
+impl fmt::Display for LocalTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = &self.datetime;
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            time.year, time.month, time.day, time.hour, time.minute, time.second
+        )?;
+        if self.offset == UtcOffset::UTC {
+            f.write_str("Z")
+        } else {
+            self.offset.fmt(f)
+        }
+    }
+}
+
+/// Uptime to calendar time, once it has been synchronised
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WallClock {
+    /// Instant of the last synchronisation and the Unix time in µs it corresponded to
+    synced: Option<(Instant, u64)>,
+    zone: TimeZone,
+}
+
+impl WallClock {
+    pub const fn new(zone: TimeZone) -> Self {
+        WallClock { synced: None, zone }
+    }
+
+    pub fn zone(&self) -> TimeZone {
+        self.zone
+    }
+
+    pub fn set_zone(&mut self, zone: TimeZone) {
+        self.zone = zone;
+    }
+
+    pub fn synchronise(&mut self, at: Instant, unix_micros: u64) {
+        self.synced = Some((at, unix_micros));
+    }
+
+    /// When the clock was last synchronised, `None` while it never was
+    pub fn last_sync(&self) -> Option<Instant> {
+        self.synced.map(|(at, _)| at)
+    }
+
+    pub fn unix_micros(&self, now: Instant) -> Option<u64> {
+        let (at, unix_micros) = self.synced?;
+        // Instants from before the sync point count backwards from it
+        Some(match now.checked_duration_since(at) {
+            Some(elapsed) => unix_micros + elapsed.as_micros(),
+            None => unix_micros.saturating_sub((at - now).as_micros()),
+        })
+    }
+
+    pub fn local_time(&self, now: Instant) -> Option<LocalTime> {
+        let unix_secs = self.unix_micros(now)? / 1_000_000;
+        Some(LocalTime::from_unix_secs(unix_secs, &self.zone))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use embassy_time::Duration;
+
+    const EU_2024: (u64, u64) = (1_711_846_800, 1_729_990_800);
+    const EU_2025: (u64, u64) = (1_743_296_400, 1_761_440_400);
+    /// In New York, UTC-5
+    const US_2024: (u64, u64) = (1_710_054_000, 1_730_613_600);
+    const US_2025: (u64, u64) = (1_741_503_600, 1_762_063_200);
+
+    fn offset(text: &str) -> UtcOffset {
+        text.parse().unwrap()
+    }
+
+    fn local(unix_secs: u64, zone: &TimeZone) -> std::string::String {
+        LocalTime::from_unix_secs(unix_secs, zone).to_string()
+    }
+
+    #[test]
+    fn converts_between_dates_and_days() {
+        for (date, days) in [
+            ((1970, 1, 1), 0),
+            ((2000, 2, 29), 11_016),
+            ((2000, 3, 1), 11_017),
+            ((2024, 2, 29), 19_782),
+            ((2100, 2, 28), 47_540),
+            // 2100 is no leap year
+            ((2100, 3, 1), 47_541),
+        ] {
+            assert_eq!(days_from_civil(date.0, date.1, date.2), days, "{date:?}");
+            assert_eq!(civil_from_days(days), date);
+        }
+        for days in (0..80_000).step_by(37) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn splits_unix_time() {
+        let time = DateTime::from_unix_secs(951_825_845);
+        assert_eq!(
+            time,
+            DateTime {
+                year: 2000,
+                month: 2,
+                day: 29,
+                hour: 12,
+                minute: 4,
+                second: 5,
+                weekday: Weekday::Tuesday,
+            }
+        );
+        assert_eq!(time.minute_of_day(), 724);
+        assert_eq!(DateTime::from_unix_secs(0).weekday, Weekday::Thursday);
+    }
+
+    #[test]
+    fn finds_sundays() {
+        assert_eq!(last_sunday(2024, 3), days_from_civil(2024, 3, 31));
+        assert_eq!(last_sunday(2024, 10), days_from_civil(2024, 10, 27));
+        assert_eq!(last_sunday(2024, 12), days_from_civil(2024, 12, 29));
+        assert_eq!(nth_sunday(2024, 3, 2), days_from_civil(2024, 3, 10));
+        assert_eq!(nth_sunday(2024, 11, 1), days_from_civil(2024, 11, 3));
+        // A month starting on a Sunday
+        assert_eq!(nth_sunday(2024, 9, 1), days_from_civil(2024, 9, 1));
+        assert_eq!(nth_sunday(2025, 3, 2), days_from_civil(2025, 3, 9));
+    }
+
+    #[test]
+    fn switches_eu_daylight_saving_time() {
+        let berlin = TimeZone {
+            standard: offset("+1"),
+            dst: DstRule::Eu,
+        };
+        for (start, end) in [EU_2024, EU_2025] {
+            assert_eq!(berlin.offset_at(start - 1), offset("+01:00"));
+            assert_eq!(berlin.offset_at(start), offset("+02:00"));
+            assert_eq!(berlin.offset_at(end - 1), offset("+02:00"));
+            assert_eq!(berlin.offset_at(end), offset("+01:00"));
+        }
+        assert_eq!(local(EU_2024.0 - 1, &berlin), "2024-03-31T01:59:59+01:00");
+        assert_eq!(local(EU_2024.0, &berlin), "2024-03-31T03:00:00+02:00");
+        // The same instant everywhere in the EU
+        let lisbon = TimeZone {
+            standard: UtcOffset::UTC,
+            dst: DstRule::Eu,
+        };
+        assert_eq!(local(EU_2024.0 - 1, &lisbon), "2024-03-31T00:59:59Z");
+        assert_eq!(local(EU_2024.0, &lisbon), "2024-03-31T02:00:00+01:00");
+    }
+
+    #[test]
+    fn switches_us_daylight_saving_time() {
+        let new_york = TimeZone {
+            standard: offset("UTC-5"),
+            dst: DstRule::Us,
+        };
+        for (start, end) in [US_2024, US_2025] {
+            assert_eq!(new_york.offset_at(start - 1), offset("-05:00"));
+            assert_eq!(new_york.offset_at(start), offset("-04:00"));
+            assert_eq!(new_york.offset_at(end - 1), offset("-04:00"));
+            assert_eq!(new_york.offset_at(end), offset("-05:00"));
+        }
+        assert_eq!(local(US_2024.0, &new_york), "2024-03-10T03:00:00-04:00");
+        assert_eq!(local(US_2024.1 - 1, &new_york), "2024-11-03T01:59:59-04:00");
+        assert_eq!(local(US_2024.1, &new_york), "2024-11-03T01:00:00-05:00");
+
+        // Local time, so an hour later in UTC further west
+        let chicago = TimeZone {
+            standard: offset("-6"),
+            dst: DstRule::Us,
+        };
+        assert_eq!(chicago.offset_at(US_2024.0), offset("-6"));
+        assert_eq!(chicago.offset_at(US_2024.0 + 3600), offset("-5"));
+    }
+
+    #[test]
+    fn daylight_saving_time_stays_within_14_hours() {
+        let kiribati = TimeZone {
+            standard: offset("+14"),
+            dst: DstRule::Eu,
+        };
+        assert_eq!(kiribati.offset_at(EU_2024.0).minutes(), MAX_OFFSET_MINUTES);
+        let no_dst = TimeZone {
+            standard: offset("+14"),
+            dst: DstRule::None,
+        };
+        assert_eq!(no_dst.offset_at(EU_2024.0).minutes(), MAX_OFFSET_MINUTES);
+    }
+
+    #[test]
+    fn parses_utc_offsets() {
+        for (text, minutes) in [
+            ("Z", 0),
+            ("utc", 0),
+            ("UTC+0", 0),
+            ("UTC-5", -300),
+            ("+2", 120),
+            ("-05:00", -300),
+            ("+0530", 330),
+            ("UTC+05:45", 345),
+            ("-1400", -840),
+            ("14", 840),
+        ] {
+            assert_eq!(offset(text).minutes(), minutes, "{text}");
+        }
+        for text in [
+            "+05:60", "+0575", "+15", "-14:01", "+123", "5:3:0", "++5", "UTC+", "+:30", "EST",
+        ] {
+            assert_eq!(text.parse::<UtcOffset>(), Err(ParseTimeZoneError), "{text}");
+        }
+        assert_eq!(offset("UTC-5").to_string(), "-05:00");
+        assert_eq!(offset("+0530").to_string(), "+05:30");
+        assert_eq!("US".parse(), Ok(DstRule::Us));
+        assert_eq!("summer".parse::<DstRule>(), Err(ParseTimeZoneError));
+    }
+
+    #[test]
+    fn counts_from_the_last_sync() {
+        let mut clock = WallClock::new(TimeZone::default());
+        assert_eq!(clock.local_time(Instant::from_secs(5)), None);
+
+        let synced_at = Instant::from_secs(100);
+        clock.synchronise(synced_at, EU_2024.0 * 1_000_000);
+        assert_eq!(clock.last_sync(), Some(synced_at));
+        assert_eq!(
+            clock.unix_micros(synced_at + Duration::from_millis(1500)),
+            Some(EU_2024.0 * 1_000_000 + 1_500_000)
+        );
+        assert_eq!(
+            clock.unix_micros(Instant::from_secs(40)),
+            Some((EU_2024.0 - 60) * 1_000_000)
+        );
+
+        clock.set_zone(TimeZone {
+            standard: offset("+1"),
+            dst: DstRule::Eu,
+        });
+        let local = clock.local_time(synced_at).unwrap();
+        assert_eq!(local.to_string(), "2024-03-31T03:00:00+02:00");
+    }
+}
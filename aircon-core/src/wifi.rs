@@ -0,0 +1,286 @@
+//! Wi-Fi connection state, as tracked by the firmware's supervisor task, and the saved
+//! networks it joins.
+
+use core::fmt;
+
+use heapless::{String, Vec};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LinkState {
+    #[default]
+    Disconnected,
+    /// Associating with the access point
+    Joining,
+    /// Associated, waiting for a DHCP lease
+    Dhcp,
+    Up,
+    /// No network could be joined, running the setup access point instead
+    Setup,
+}
+
+impl LinkState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LinkState::Disconnected => "disconnected",
+            LinkState::Joining => "joining",
+            LinkState::Dhcp => "dhcp",
+            LinkState::Up => "up",
+            LinkState::Setup => "setup",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WifiStatus {
+    pub state: LinkState,
+    /// Signal strength, only known while associated
+    pub rssi_dbm: Option<i32>,
+    /// Failed join or DHCP attempts since the link was last up
+    pub retries: u32,
+}
+
+pub const MAX_SSID_LENGTH: usize = 32;
+pub const MAX_PASSPHRASE_LENGTH: usize = 63;
+/// WPA2 needs at least this many characters, anything shorter is a typo
+pub const MIN_PASSPHRASE_LENGTH: usize = 8;
+/// Networks that can be saved at once
+pub const MAX_NETWORKS: usize = 4;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CredentialsError {
+    SsidLength,
+    PassphraseLength,
+    /// Every slot is taken, forget a network first
+    TooManyNetworks,
+}
+
+impl fmt::Display for CredentialsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredentialsError::SsidLength => {
+                write!(f, "SSID must be 1 to {} bytes", MAX_SSID_LENGTH)
+            }
+            CredentialsError::PassphraseLength => write!(
+                f,
+                "passphrase must be {} to {} characters",
+                MIN_PASSPHRASE_LENGTH, MAX_PASSPHRASE_LENGTH
+            ),
+            CredentialsError::TooManyNetworks => {
+                write!(f, "at most {} networks can be saved", MAX_NETWORKS)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WifiCredentials {
+    pub ssid: String<MAX_SSID_LENGTH>,
+    /// WPA2 passphrase, `None` for an open network
+    pub passphrase: Option<String<MAX_PASSPHRASE_LENGTH>>,
+}
+
+impl WifiCredentials {
+    /// An empty passphrase, like none at all, means an open network
+    pub fn new(ssid: &str, passphrase: Option<&str>) -> Result<Self, CredentialsError> {
+        if ssid.is_empty() {
+            return Err(CredentialsError::SsidLength);
+        }
+        let ssid = String::try_from(ssid).map_err(|_| CredentialsError::SsidLength)?;
+        let passphrase = match passphrase.filter(|passphrase| !passphrase.is_empty()) {
+            Some(passphrase) if passphrase.len() < MIN_PASSPHRASE_LENGTH => {
+                return Err(CredentialsError::PassphraseLength)
+            }
+            Some(passphrase) => {
+                Some(String::try_from(passphrase).map_err(|_| CredentialsError::PassphraseLength)?)
+            }
+            None => None,
+        };
+        Ok(WifiCredentials { ssid, passphrase })
+    }
+}
+
+/// Saved networks, tried in order when joining
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WifiNetworks {
+    networks: Vec<WifiCredentials, MAX_NETWORKS>,
+}
+
+impl WifiNetworks {
+    pub const fn new() -> Self {
+        WifiNetworks {
+            networks: Vec::new(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &WifiCredentials> {
+        self.networks.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    /// Updates the passphrase of a saved network, or adds it with the lowest priority
+    pub fn set(&mut self, credentials: WifiCredentials) -> Result<(), CredentialsError> {
+        match self
+            .networks
+            .iter_mut()
+            .find(|network| network.ssid == credentials.ssid)
+        {
+            Some(network) => {
+                *network = credentials;
+                Ok(())
+            }
+            None => self
+                .networks
+                .push(credentials)
+                .map_err(|_| CredentialsError::TooManyNetworks),
+        }
+    }
+
+    /// Removes a saved network, returns whether it was saved
+    pub fn forget(&mut self, ssid: &str) -> bool {
+        match self
+            .networks
+            .iter()
+            .position(|network| network.ssid == ssid)
+        {
+            Some(index) => {
+                self.networks.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(ssid: &str, passphrase: Option<&str>) -> WifiCredentials {
+        WifiCredentials::new(ssid, passphrase).unwrap()
+    }
+
+    fn ssids(networks: &WifiNetworks) -> std::vec::Vec<&str> {
+        networks
+            .iter()
+            .map(|network| network.ssid.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn checks_credential_lengths() {
+        let longest_ssid = "s".repeat(MAX_SSID_LENGTH);
+        assert!(WifiCredentials::new(&longest_ssid, None).is_ok());
+        assert_eq!(
+            WifiCredentials::new(&(longest_ssid + "s"), None),
+            Err(CredentialsError::SsidLength)
+        );
+        assert_eq!(
+            WifiCredentials::new("", Some("password")),
+            Err(CredentialsError::SsidLength)
+        );
+
+        let shortest = "p".repeat(MIN_PASSPHRASE_LENGTH);
+        let longest = "p".repeat(MAX_PASSPHRASE_LENGTH);
+        assert!(WifiCredentials::new("Home", Some(&shortest)).is_ok());
+        assert!(WifiCredentials::new("Home", Some(&longest)).is_ok());
+        assert_eq!(
+            WifiCredentials::new("Home", Some(&shortest[1..])),
+            Err(CredentialsError::PassphraseLength)
+        );
+        assert_eq!(
+            WifiCredentials::new("Home", Some(&(longest + "p"))),
+            Err(CredentialsError::PassphraseLength)
+        );
+    }
+
+    #[test]
+    fn open_networks_have_no_passphrase() {
+        let open = network("Cafe", None);
+        assert_eq!(open.passphrase, None);
+        assert_eq!(network("Cafe", Some("")), open);
+        assert_eq!(
+            WifiCredentials::new("Cafe", Some(" ")),
+            Err(CredentialsError::PassphraseLength)
+        );
+
+        let mut networks = WifiNetworks::new();
+        networks.set(open.clone()).unwrap();
+        assert_eq!(networks.iter().next(), Some(&open));
+    }
+
+    #[test]
+    fn keeps_networks_in_priority_order() {
+        let mut networks = WifiNetworks::new();
+        assert!(networks.is_empty());
+        for ssid in ["Home", "Office", "Phone"] {
+            networks.set(network(ssid, Some("password"))).unwrap();
+        }
+        assert!(!networks.is_empty());
+        assert_eq!(ssids(&networks), ["Home", "Office", "Phone"]);
+    }
+
+    #[test]
+    fn replaces_a_saved_network_in_place() {
+        let mut networks = WifiNetworks::new();
+        networks.set(network("Home", Some("old password"))).unwrap();
+        networks.set(network("Office", Some("password"))).unwrap();
+
+        networks.set(network("Home", Some("new password"))).unwrap();
+        assert_eq!(ssids(&networks), ["Home", "Office"]);
+        assert_eq!(
+            networks.iter().next().unwrap().passphrase.as_deref(),
+            Some("new password")
+        );
+
+        // The router was switched to an open network
+        networks.set(network("Office", None)).unwrap();
+        assert_eq!(networks.iter().nth(1).unwrap().passphrase, None);
+        // SSIDs are case sensitive
+        networks.set(network("home", None)).unwrap();
+        assert_eq!(ssids(&networks), ["Home", "Office", "home"]);
+    }
+
+    #[test]
+    fn forgets_a_network() {
+        let mut networks = WifiNetworks::new();
+        for ssid in ["Home", "Office", "Phone"] {
+            networks.set(network(ssid, None)).unwrap();
+        }
+        assert!(networks.forget("Office"));
+        assert_eq!(ssids(&networks), ["Home", "Phone"]);
+        assert!(!networks.forget("Office"));
+        assert!(!networks.forget("Nowhere"));
+        assert!(networks.forget("Home"));
+        assert!(networks.forget("Phone"));
+        assert!(networks.is_empty());
+    }
+
+    #[test]
+    fn refuses_new_networks_once_full() {
+        let mut networks = WifiNetworks::new();
+        for index in 0..MAX_NETWORKS {
+            networks
+                .set(network(&std::format!("Net {index}"), None))
+                .unwrap();
+        }
+        assert_eq!(
+            networks.set(network("One more", None)),
+            Err(CredentialsError::TooManyNetworks)
+        );
+        assert_eq!(networks.iter().count(), MAX_NETWORKS);
+
+        // Saved ones can still be changed
+        networks.set(network("Net 0", Some("password"))).unwrap();
+        assert!(networks.forget("Net 1"));
+        networks.set(network("One more", None)).unwrap();
+        assert_eq!(ssids(&networks), ["Net 0", "Net 2", "Net 3", "One more"]);
+    }
+}
@@ -0,0 +1,118 @@
+//! Saved Wi-Fi networks in two sectors of NOR flash.
+//!
+//! Credentials change rarely and a record doesn't fit in a settings slot, so they
+//! live in a [`SectorStore`] of their own with the magic `AWIF`.
+//!
+//! The payload is a network count followed by, for every network in priority
+//! order, the SSID length and bytes and the passphrase length and bytes, where a
+//! passphrase length of 0 means an open network.
+
+use crate::config_store::RecordError;
+use crate::sector_store::{RecordCodec, SectorStore, RECORD_OVERHEAD};
+use crate::wifi::{
+    WifiCredentials, WifiNetworks, MAX_NETWORKS, MAX_PASSPHRASE_LENGTH, MAX_SSID_LENGTH,
+};
+
+/// Largest possible record, header, every network at full length and CRC
+pub const MAX_RECORD_SIZE: usize =
+    RECORD_OVERHEAD + 1 + MAX_NETWORKS * (2 + MAX_SSID_LENGTH + MAX_PASSPHRASE_LENGTH);
+
+/// Records are read and written through a buffer rounded up from `MAX_RECORD_SIZE`
+const BUFFER_SIZE: usize = 512;
+
+/// Saved networks in the two erase sectors starting at `start`
+pub type WifiStore<F> = SectorStore<F, WifiRecord, BUFFER_SIZE>;
+
+pub struct WifiRecord;
+
+fn text(bytes: &[u8]) -> Result<&str, RecordError> {
+    core::str::from_utf8(bytes).map_err(|_| RecordError::InvalidField)
+}
+
+impl RecordCodec for WifiRecord {
+    type Value = WifiNetworks;
+
+    const MAGIC: [u8; 4] = *b"AWIF";
+    const VERSION: u16 = 1;
+
+    fn encode(networks: &WifiNetworks, out: &mut [u8]) -> usize {
+        let mut length = 0;
+        let mut push = |bytes: &[u8]| {
+            out[length..length + bytes.len()].copy_from_slice(bytes);
+            length += bytes.len();
+        };
+        push(&[networks.iter().count() as u8]);
+        for network in networks.iter() {
+            push(&[network.ssid.len() as u8]);
+            push(network.ssid.as_bytes());
+            let passphrase = network.passphrase.as_deref().unwrap_or("");
+            push(&[passphrase.len() as u8]);
+            push(passphrase.as_bytes());
+        }
+        length
+    }
+
+    fn decode(mut payload: &[u8]) -> Result<WifiNetworks, RecordError> {
+        let mut take = |length: usize| -> Result<&[u8], RecordError> {
+            if length > payload.len() {
+                return Err(RecordError::InvalidField);
+            }
+            let (taken, rest) = payload.split_at(length);
+            payload = rest;
+            Ok(taken)
+        };
+
+        let mut networks = WifiNetworks::default();
+        let count = take(1)?[0];
+        for _ in 0..count {
+            let ssid_length = take(1)?[0] as usize;
+            let ssid = text(take(ssid_length)?)?;
+            let passphrase_length = take(1)?[0] as usize;
+            let passphrase = match text(take(passphrase_length)?)? {
+                "" => None,
+                passphrase => Some(passphrase),
+            };
+            let credentials =
+                WifiCredentials::new(ssid, passphrase).map_err(|_| RecordError::InvalidField)?;
+            networks
+                .set(credentials)
+                .map_err(|_| RecordError::InvalidField)?;
+        }
+        Ok(networks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mock_flash::MockFlash;
+
+    #[test]
+    fn networks_round_trip() {
+        let mut networks = WifiNetworks::default();
+        networks
+            .set(WifiCredentials::new("home", Some("correct horse battery")).unwrap())
+            .unwrap();
+        networks
+            .set(WifiCredentials::new("café guest", None).unwrap())
+            .unwrap();
+        let long_ssid = "s".repeat(MAX_SSID_LENGTH);
+        let long_passphrase = "p".repeat(MAX_PASSPHRASE_LENGTH);
+        networks
+            .set(WifiCredentials::new(&long_ssid, Some(&long_passphrase)).unwrap())
+            .unwrap();
+
+        let mut store = WifiStore::new(MockFlash::new(2), 0);
+        store.save(&networks).unwrap();
+        assert_eq!(store.load(), Ok(Some(networks)));
+    }
+
+    #[test]
+    fn rejects_a_payload_cut_short() {
+        assert_eq!(
+            WifiRecord::decode(&[1, 4, b'h', b'o']),
+            Err(RecordError::InvalidField)
+        );
+    }
+}
@@ -9,9 +9,35 @@
 //! new memory settings.
 
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+/// Gzips the dashboard page into `out`, stamped with the crate version so the
+/// page served by a unit always matches its firmware.
+fn bundle_dashboard(out: &Path) {
+    let page = fs::read_to_string("web/index.html")
+        .unwrap()
+        .replace("{{VERSION}}", env!("CARGO_PKG_VERSION"));
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(page.as_bytes()).unwrap();
+    let compressed = encoder.finish().unwrap();
+    File::create(out.join("index.html.gz"))
+        .unwrap()
+        .write_all(&compressed)
+        .unwrap();
+
+    // FNV-1a of the compressed page, browsers revalidate against it as the ETag
+    let hash = compressed.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    println!("cargo:rustc-env=DASHBOARD_ETAG={:016x}", hash);
+    println!("cargo:rerun-if-changed=web/index.html");
+}
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
@@ -29,6 +55,8 @@ fn main() {
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
 
+    bundle_dashboard(out);
+
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
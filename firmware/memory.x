@@ -1,6 +1,7 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    /* The last 32K are reserved for the schedule, settings and Wi-Fi networks, see SCHEDULE_SECTORS, SETTINGS_SECTORS and WIFI_SECTORS in src/settings.rs */
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 32K
 
     /* Pick one of the two options for RAM layout     */
 
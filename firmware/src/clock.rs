@@ -0,0 +1,168 @@
+//! Wall clock time, kept in sync with an SNTP server once the network is up.
+//!
+//! The server is `pool.ntp.org` unless `SNTP_SERVER` names another host at build time.
+//! The time zone is a user setting, see `settings::set_time_zone`. Until the first sync
+//! nothing has a date and log timestamps count from boot, as if it was 1970-01-01.
+
+use core::cell::Cell;
+
+use aircon_core::backoff::Backoff;
+use aircon_core::sntp::{self, NtpTimestamp, SntpError};
+use aircon_core::wall_clock::{DstRule, LocalTime, TimeZone, UtcOffset, WallClock};
+use cyw43::NetDriver;
+use defmt::{info, unwrap, warn};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpEndpoint, Stack};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+
+const SERVER: &str = match option_env!("SNTP_SERVER") {
+    Some(server) => server,
+    None => "pool.ntp.org",
+};
+/// Even a cheap crystal stays within a second a day, hourly syncs are plenty
+const SYNC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+static WALL_CLOCK: BlockingMutex<CriticalSectionRawMutex, Cell<WallClock>> =
+    BlockingMutex::new(Cell::new(WallClock::new(TimeZone {
+        standard: UtcOffset::UTC,
+        dst: DstRule::None,
+    })));
+
+pub fn wall_clock() -> WallClock {
+    WALL_CLOCK.lock(|clock| clock.get())
+}
+
+fn update_clock(update: impl FnOnce(&mut WallClock)) {
+    WALL_CLOCK.lock(|clock| {
+        let mut updated = clock.get();
+        update(&mut updated);
+        clock.set(updated);
+    });
+}
+
+/// Current local time, `None` until the clock was synchronised
+pub fn local_time() -> Option<LocalTime> {
+    wall_clock().local_time(Instant::now())
+}
+
+pub fn time_zone() -> TimeZone {
+    wall_clock().zone()
+}
+
+/// Only applies the zone, `settings::set_time_zone` also saves it
+pub fn apply_time_zone(zone: TimeZone) {
+    update_clock(|clock| clock.set_zone(zone));
+}
+
+// Logs carry UTC once synced, uptime from the epoch before that
+defmt::timestamp!("{=u64:iso8601ms}", {
+    let now = Instant::now();
+    match wall_clock().unix_micros(now) {
+        Some(micros) => micros / 1000,
+        None => now.as_millis(),
+    }
+});
+
+#[derive(Debug, defmt::Format)]
+enum SyncError {
+    Dns,
+    Socket,
+    Timeout,
+    Reply(SntpError),
+}
+
+async fn sync_once(
+    stack: &'static Stack<NetDriver<'static>>,
+    socket: &mut UdpSocket<'_>,
+) -> Result<(), SyncError> {
+    let addresses = stack
+        .dns_query(SERVER, DnsQueryType::A)
+        .await
+        .map_err(|_| SyncError::Dns)?;
+    let server = IpEndpoint::new(*addresses.first().ok_or(SyncError::Dns)?, sntp::NTP_PORT);
+
+    let sent_at = Instant::now();
+    // Only echoed back by the server, uptime keeps it unique
+    let transmit = NtpTimestamp::from_unix_micros(sent_at.as_micros());
+    socket
+        .send_to(&sntp::encode_request(transmit), server)
+        .await
+        .map_err(|_| SyncError::Socket)?;
+
+    let mut buf = [0; sntp::PACKET_SIZE];
+    let receive = async {
+        loop {
+            let (length, from) = socket
+                .recv_from(&mut buf)
+                .await
+                .map_err(|_| SyncError::Socket)?;
+            // Late replies to an earlier attempt or strays from elsewhere are skipped
+            if from != server {
+                continue;
+            }
+            match sntp::decode_reply(&buf[..length], transmit) {
+                Err(SntpError::OriginMismatch) => continue,
+                reply => return reply.map_err(SyncError::Reply),
+            }
+        }
+    };
+    let reply = with_timeout(REPLY_TIMEOUT, receive)
+        .await
+        .map_err(|_| SyncError::Timeout)??;
+
+    let arrived_at = Instant::now();
+    let unix_micros = reply.unix_micros_at_arrival(arrived_at - sent_at);
+    update_clock(|clock| clock.synchronise(arrived_at, unix_micros));
+    Ok(())
+}
+
+#[embassy_executor::task]
+pub async fn sntp_task(stack: &'static Stack<NetDriver<'static>>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 128];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 128];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    // Any free local port, the reply comes back to it
+    unwrap!(socket.bind(0));
+
+    let mut backoff = Backoff::new(Duration::from_secs(2), Duration::from_secs(300));
+    loop {
+        while !stack.is_config_up() {
+            Timer::after_millis(100).await;
+        }
+
+        let delay = match sync_once(stack, &mut socket).await {
+            Ok(()) => {
+                backoff.reset();
+                info!("Clock synchronised with {}", SERVER);
+                SYNC_INTERVAL
+            }
+            Err(SyncError::Reply(SntpError::KissOfDeath(code))) => {
+                warn!("{} refused SNTP: {=[u8]:a}", SERVER, &code[..]);
+                // Asked to slow down, don't bother it again before the next regular sync
+                SYNC_INTERVAL
+            }
+            Err(err) => {
+                let delay = backoff.next_delay();
+                warn!(
+                    "SNTP sync failed: {}, retrying in {}s",
+                    err,
+                    delay.as_secs()
+                );
+                delay
+            }
+        };
+        Timer::after(delay).await;
+    }
+}
@@ -0,0 +1,475 @@
+//! Command dispatch shared by the UART and TCP command lines, both parse input into
+//! `aircon_core::cli::BaseCommand` and hand it to [`CommandContext::execute`].
+
+use core::fmt::{self, Write};
+
+use aircon_core::cli::{BaseCommand, ScheduleCommand, WifiCommand};
+use aircon_core::schedule::Block;
+use aircon_core::temp_controller::{
+    user_duration, ConfigError, ControllerMode, ControllerState, DutyLimit, Failsafe,
+    TempControllerConfig, DEFAULT_BOOST, DEFAULT_DUTY_WINDOW, DEFAULT_FAILSAFE_PERIOD,
+};
+use aircon_core::wall_clock::TimeZone;
+use aircon_core::wifi::WifiCredentials;
+use cyw43::NetDriver;
+use embassy_net::Stack;
+use embassy_time::{Duration, Instant};
+
+use crate::clock::{local_time, time_zone, wall_clock};
+use crate::schedule::is_overridden;
+use crate::settings::{
+    display_unit, schedule, set_display_unit, set_time_zone, update_schedule, update_wifi_networks,
+    wifi_networks,
+};
+use crate::wifi::{fallback_network, wifi_status};
+use crate::{
+    controller_mode, ReadingReceiver, StatusReceiver, CONTROLLER_CURRENT_STATUS,
+    CONTROLLER_SET_MODE, CONTROLLER_UPDATE_CONFIG, DHT11_WATCH,
+};
+
+/// Whether any of the seconds given is too long, checked before they are turned into
+/// durations since `Duration::from_secs` overflows on huge values
+fn too_long(secs: &[Option<u64>]) -> bool {
+    secs.iter()
+        .flatten()
+        .any(|secs| user_duration(*secs).is_none())
+}
+
+fn write_mode(out: &mut impl Write, mode: ControllerMode) -> fmt::Result {
+    write!(out, "Mode: {}", mode.name())?;
+    if let ControllerMode::Boost { .. } = mode {
+        write!(
+            out,
+            " - Remaining: {}s",
+            mode.time_remaining(Instant::now()).as_secs()
+        )?;
+    }
+    Ok(())
+}
+
+fn write_failsafe(out: &mut impl Write, config: &TempControllerConfig) -> fmt::Result {
+    write!(
+        out,
+        "Sensor Timeout: {}s\nFailsafe: ",
+        config.sensor_timeout.as_secs()
+    )?;
+    match config.failsafe {
+        Failsafe::Off => write!(out, "off"),
+        Failsafe::DutyCycle { percent, period } => {
+            write!(out, "{}% of {}s", percent, period.as_secs())
+        }
+    }
+}
+
+fn write_limits(out: &mut impl Write, config: &TempControllerConfig) -> fmt::Result {
+    match config.maximum_runtime {
+        Some(maximum) => write!(out, "Max Runtime: {}s", maximum.as_secs())?,
+        None => write!(out, "Max Runtime: none")?,
+    }
+    match config.duty_limit {
+        Some(limit) => write!(
+            out,
+            "\nDuty Limit: {}% of {}s",
+            limit.percent,
+            limit.window.as_secs()
+        )?,
+        None => write!(out, "\nDuty Limit: none")?,
+    }
+    write!(out, "\nForced Rest: {}s", config.forced_rest.as_secs())
+}
+
+fn security(network: &WifiCredentials) -> &'static str {
+    match network.passphrase {
+        Some(_) => "wpa2",
+        None => "open",
+    }
+}
+
+/// What a command line needs to answer commands, one per transport
+pub struct CommandContext {
+    network_stack: &'static Stack<NetDriver<'static>>,
+    dht11_receiver: ReadingReceiver,
+    status_receiver: StatusReceiver,
+}
+
+impl CommandContext {
+    pub fn new(network_stack: &'static Stack<NetDriver<'static>>) -> Self {
+        CommandContext {
+            network_stack,
+            dht11_receiver: DHT11_WATCH.receiver().unwrap(),
+            status_receiver: CONTROLLER_CURRENT_STATUS.receiver().unwrap(),
+        }
+    }
+
+    /// Runs `command` and writes its response to `out`
+    pub fn execute(&mut self, command: BaseCommand<'_>, out: &mut impl Write) -> fmt::Result {
+        let unit = display_unit();
+        match command {
+            BaseCommand::Temp => match self.dht11_receiver.try_get() {
+                Some(reading) => write!(
+                    out,
+                    "Temp: {}{}\nHumidity: {}%",
+                    reading.temperature.in_unit(unit),
+                    unit,
+                    reading.humidity,
+                ),
+                None => write!(out, "No reading yet"),
+            },
+            BaseCommand::Addr => match self.network_stack.config_v4().map(|x| x.address) {
+                Some(addr) => write!(out, "{}", addr),
+                None => write!(out, "No Address Assigned"),
+            },
+            BaseCommand::Status => {
+                match local_time() {
+                    Some(time) => writeln!(out, "Time: {}", time)?,
+                    None => writeln!(out, "Time: not synchronised")?,
+                }
+                write_mode(out, controller_mode())?;
+                writeln!(out)?;
+                let Some((state, config)) = self.status_receiver.try_get() else {
+                    return write!(out, "Status: Starting");
+                };
+                let now = Instant::now();
+                let time_remaining = state.time_remaining(&config, now);
+                match state {
+                    ControllerState::Idle => write!(out, "Status: Idle"),
+                    ControllerState::Running { .. } if time_remaining.as_ticks() > 0 => write!(
+                        out,
+                        "Status: Running - Remaining: {}s",
+                        time_remaining.as_secs()
+                    ),
+                    ControllerState::Running { .. } => write!(
+                        out,
+                        "Status: Running - Until below {}{}",
+                        config.lower_temperature().in_unit(unit),
+                        unit
+                    ),
+                    ControllerState::Cooldown { .. } => write!(
+                        out,
+                        "Status: Cooldown - Remaining: {}s",
+                        time_remaining.as_secs()
+                    ),
+                    ControllerState::Rest { limit, .. } => write!(
+                        out,
+                        "Status: Rest - {} limit, Remaining: {}s",
+                        limit.name(),
+                        time_remaining.as_secs()
+                    ),
+                    ControllerState::Fault { relay, .. } => {
+                        let relay = if relay { "on" } else { "off" };
+                        match self.dht11_receiver.try_get() {
+                            Some(reading) => write!(
+                                out,
+                                "Status: Fault - No sensor reading for {}s, relay {}",
+                                now.checked_duration_since(reading.taken_at)
+                                    .unwrap_or_default()
+                                    .as_secs(),
+                                relay
+                            ),
+                            None => {
+                                write!(
+                                    out,
+                                    "Status: Fault - No sensor reading yet, relay {}",
+                                    relay
+                                )
+                            }
+                        }
+                    }
+                }
+            }
+            BaseCommand::GetConfig => {
+                let Some((_, config)) = self.status_receiver.try_get() else {
+                    return write!(out, "Controller not started yet");
+                };
+                write!(
+                    out,
+                    "Threshold Temp: {}{}\nHysteresis: {}{}\nMin Runtime: {}s\nCooldown Time: {}s",
+                    config.threshold_temperature.in_unit(unit),
+                    unit,
+                    config.hysteresis.difference_in_unit(unit),
+                    unit,
+                    config.minimum_runtime.as_secs(),
+                    config.cooldown_time.as_secs(),
+                )?;
+                writeln!(out)?;
+                write_failsafe(out, &config)?;
+                writeln!(out)?;
+                write_limits(out, &config)
+            }
+            BaseCommand::SetConfig {
+                set_temp,
+                hysteresis,
+                min_runtime_secs,
+                min_cooldown_secs,
+            } => {
+                let Some((_, config)) = self.status_receiver.try_get() else {
+                    return write!(out, "Controller not started yet");
+                };
+                if too_long(&[min_runtime_secs, min_cooldown_secs]) {
+                    return write!(out, "error: {}", ConfigError::Duration);
+                }
+                let new_config = TempControllerConfig {
+                    // The setpoint as shown keeps the exact one in effect, see `apply_manual`
+                    threshold_temperature: set_temp
+                        .map(|input| input.temperature(unit))
+                        .filter(|setpoint| {
+                            !setpoint.same_in_unit(config.threshold_temperature, unit)
+                        })
+                        .unwrap_or(config.threshold_temperature),
+                    hysteresis: hysteresis
+                        .map(|input| input.difference(unit))
+                        .unwrap_or(config.hysteresis),
+                    minimum_runtime: min_runtime_secs
+                        .map(Duration::from_secs)
+                        .unwrap_or(config.minimum_runtime),
+                    cooldown_time: min_cooldown_secs
+                        .map(Duration::from_secs)
+                        .unwrap_or(config.cooldown_time),
+                    ..config
+                };
+                if let Err(err) = new_config.validate() {
+                    return write!(out, "error: {}", err);
+                }
+                CONTROLLER_UPDATE_CONFIG.signal(new_config);
+                Ok(())
+            }
+            BaseCommand::Units { unit: new_unit } => {
+                if let Some(new_unit) = new_unit {
+                    set_display_unit(new_unit);
+                }
+                write!(out, "Units: {}", display_unit())
+            }
+            BaseCommand::Failsafe {
+                timeout_secs,
+                duty_percent,
+                period_secs,
+            } => {
+                let Some((_, config)) = self.status_receiver.try_get() else {
+                    return write!(out, "Controller not started yet");
+                };
+                if too_long(&[timeout_secs, period_secs]) {
+                    return write!(out, "error: {}", ConfigError::Duration);
+                }
+                let current_period = match config.failsafe {
+                    Failsafe::Off => DEFAULT_FAILSAFE_PERIOD,
+                    Failsafe::DutyCycle { period, .. } => period,
+                };
+                let failsafe = match (duty_percent, config.failsafe) {
+                    (Some(0), _) => Failsafe::Off,
+                    (Some(percent), _) | (None, Failsafe::DutyCycle { percent, .. }) => {
+                        Failsafe::DutyCycle {
+                            percent,
+                            period: period_secs
+                                .map(Duration::from_secs)
+                                .unwrap_or(current_period),
+                        }
+                    }
+                    (None, Failsafe::Off) => Failsafe::Off,
+                };
+                let new_config = TempControllerConfig {
+                    sensor_timeout: timeout_secs
+                        .map(Duration::from_secs)
+                        .unwrap_or(config.sensor_timeout),
+                    failsafe,
+                    ..config
+                };
+                if let Err(err) = new_config.validate() {
+                    return write!(out, "error: {}", err);
+                }
+                if new_config != config {
+                    CONTROLLER_UPDATE_CONFIG.signal(new_config);
+                }
+                write_failsafe(out, &new_config)
+            }
+            BaseCommand::Limits {
+                max_runtime_secs,
+                duty_percent,
+                window_secs,
+                rest_secs,
+            } => {
+                let Some((_, config)) = self.status_receiver.try_get() else {
+                    return write!(out, "Controller not started yet");
+                };
+                if too_long(&[max_runtime_secs, window_secs, rest_secs]) {
+                    return write!(out, "error: {}", ConfigError::Duration);
+                }
+                let maximum_runtime = match max_runtime_secs {
+                    Some(0) => None,
+                    Some(secs) => Some(Duration::from_secs(secs)),
+                    None => config.maximum_runtime,
+                };
+                let current_window = config
+                    .duty_limit
+                    .map(|limit| limit.window)
+                    .unwrap_or(DEFAULT_DUTY_WINDOW);
+                let duty_limit = match (duty_percent, config.duty_limit) {
+                    (Some(0), _) => None,
+                    (Some(percent), _) | (None, Some(DutyLimit { percent, .. })) => {
+                        Some(DutyLimit {
+                            percent,
+                            window: window_secs
+                                .map(Duration::from_secs)
+                                .unwrap_or(current_window),
+                        })
+                    }
+                    (None, None) => None,
+                };
+                let new_config = TempControllerConfig {
+                    maximum_runtime,
+                    duty_limit,
+                    forced_rest: rest_secs
+                        .map(Duration::from_secs)
+                        .unwrap_or(config.forced_rest),
+                    ..config
+                };
+                if let Err(err) = new_config.validate() {
+                    return write!(out, "error: {}", err);
+                }
+                if new_config != config {
+                    CONTROLLER_UPDATE_CONFIG.signal(new_config);
+                }
+                write_limits(out, &new_config)
+            }
+            BaseCommand::Mode { mode, minutes } => {
+                let Some(mode) = mode else {
+                    return write_mode(out, controller_mode());
+                };
+                let boost = match minutes {
+                    Some(minutes) => match user_duration(minutes.saturating_mul(60)) {
+                        Some(boost) => boost,
+                        None => return write!(out, "error: {}", ConfigError::Duration),
+                    },
+                    None => DEFAULT_BOOST,
+                };
+                let mode = mode.mode(Instant::now(), boost);
+                CONTROLLER_SET_MODE.signal(mode);
+                // The controller applies it on its next pass
+                write_mode(out, mode)
+            }
+            BaseCommand::Time => {
+                let clock = wall_clock();
+                let now = Instant::now();
+                match (clock.local_time(now), clock.last_sync()) {
+                    (Some(time), Some(synced)) => write!(
+                        out,
+                        "Time: {}\nSynchronised: {}s ago",
+                        time,
+                        (now - synced).as_secs()
+                    ),
+                    _ => write!(out, "Time: not synchronised"),
+                }
+            }
+            BaseCommand::Timezone { offset, dst } => {
+                let current = time_zone();
+                if offset.is_some() || dst.is_some() {
+                    set_time_zone(TimeZone {
+                        standard: offset.unwrap_or(current.standard),
+                        dst: dst.unwrap_or(current.dst),
+                    });
+                }
+                let zone = time_zone();
+                write!(
+                    out,
+                    "Timezone: UTC{} DST: {}",
+                    zone.standard,
+                    zone.dst.name()
+                )
+            }
+            BaseCommand::Wifi(WifiCommand::Set { ssid, passphrase }) => {
+                let saved = WifiCredentials::new(ssid, passphrase)
+                    .and_then(|network| update_wifi_networks(|networks| networks.set(network)));
+                match saved {
+                    Ok(()) => write!(out, "Saved {}", ssid),
+                    Err(err) => write!(out, "error: {}", err),
+                }
+            }
+            BaseCommand::Wifi(WifiCommand::Show) => {
+                let status = wifi_status();
+                write!(out, "Wifi: {}", status.state.name())?;
+                if let Some(rssi) = status.rssi_dbm {
+                    write!(out, "\nRSSI: {}dBm", rssi)?;
+                }
+                write!(out, "\nRetries: {}", status.retries)?;
+                for (priority, network) in wifi_networks().iter().enumerate() {
+                    write!(
+                        out,
+                        "\n{}. {} ({})",
+                        priority + 1,
+                        network.ssid,
+                        security(network)
+                    )?;
+                }
+                if let Some(network) = fallback_network() {
+                    write!(out, "\nBuilt in: {} ({})", network.ssid, security(&network))?;
+                }
+                Ok(())
+            }
+            BaseCommand::Schedule(ScheduleCommand::Show) => {
+                let schedule = schedule();
+                if schedule.is_empty() {
+                    return write!(out, "Nothing scheduled");
+                }
+                let active = local_time().and_then(|time| schedule.active_at(&time.datetime));
+                for (number, block) in schedule.iter().enumerate() {
+                    if number > 0 {
+                        writeln!(out)?;
+                    }
+                    write!(
+                        out,
+                        "{}. {} {} {}",
+                        number + 1,
+                        block.days,
+                        block.start,
+                        block.action.display(unit)
+                    )?;
+                    if active.is_some_and(|active| active.block == *block) {
+                        let note = if is_overridden() { "overridden" } else { "now" };
+                        write!(out, " ({})", note)?;
+                    }
+                }
+                Ok(())
+            }
+            BaseCommand::Schedule(ScheduleCommand::Add {
+                days,
+                start,
+                setpoint,
+            }) => {
+                let block = Block {
+                    days,
+                    start,
+                    action: setpoint.action(unit),
+                };
+                match update_schedule(|schedule| schedule.add(block)) {
+                    Ok(()) => write!(
+                        out,
+                        "Scheduled {} {} {}",
+                        days,
+                        start,
+                        block.action.display(unit)
+                    ),
+                    Err(err) => write!(out, "error: {}", err),
+                }
+            }
+            BaseCommand::Schedule(ScheduleCommand::Remove { number }) => {
+                let removed = number
+                    .checked_sub(1)
+                    .and_then(|index| update_schedule(|schedule| schedule.remove(index)));
+                match removed {
+                    Some(block) => write!(out, "Removed {} {}", block.days, block.start),
+                    None => write!(out, "No block {}", number),
+                }
+            }
+            BaseCommand::Schedule(ScheduleCommand::Clear) => {
+                update_schedule(|schedule| schedule.clear());
+                write!(out, "Schedule cleared")
+            }
+            BaseCommand::Wifi(WifiCommand::Forget { ssid }) => {
+                if update_wifi_networks(|networks| networks.forget(ssid)) {
+                    write!(out, "Forgot {}", ssid)
+                } else {
+                    write!(out, "{} is not saved", ssid)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use aircon_core::dht11::{decode_frame, Dht11Error, Reading, SensorModel, FRAME_LENGTH};
+use defmt::{info, warn};
+use embassy_rp::{
+    peripherals::PIO1,
+    pio::{Config, Pio, PioPin, ShiftDirection, StateMachine},
+};
+use embassy_time::{with_timeout, Duration, Instant};
+use fixed::traits::ToFixed;
+
+/// A complete transaction (start signal plus 40 data bits) takes around 25ms,
+/// anything much longer means the sensor is not answering.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// PIO driver for the DHT11 and the DHT22/AM2302, which share the same one-wire timing
+pub struct DHT11 {
+    state_machine: StateMachine<'static, PIO1, 0>,
+    config: Config<'static, PIO1>,
+    model: SensorModel,
+}
+
+impl DHT11 {
+    pub fn new<T: PioPin>(pio: Pio<'static, PIO1>, pin: T, model: SensorModel) -> Self {
+        let prg = pio_proc::pio_file!("src/dht11.pio");
+
+        let Pio {
+            mut common, sm0, ..
+        } = pio;
+
+        let mut cfg = Config::default();
+        cfg.use_program(&common.load_program(&prg.program), &[]);
+        let mut data_pin = common.make_pio_pin(pin);
+        data_pin.set_pull(embassy_rp::gpio::Pull::Up);
+        cfg.set_set_pins(&[&data_pin]);
+        cfg.set_in_pins(&[&data_pin]);
+        cfg.set_jmp_pin(&data_pin);
+        // x&y are set to 31 in the pio program, this helps initialize a loop of 1024*100 cycles which needs to add up to ~20ms according to whoever wrote it.
+        // Cursory examination indicated a system clock frequency of 125MHz
+        // 102400 cycles / 20ms = 5.12 MHz -> 125MHz/5.12MHz = 24.414, thus a clock divider of near to 24.414
+        // cfg.clock_divider = 82.to_fixed();
+        cfg.clock_divider = 125.to_fixed();
+        cfg.shift_in.auto_fill = true;
+        cfg.shift_in.threshold = 8;
+        cfg.shift_in.direction = ShiftDirection::Left;
+
+        DHT11 {
+            state_machine: sm0,
+            config: cfg,
+            model,
+        }
+    }
+
+    pub fn model(&self) -> SensorModel {
+        self.model
+    }
+
+    pub async fn get_temperature_humidity(&mut self) -> Result<Reading, Dht11Error> {
+        self.state_machine.set_config(&self.config);
+        self.state_machine.set_enable(true);
+
+        let mut dht11_data_buf: [u8; FRAME_LENGTH] = [0; FRAME_LENGTH];
+        let read = with_timeout(READ_TIMEOUT, async {
+            for item in &mut dht11_data_buf {
+                *item = self.state_machine.rx().wait_pull().await as u8;
+            }
+        })
+        .await;
+
+        if read.is_err() {
+            warn!("DHT11 did not respond, is the sensor connected?");
+            self.reset();
+            return Err(Dht11Error::Timeout);
+        }
+        self.state_machine.restart();
+
+        match decode_frame(self.model, &dht11_data_buf, Instant::now()) {
+            Ok(reading) => {
+                info!(
+                    "Temperature {}°C, Humidity: {}%",
+                    reading.temperature, reading.humidity
+                );
+                Ok(reading)
+            }
+            Err(err) => {
+                warn!("Invalid DHT11 frame {}: {}", dht11_data_buf, err);
+                Err(err)
+            }
+        }
+    }
+
+    /// Stops the state machine and throws away any partial frame so the next read starts clean
+    fn reset(&mut self) {
+        self.state_machine.set_enable(false);
+        while self.state_machine.rx().try_pull().is_some() {}
+        self.state_machine.restart();
+    }
+}
@@ -0,0 +1,323 @@
+//! HTTP server for the dashboard and JSON API, see `aircon_core::api` for the routes.
+//! While the setup access point is up it serves `aircon_core::setup` instead.
+
+use core::cell::Cell;
+
+use aircon_core::api::{self, Route, CONTENT_TYPE_JSON};
+use aircon_core::http::{self, ParseError, Request, Status};
+use aircon_core::metrics::{self, Metrics, CONTENT_TYPE_METRICS};
+use aircon_core::setup::{self, SetupRoute, CONTENT_TYPE_HTML};
+use cyw43::NetDriver;
+use defmt::{info, warn};
+use embassy_net::tcp::{Error as TcpError, TcpSocket};
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_time::{Duration, Instant};
+use embedded_io_async::Write;
+use heapless::String;
+
+use crate::clock::local_time;
+use crate::metrics::{compressor_stats, sensor_errors};
+use crate::provisioning;
+use crate::settings::display_unit;
+use crate::wifi::wifi_status;
+use crate::{
+    ReadingReceiver, SharedControl, StatusReceiver, CONTROLLER_CURRENT_STATUS,
+    CONTROLLER_UPDATE_CONFIG, DHT11_WATCH,
+};
+
+pub const HTTP_PORT: u16 = 80;
+
+/// Dashboard page, gzipped by `build.rs` from `web/index.html`
+const DASHBOARD: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/index.html.gz"));
+const DASHBOARD_ETAG: &str = concat!("\"", env!("DASHBOARD_ETAG"), "\"");
+
+/// Connections served at the same time, each listener task owns its socket and buffers.
+/// Every listener also takes a receiver on both watches and a socket from the stack,
+/// see `main` for how those are sized.
+pub const LISTENERS: usize = 3;
+
+/// Listeners currently serving a connection, the LED stays on while any of them is
+static ACTIVE_CONNECTIONS: BlockingMutex<CriticalSectionRawMutex, Cell<usize>> =
+    BlockingMutex::new(Cell::new(0));
+
+async fn set_connection_active(control: &SharedControl, active: bool) {
+    let active_connections = ACTIVE_CONNECTIONS.lock(|count| {
+        let updated = if active {
+            count.get() + 1
+        } else {
+            count.get().saturating_sub(1)
+        };
+        count.set(updated);
+        updated
+    });
+    control
+        .lock()
+        .await
+        .gpio_set(0, active_connections > 0)
+        .await;
+}
+
+/// Accepts one connection at a time, answers a single request and closes it
+#[embassy_executor::task(pool_size = LISTENERS)]
+pub async fn listener_task(
+    id: usize,
+    stack: &'static Stack<NetDriver<'static>>,
+    control: &'static SharedControl,
+) -> ! {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut request_buffer = [0; 1024];
+
+    let mut dht11_receiver = DHT11_WATCH.receiver().unwrap();
+    let mut status_receiver = CONTROLLER_CURRENT_STATUS.receiver().unwrap();
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+
+        info!("[{}] Listening on TCP:{}...", id, HTTP_PORT);
+        if let Err(e) = socket.accept(HTTP_PORT).await {
+            warn!("[{}] accept error: {:?}", id, e);
+            continue;
+        }
+
+        info!(
+            "[{}] Received connection from {:?}",
+            id,
+            socket.remote_endpoint()
+        );
+        set_connection_active(control, true).await;
+
+        if let Err(e) = handle_connection(
+            &mut socket,
+            &mut request_buffer,
+            &mut dht11_receiver,
+            &mut status_receiver,
+        )
+        .await
+        {
+            warn!("[{}] HTTP connection error: {:?}", id, e);
+        }
+        socket.close();
+        let _ = socket.flush().await;
+        set_connection_active(control, false).await;
+    }
+}
+
+async fn handle_connection(
+    socket: &mut TcpSocket<'_>,
+    buf: &mut [u8],
+    dht11_receiver: &mut ReadingReceiver,
+    status_receiver: &mut StatusReceiver,
+) -> Result<(), TcpError> {
+    let mut length = 0;
+    let received = loop {
+        if length == buf.len() {
+            break Err(Status::PayloadTooLarge);
+        }
+        let n = socket.read(&mut buf[length..]).await?;
+        if n == 0 {
+            warn!("read EOF");
+            return Ok(());
+        }
+        length += n;
+        match http::parse_request(&buf[..length], buf.len()) {
+            Ok(_) => break Ok(()),
+            Err(ParseError::Incomplete) => continue,
+            Err(ParseError::BadRequest) => break Err(Status::BadRequest),
+            Err(ParseError::TooLarge) => break Err(Status::PayloadTooLarge),
+        }
+    };
+
+    let request = received.and_then(|_| {
+        http::parse_request(&buf[..length], buf.len()).map_err(|_| Status::BadRequest)
+    });
+    if provisioning::is_active() {
+        return send_setup(socket, request, status_receiver).await;
+    }
+    if let Ok(request) = &request {
+        match api::route(request) {
+            Route::Dashboard => return send_dashboard(socket, request).await,
+            Route::Metrics => return send_metrics(socket, dht11_receiver, status_receiver).await,
+            _ => {}
+        }
+    }
+
+    let mut body = String::<512>::new();
+    let status = match request {
+        Ok(request) => respond(&request, &mut body, dht11_receiver, status_receiver),
+        Err(status) => status,
+    };
+    if status != Status::Ok {
+        body.clear();
+        let _ = api::render_error(&mut body, status);
+    }
+
+    let mut head = String::<160>::new();
+    let _ = http::write_head(&mut head, status, CONTENT_TYPE_JSON, body.len(), &[]);
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body.as_bytes()).await?;
+    socket.flush().await
+}
+
+async fn send_dashboard(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<(), TcpError> {
+    let (status, body) = if request.if_none_match == Some(DASHBOARD_ETAG) {
+        (Status::NotModified, &[][..])
+    } else {
+        (Status::Ok, DASHBOARD)
+    };
+
+    let mut head = String::<256>::new();
+    let _ = http::write_head(
+        &mut head,
+        status,
+        "text/html; charset=utf-8",
+        body.len(),
+        &[
+            ("Content-Encoding", "gzip"),
+            ("ETag", DASHBOARD_ETAG),
+            ("Cache-Control", "no-cache"),
+        ],
+    );
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body).await?;
+    socket.flush().await
+}
+
+async fn send_metrics(
+    socket: &mut TcpSocket<'_>,
+    dht11_receiver: &mut ReadingReceiver,
+    status_receiver: &mut StatusReceiver,
+) -> Result<(), TcpError> {
+    let metrics = Metrics {
+        reading: dht11_receiver.try_get(),
+        status: status_receiver.try_get(),
+        sensor_errors: sensor_errors(),
+        compressor: compressor_stats(),
+        wifi: wifi_status(),
+        uptime: Duration::from_ticks(Instant::now().as_ticks()),
+    };
+    let mut body = String::<3072>::new();
+    let status = match metrics::render_metrics(&mut body, &metrics) {
+        Ok(()) => Status::Ok,
+        Err(_) => {
+            warn!("Metrics did not fit in the response buffer");
+            body.clear();
+            Status::ServiceUnavailable
+        }
+    };
+
+    let mut head = String::<160>::new();
+    let _ = http::write_head(&mut head, status, CONTENT_TYPE_METRICS, body.len(), &[]);
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body.as_bytes()).await?;
+    socket.flush().await
+}
+
+async fn send_setup(
+    socket: &mut TcpSocket<'_>,
+    request: Result<Request<'_>, Status>,
+    status_receiver: &mut StatusReceiver,
+) -> Result<(), TcpError> {
+    let unit = display_unit();
+    let config = status_receiver.try_get().map(|(_, config)| config);
+    let setpoint = config.unwrap_or_default().threshold_temperature;
+    let networks = provisioning::scanned_networks();
+
+    let mut body = String::<2048>::new();
+    let status = match request {
+        Ok(request) => match setup::route(&request) {
+            SetupRoute::Page => {
+                let _ = setup::render_setup_page(&mut body, &networks, setpoint, unit, None);
+                Status::Ok
+            }
+            SetupRoute::Submit => match setup::parse_setup_form(request.body, unit) {
+                Ok(form) => {
+                    info!("Setup saved for {}", form.credentials.ssid.as_str());
+                    let _ = setup::render_setup_done(&mut body, &form.credentials.ssid);
+                    provisioning::submit(form, config);
+                    Status::Ok
+                }
+                Err(err) => {
+                    warn!("Rejected setup form: {}", err);
+                    let _ =
+                        setup::render_setup_page(&mut body, &networks, setpoint, unit, Some(&err));
+                    Status::BadRequest
+                }
+            },
+            SetupRoute::MethodNotAllowed => Status::MethodNotAllowed,
+        },
+        Err(status) => status,
+    };
+    if body.is_empty() {
+        let _ = body.push_str(status.reason());
+    }
+
+    let mut head = String::<160>::new();
+    let _ = http::write_head(&mut head, status, CONTENT_TYPE_HTML, body.len(), &[]);
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body.as_bytes()).await?;
+    socket.flush().await
+}
+
+fn respond(
+    request: &Request<'_>,
+    body: &mut String<512>,
+    dht11_receiver: &mut ReadingReceiver,
+    status_receiver: &mut StatusReceiver,
+) -> Status {
+    let unit = display_unit();
+    match api::route(request) {
+        Route::Sensor => match dht11_receiver.try_get() {
+            Some(reading) => {
+                let _ = api::render_sensor(body, &reading, unit, local_time().as_ref());
+                Status::Ok
+            }
+            None => Status::ServiceUnavailable,
+        },
+        Route::Status => match status_receiver.try_get() {
+            Some((state, config)) => {
+                let _ = api::render_status(
+                    body,
+                    &state,
+                    &config,
+                    Instant::now(),
+                    local_time().as_ref(),
+                );
+                Status::Ok
+            }
+            None => Status::ServiceUnavailable,
+        },
+        Route::GetConfig => match status_receiver.try_get() {
+            Some((_, config)) => {
+                let _ = api::render_config(body, &config, unit);
+                Status::Ok
+            }
+            None => Status::ServiceUnavailable,
+        },
+        Route::Wifi => {
+            let _ = api::render_wifi(body, &wifi_status());
+            Status::Ok
+        }
+        Route::PutConfig => match status_receiver.try_get() {
+            Some((_, config)) => match api::apply_config_update(request.body, config, unit) {
+                Ok(new_config) => {
+                    CONTROLLER_UPDATE_CONFIG.signal(new_config);
+                    let _ = api::render_config(body, &new_config, unit);
+                    Status::Ok
+                }
+                Err(err) => {
+                    warn!("Rejected config update: {}", err);
+                    Status::BadRequest
+                }
+            },
+            None => Status::ServiceUnavailable,
+        },
+        // Served by `send_dashboard` and `send_metrics` before getting here
+        Route::Dashboard | Route::Metrics => Status::NotFound,
+        Route::MethodNotAllowed => Status::MethodNotAllowed,
+        Route::NotFound => Status::NotFound,
+    }
+}
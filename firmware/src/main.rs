@@ -0,0 +1,273 @@
+//! This example uses the RP Pico W board Wifi chip (cyw43).
+//! Connects to specified Wifi network, serves an HTTP API on port 80, a command line on
+//! port 1234 and publishes to an MQTT broker.
+
+#![no_std]
+#![no_main]
+#![allow(async_fn_in_trait)]
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_sync::watch::{Receiver, Watch};
+
+use cyw43_pio::PioSpi;
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_net::{Config as IPConfig, Stack, StackResources};
+use embassy_rp::clocks::clk_sys_freq;
+use embassy_rp::gpio::{Level, Output, Pin};
+use embassy_rp::peripherals::{DMA_CH0, PIO0, PIO1, UART0};
+use embassy_rp::pio::{InterruptHandler as PIOInterruptHandler, Pio};
+use embassy_rp::{
+    bind_interrupts,
+    uart::{self, InterruptHandler as UARTInterruptHandler},
+};
+use embassy_time::{Instant, Timer};
+
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+use aircon_core::dht11::{Reading, SensorModel};
+use aircon_core::temp_controller::{
+    Clock, ControllerMode, ControllerState, RuntimeLimit, TempController, TempControllerConfig,
+};
+
+mod clock;
+mod dht11;
+use dht11::DHT11;
+mod commands;
+mod http_server;
+mod metrics;
+mod mqtt_client;
+mod provisioning;
+mod schedule;
+mod settings;
+use settings::{settings_task, SAVE_CONTROLLER_CONFIG};
+mod tcp_cli;
+mod uart_cli;
+use uart_cli::uart_cli;
+mod wifi;
+
+bind_interrupts!(struct PIOIrqs {
+    PIO0_IRQ_0 => PIOInterruptHandler<PIO0>;
+    PIO1_IRQ_0 => PIOInterruptHandler<PIO1>;
+});
+
+bind_interrupts!(struct UARTIrqs {
+    UART0_IRQ  => UARTInterruptHandler<UART0>;
+});
+
+/// Sensor attached to the DHT data pin, switch to `SensorModel::Dht22` for an AM2302
+const SENSOR_MODEL: SensorModel = SensorModel::Dht11;
+
+/// Receivers of `DHT11_WATCH`: controller, UART and TCP CLI, MQTT and one per HTTP listener
+const DHT11_RECEIVERS: usize = 4 + http_server::LISTENERS;
+/// Receivers of `CONTROLLER_CURRENT_STATUS`: UART and TCP CLI, MQTT and one per HTTP listener
+const STATUS_RECEIVERS: usize = 3 + http_server::LISTENERS;
+/// Sockets: DHCP client, DHCP and DNS servers for setup, DNS, SNTP, MQTT, TCP CLI and one
+/// per HTTP listener
+const SOCKETS: usize = 7 + http_server::LISTENERS;
+
+static DHT11_WATCH: Watch<CriticalSectionRawMutex, Reading, DHT11_RECEIVERS> = Watch::new();
+
+static CONTROLLER_UPDATE_CONFIG: Signal<CriticalSectionRawMutex, TempControllerConfig> =
+    Signal::new();
+static CONTROLLER_SET_MODE: Signal<CriticalSectionRawMutex, ControllerMode> = Signal::new();
+/// Raised when the compressor is rested for running into a limit, published over MQTT
+static CONTROLLER_WARNING: Signal<CriticalSectionRawMutex, RuntimeLimit> = Signal::new();
+/// Mode the controller is in, a boost falls back to auto on its own
+static CONTROLLER_MODE: BlockingMutex<CriticalSectionRawMutex, Cell<ControllerMode>> =
+    BlockingMutex::new(Cell::new(ControllerMode::Auto));
+
+fn controller_mode() -> ControllerMode {
+    CONTROLLER_MODE.lock(|mode| mode.get())
+}
+static CONTROLLER_CURRENT_STATUS: Watch<
+    CriticalSectionRawMutex,
+    (ControllerState, TempControllerConfig),
+    STATUS_RECEIVERS,
+> = Watch::new();
+
+type ReadingReceiver = Receiver<'static, CriticalSectionRawMutex, Reading, DHT11_RECEIVERS>;
+type StatusReceiver = Receiver<
+    'static,
+    CriticalSectionRawMutex,
+    (ControllerState, TempControllerConfig),
+    STATUS_RECEIVERS,
+>;
+
+/// The cyw43 control handle, shared between everything that drives the Wi-Fi chip or its LED
+type SharedControl = Mutex<CriticalSectionRawMutex, cyw43::Control<'static>>;
+
+/// Clock backed by the embassy time driver
+#[derive(Debug, Clone, Copy, Default)]
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[embassy_executor::task]
+async fn wifi_task(
+    runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>,
+) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn net_task(stack: &'static Stack<cyw43::NetDriver<'static>>) -> ! {
+    stack.run().await
+}
+
+#[embassy_executor::task]
+async fn temp_monitor_task(mut dht11_ctl: DHT11) {
+    let dht11_monitor = DHT11_WATCH.sender();
+    let sampling_interval = dht11_ctl.model().min_sampling_interval();
+
+    // Since I think the first few readings are garbage, let's just throw them away
+    let _ = dht11_ctl.get_temperature_humidity().await;
+    Timer::after(sampling_interval).await;
+    let _ = dht11_ctl.get_temperature_humidity().await;
+
+    loop {
+        Timer::after(sampling_interval).await;
+        // Corrupted frames are dropped, consumers keep the last valid reading
+        match dht11_ctl.get_temperature_humidity().await {
+            Ok(reading) => dht11_monitor.send(reading),
+            Err(err) => metrics::record_sensor_error(&err),
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn temp_controller(relay_pin: impl Pin, config: TempControllerConfig) {
+    let mut dht11_controller_reciever = DHT11_WATCH.receiver().unwrap();
+    let controller_status = CONTROLLER_CURRENT_STATUS.sender();
+
+    let mut schedule = schedule::ScheduleRunner::new(&config);
+    let mut controller =
+        TempController::new(config, Output::new(relay_pin, Level::Low), SystemClock);
+
+    loop {
+        // Readings carry their time, the controller faults once the last one is too old
+        let reading = dht11_controller_reciever.try_get();
+        schedule.poll(&mut controller);
+        if let Some(mode) = CONTROLLER_SET_MODE.try_take() {
+            info!("Controller mode: {}", mode);
+            controller.set_mode(mode);
+        }
+        controller.update(reading);
+        if let Some(limit) = controller.take_warning() {
+            CONTROLLER_WARNING.signal(limit);
+        }
+        CONTROLLER_MODE.lock(|current| current.set(controller.get_mode()));
+
+        controller_status.send((controller.get_state(), controller.get_config()));
+        metrics::set_compressor_stats(controller.compressor_stats());
+
+        if let Some(new_config) = CONTROLLER_UPDATE_CONFIG.try_take() {
+            let saved = schedule.apply_manual(&mut controller, new_config);
+            SAVE_CONTROLLER_CONFIG.signal(saved);
+        }
+        Timer::after_secs(1).await;
+    }
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    info!("Hello World! {}", clk_sys_freq());
+
+    let p = embassy_rp::init(Default::default());
+
+    let (stores, settings) = settings::load_settings(p.FLASH);
+    let controller_config = settings.controller;
+    unwrap!(spawner.spawn(settings_task(stores, settings)));
+
+    // Regulation doesn't depend on the network, start it before anything Wi-Fi related
+    unwrap!(spawner.spawn(temp_controller(p.PIN_13, controller_config)));
+
+    let pio1 = Pio::new(p.PIO1, PIOIrqs);
+    let dht11_ctl = DHT11::new(pio1, p.PIN_15, SENSOR_MODEL);
+    unwrap!(spawner.spawn(temp_monitor_task(dht11_ctl)));
+    info!("DHT11 initialized");
+
+    let config = uart::Config::default();
+    let uart = uart::Uart::new(
+        p.UART0, p.PIN_0, p.PIN_1, UARTIrqs, p.DMA_CH1, p.DMA_CH2, config,
+    );
+
+    // let fw = include_bytes!("../cyw43-firmware/43439A0.bin");
+    // let clm = include_bytes!("../cyw43-firmware/43439A0_clm.bin");
+
+    // To make flashing faster for development, you may want to flash the firmwares independently
+    // at hardcoded addresses, instead of baking them into the program with `include_bytes!`:
+    //     probe-rs download 43439A0.bin --format bin --chip RP2040 --base-address 0x10100000
+    //     probe-rs download 43439A0_clm.bin --format bin --chip RP2040 --base-address 0x10140000
+    let fw = unsafe { core::slice::from_raw_parts(0x10100000 as *const u8, 230321) };
+    let clm = unsafe { core::slice::from_raw_parts(0x10140000 as *const u8, 4752) };
+
+    let pwr = Output::new(p.PIN_23, Level::Low);
+    let cs = Output::new(p.PIN_25, Level::High);
+
+    let mut pio0 = Pio::new(p.PIO0, PIOIrqs);
+    let spi = PioSpi::new(
+        &mut pio0.common,
+        pio0.sm0,
+        pio0.irq0,
+        cs,
+        p.PIN_24,
+        p.PIN_29,
+        p.DMA_CH0,
+    );
+
+    static STATE: StaticCell<cyw43::State> = StaticCell::new();
+    let state = STATE.init(cyw43::State::new());
+    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, fw).await;
+    unwrap!(spawner.spawn(wifi_task(runner)));
+
+    control.init(clm).await;
+    control
+        .set_power_management(cyw43::PowerManagementMode::PowerSave)
+        .await;
+
+    let config = IPConfig::dhcpv4(Default::default());
+    //let config = embassy_net::Config::ipv4_static(embassy_net::StaticConfigV4 {
+    //    address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+    //    dns_servers: Vec::new(),
+    //    gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+    //});
+
+    // Generate random seed
+    let seed = 0x0123_4567_89ab_cdef; // chosen by fair dice roll. guarenteed to be random.
+
+    // Init network stack
+    static STACK: StaticCell<Stack<cyw43::NetDriver<'static>>> = StaticCell::new();
+    static RESOURCES: StaticCell<StackResources<SOCKETS>> = StaticCell::new();
+    let stack = &*STACK.init(Stack::new(
+        net_device,
+        config,
+        RESOURCES.init(StackResources::<SOCKETS>::new()),
+        seed,
+    ));
+
+    unwrap!(spawner.spawn(uart_cli(uart, stack)));
+
+    unwrap!(spawner.spawn(net_task(stack)));
+
+    static CONTROL: StaticCell<SharedControl> = StaticCell::new();
+    let control = &*CONTROL.init(Mutex::new(control));
+    unwrap!(spawner.spawn(wifi::wifi_supervisor(stack, control)));
+
+    // These only wait for connections, they don't need the link to be up yet
+    unwrap!(spawner.spawn(clock::sntp_task(stack)));
+    unwrap!(spawner.spawn(mqtt_client::mqtt_task(stack)));
+    unwrap!(spawner.spawn(tcp_cli::tcp_cli_task(stack)));
+    for id in 0..http_server::LISTENERS {
+        unwrap!(spawner.spawn(http_server::listener_task(id, stack, control)));
+    }
+}
@@ -0,0 +1,45 @@
+//! Counters only `/metrics` needs, updated by the tasks that observe them.
+
+use core::cell::Cell;
+
+use aircon_core::dht11::Dht11Error;
+use aircon_core::metrics::SensorErrorCounts;
+use aircon_core::temp_controller::CompressorStats;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_time::Duration;
+
+static SENSOR_ERRORS: BlockingMutex<CriticalSectionRawMutex, Cell<SensorErrorCounts>> =
+    BlockingMutex::new(Cell::new(SensorErrorCounts {
+        checksum: 0,
+        out_of_range: 0,
+        timeout: 0,
+    }));
+
+static COMPRESSOR_STATS: BlockingMutex<CriticalSectionRawMutex, Cell<CompressorStats>> =
+    BlockingMutex::new(Cell::new(CompressorStats {
+        starts: 0,
+        runtime: Duration::from_ticks(0),
+        maximum_runtime_rests: 0,
+        duty_cycle_rests: 0,
+    }));
+
+pub fn record_sensor_error(err: &Dht11Error) {
+    SENSOR_ERRORS.lock(|counts| {
+        let mut updated = counts.get();
+        updated.record(err);
+        counts.set(updated);
+    });
+}
+
+pub fn sensor_errors() -> SensorErrorCounts {
+    SENSOR_ERRORS.lock(|counts| counts.get())
+}
+
+pub fn set_compressor_stats(stats: CompressorStats) {
+    COMPRESSOR_STATS.lock(|current| current.set(stats));
+}
+
+pub fn compressor_stats() -> CompressorStats {
+    COMPRESSOR_STATS.lock(|stats| stats.get())
+}
@@ -0,0 +1,495 @@
+//! MQTT 3.1.1 client publishing readings and controller status to a broker.
+//!
+//! Broker and topics are set at build time through environment variables:
+//!
+//! | variable            | default       |
+//! |---------------------|---------------|
+//! | `MQTT_BROKER`       | `192.168.1.2` |
+//! | `MQTT_CLIENT_ID`    | `aircon`      |
+//! | `MQTT_TOPIC_PREFIX` | `aircon`      |
+//! | `MQTT_USERNAME`     | none          |
+//! | `MQTT_PASSWORD`     | none          |
+//!
+//! Under the prefix the device publishes `sensor`, `status`, `wifi`, `config` (retained),
+//! `availability` (retained, `online`/`offline` through the Last Will) and a `warning` event
+//! whenever the compressor is rested for running into a runtime limit, and listens on
+//! `set` for the same JSON body `PUT /api/config` takes.
+//!
+//! Home Assistant discovery payloads are published under `homeassistant/` (override with
+//! `MQTT_DISCOVERY_PREFIX`), keyed by the flash unique ID.
+
+use core::fmt::Write as _;
+use core::net::Ipv4Addr;
+
+use aircon_core::api;
+use aircon_core::backoff::Backoff;
+use aircon_core::dht11::Reading;
+use aircon_core::home_assistant::{self, Component, Device};
+use aircon_core::json::JsonError;
+use aircon_core::mqtt::{self, Connect, DecodeError, EncodeError, Packet, Will};
+use aircon_core::temp_controller::TempControllerConfig;
+use aircon_core::units::TemperatureUnit;
+use cyw43::NetDriver;
+use defmt::{error, info, warn, Format};
+use embassy_futures::select::{select4, Either4};
+use embassy_net::tcp::{ConnectError, Error as TcpError, TcpSocket};
+use embassy_net::{Ipv4Address, Stack};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use embedded_io_async::Write;
+use heapless::String;
+
+use crate::clock::local_time;
+use crate::settings::{display_unit, flash_unique_id};
+use crate::wifi::wifi_status;
+use crate::{
+    ReadingReceiver, StatusReceiver, CONTROLLER_CURRENT_STATUS, CONTROLLER_UPDATE_CONFIG,
+    CONTROLLER_WARNING, DHT11_WATCH,
+};
+
+const BROKER_ADDRESS: &str = match option_env!("MQTT_BROKER") {
+    Some(address) => address,
+    None => "192.168.1.2",
+};
+const BROKER_PORT: u16 = 1883;
+const CLIENT_ID: &str = match option_env!("MQTT_CLIENT_ID") {
+    Some(client_id) => client_id,
+    None => "aircon",
+};
+const TOPIC_PREFIX: &str = match option_env!("MQTT_TOPIC_PREFIX") {
+    Some(prefix) => prefix,
+    None => "aircon",
+};
+const DISCOVERY_PREFIX: &str = match option_env!("MQTT_DISCOVERY_PREFIX") {
+    Some(prefix) => prefix,
+    None => home_assistant::DISCOVERY_PREFIX,
+};
+const USERNAME: Option<&str> = option_env!("MQTT_USERNAME");
+const PASSWORD: Option<&str> = option_env!("MQTT_PASSWORD");
+
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// Everything is republished this often even without changes, which also keeps the connection alive
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+const CONNACK_TIMEOUT: Duration = Duration::from_secs(10);
+const SET_PACKET_ID: u16 = 1;
+
+type Topic = String<64>;
+
+#[derive(Debug, Format)]
+enum SessionError {
+    Connect(ConnectError),
+    Tcp(TcpError),
+    Encode(EncodeError),
+    Decode(DecodeError),
+    /// CONNACK or SUBACK refused with this return code
+    Refused(u8),
+    Timeout,
+    Closed,
+}
+
+impl From<ConnectError> for SessionError {
+    fn from(err: ConnectError) -> Self {
+        SessionError::Connect(err)
+    }
+}
+
+impl From<TcpError> for SessionError {
+    fn from(err: TcpError) -> Self {
+        SessionError::Tcp(err)
+    }
+}
+
+impl From<EncodeError> for SessionError {
+    fn from(err: EncodeError) -> Self {
+        SessionError::Encode(err)
+    }
+}
+
+impl From<DecodeError> for SessionError {
+    fn from(err: DecodeError) -> Self {
+        SessionError::Decode(err)
+    }
+}
+
+struct Topics {
+    sensor: Topic,
+    status: Topic,
+    config: Topic,
+    wifi: Topic,
+    availability: Topic,
+    warning: Topic,
+    set: Topic,
+}
+
+impl Topics {
+    fn new(prefix: &str) -> Self {
+        let topic = |suffix: &str| {
+            let mut topic = Topic::new();
+            let _ = write!(topic, "{}/{}", prefix, suffix);
+            topic
+        };
+        Topics {
+            sensor: topic("sensor"),
+            status: topic("status"),
+            config: topic("config"),
+            wifi: topic("wifi"),
+            availability: topic("availability"),
+            warning: topic("warning"),
+            set: topic("set"),
+        }
+    }
+}
+
+/// Socket plus the buffers needed to frame packets on it
+struct Connection<'s, 'b> {
+    socket: TcpSocket<'s>,
+    packet_buffer: &'b mut [u8],
+    received: usize,
+}
+
+impl Connection<'_, '_> {
+    async fn send(
+        &mut self,
+        encode: impl FnOnce(&mut [u8]) -> Result<usize, EncodeError>,
+    ) -> Result<(), SessionError> {
+        let mut out = [0; 1024];
+        let length = encode(&mut out)?;
+        self.socket.write_all(&out[..length]).await?;
+        Ok(())
+    }
+
+    async fn publish(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        retain: bool,
+    ) -> Result<(), SessionError> {
+        self.send(|out| mqtt::encode_publish(out, topic, payload, retain))
+            .await
+    }
+
+    /// Reads more bytes from the broker into the packet buffer
+    async fn fill(&mut self) -> Result<(), SessionError> {
+        if self.received == self.packet_buffer.len() {
+            // Not even one packet fits, nothing we subscribe to should be that large
+            return Err(SessionError::Decode(DecodeError::Malformed));
+        }
+        match self
+            .socket
+            .read(&mut self.packet_buffer[self.received..])
+            .await?
+        {
+            0 => Err(SessionError::Closed),
+            n => {
+                self.received += n;
+                Ok(())
+            }
+        }
+    }
+
+    /// Drops the first `length` bytes of the packet buffer
+    fn consume(&mut self, length: usize) {
+        self.packet_buffer.copy_within(length..self.received, 0);
+        self.received -= length;
+    }
+}
+
+#[embassy_executor::task]
+pub async fn mqtt_task(stack: &'static Stack<NetDriver<'static>>) {
+    let Ok(broker) = BROKER_ADDRESS.parse::<Ipv4Addr>() else {
+        error!(
+            "Invalid MQTT_BROKER address {}, MQTT disabled",
+            BROKER_ADDRESS
+        );
+        return;
+    };
+    let [a, b, c, d] = broker.octets();
+    let broker = (Ipv4Address::new(a, b, c, d), BROKER_PORT);
+
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut packet_buffer = [0; 512];
+
+    let mut dht11_receiver = DHT11_WATCH.receiver().unwrap();
+    let mut status_receiver = CONTROLLER_CURRENT_STATUS.receiver().unwrap();
+    let topics = Topics::new(TOPIC_PREFIX);
+    let device_id = home_assistant::format_device_id(&flash_unique_id());
+    let device = Device {
+        id: &device_id,
+        topic_prefix: TOPIC_PREFIX,
+        sw_version: env!("CARGO_PKG_VERSION"),
+    };
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(300));
+
+    loop {
+        while !stack.is_config_up() {
+            Timer::after_millis(100).await;
+        }
+
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        // Heartbeats go out well within this, so a silent broker means the link is gone
+        socket.set_timeout(Some(KEEP_ALIVE));
+        info!(
+            "Connecting to MQTT broker {}:{}",
+            BROKER_ADDRESS, BROKER_PORT
+        );
+        let connected = socket.connect(broker).await;
+        let result = match connected {
+            Ok(()) => {
+                let mut connection = Connection {
+                    socket,
+                    packet_buffer: &mut packet_buffer,
+                    received: 0,
+                };
+                let result = run_session(
+                    &mut connection,
+                    &topics,
+                    &device,
+                    &mut backoff,
+                    &mut dht11_receiver,
+                    &mut status_receiver,
+                )
+                .await;
+                connection.socket.abort();
+                let _ = connection.socket.flush().await;
+                result
+            }
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = result {
+            warn!("MQTT connection lost: {}", err);
+        }
+
+        let delay = backoff.next_delay();
+        info!("Reconnecting to MQTT broker in {}s", delay.as_secs());
+        Timer::after(delay).await;
+    }
+}
+
+async fn run_session(
+    connection: &mut Connection<'_, '_>,
+    topics: &Topics,
+    device: &Device<'_>,
+    backoff: &mut Backoff,
+    dht11_receiver: &mut ReadingReceiver,
+    status_receiver: &mut StatusReceiver,
+) -> Result<(), SessionError> {
+    connection
+        .send(|out| {
+            mqtt::encode_connect(
+                out,
+                &Connect {
+                    client_id: CLIENT_ID,
+                    keep_alive_secs: KEEP_ALIVE.as_secs() as u16,
+                    will: Some(Will {
+                        topic: &topics.availability,
+                        payload: b"offline",
+                        retain: true,
+                    }),
+                    username: USERNAME,
+                    password: PASSWORD,
+                },
+            )
+        })
+        .await?;
+
+    let connack = with_timeout(CONNACK_TIMEOUT, async {
+        loop {
+            match mqtt::decode_packet(&connection.packet_buffer[..connection.received]) {
+                Ok((Packet::ConnAck { return_code, .. }, length)) => {
+                    connection.consume(length);
+                    return Ok(return_code);
+                }
+                Ok((packet, _)) => {
+                    warn!("Expected CONNACK, got {}", packet);
+                    return Err(SessionError::Decode(DecodeError::Malformed));
+                }
+                Err(DecodeError::Incomplete) => connection.fill().await?,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    })
+    .await;
+    match connack {
+        Ok(Ok(0)) => {}
+        Ok(Ok(return_code)) => return Err(SessionError::Refused(return_code)),
+        Ok(Err(err)) => return Err(err),
+        Err(_) => return Err(SessionError::Timeout),
+    }
+    info!("Connected to MQTT broker as {}", CLIENT_ID);
+    backoff.reset();
+
+    connection
+        .send(|out| mqtt::encode_subscribe(out, SET_PACKET_ID, &topics.set))
+        .await?;
+    connection
+        .publish(&topics.availability, b"online", true)
+        .await?;
+
+    let mut last_reading = None;
+    let mut last_status = None;
+    let mut discovery_unit = None;
+    let mut heartbeat = true;
+    loop {
+        // Discovery carries the temperature unit, so it has to follow unit changes
+        let unit = display_unit();
+        if discovery_unit != Some(unit) {
+            publish_discovery(connection, device, unit).await?;
+            discovery_unit = Some(unit);
+            heartbeat = true;
+        }
+
+        if let Some(reading) = dht11_receiver.try_get() {
+            // Every reading has its own timestamp, only a new value is worth publishing
+            let changed = last_reading.is_none_or(|last: Reading| {
+                (last.temperature, last.humidity) != (reading.temperature, reading.humidity)
+            });
+            if heartbeat || changed {
+                let mut payload = String::<192>::new();
+                let _ = api::render_sensor(
+                    &mut payload,
+                    &reading,
+                    display_unit(),
+                    local_time().as_ref(),
+                );
+                connection
+                    .publish(&topics.sensor, payload.as_bytes(), false)
+                    .await?;
+                last_reading = Some(reading);
+            }
+        }
+
+        let status = status_receiver.try_get();
+        if let Some((state, config)) = status {
+            if heartbeat || last_status.is_none_or(|(_, last_config)| last_config != config) {
+                let mut payload = String::<512>::new();
+                let _ = api::render_config(&mut payload, &config, display_unit());
+                connection
+                    .publish(&topics.config, payload.as_bytes(), true)
+                    .await?;
+            }
+            if heartbeat || last_status.is_none_or(|(last_state, _)| last_state != state) {
+                let mut payload = String::<192>::new();
+                let _ = api::render_status(
+                    &mut payload,
+                    &state,
+                    &config,
+                    Instant::now(),
+                    local_time().as_ref(),
+                );
+                connection
+                    .publish(&topics.status, payload.as_bytes(), false)
+                    .await?;
+            }
+            last_status = status;
+        }
+
+        // The state change that comes with a rest wakes this loop up
+        if let Some(limit) = CONTROLLER_WARNING.try_take() {
+            let mut payload = String::<96>::new();
+            let _ = api::render_warning(&mut payload, limit, local_time().as_ref());
+            connection
+                .publish(&topics.warning, payload.as_bytes(), false)
+                .await?;
+        }
+
+        if heartbeat {
+            let mut payload = String::<128>::new();
+            let _ = api::render_wifi(&mut payload, &wifi_status());
+            connection
+                .publish(&topics.wifi, payload.as_bytes(), false)
+                .await?;
+        }
+
+        heartbeat = false;
+        match select4(
+            connection.fill(),
+            Timer::after(HEARTBEAT_INTERVAL),
+            dht11_receiver.changed(),
+            status_receiver.changed(),
+        )
+        .await
+        {
+            Either4::First(filled) => {
+                filled?;
+                handle_incoming(connection, topics, status_receiver).await?;
+            }
+            Either4::Second(()) => heartbeat = true,
+            Either4::Third(_) | Either4::Fourth(_) => {}
+        }
+    }
+}
+
+async fn publish_discovery(
+    connection: &mut Connection<'_, '_>,
+    device: &Device<'_>,
+    unit: TemperatureUnit,
+) -> Result<(), SessionError> {
+    for component in Component::ALL {
+        let mut topic = String::<96>::new();
+        let _ = home_assistant::discovery_topic(&mut topic, DISCOVERY_PREFIX, component, device);
+        let mut payload = String::<768>::new();
+        let _ = home_assistant::render_discovery(&mut payload, component, device, unit);
+        connection.publish(&topic, payload.as_bytes(), true).await?;
+    }
+    info!("Published Home Assistant discovery for {}", device.id);
+    Ok(())
+}
+
+/// Handles every complete packet in the buffer, leaving any partial one for the next read
+async fn handle_incoming(
+    connection: &mut Connection<'_, '_>,
+    topics: &Topics,
+    status_receiver: &mut StatusReceiver,
+) -> Result<(), SessionError> {
+    loop {
+        let (packet, length) =
+            match mqtt::decode_packet(&connection.packet_buffer[..connection.received]) {
+                Ok(decoded) => decoded,
+                Err(DecodeError::Incomplete) => return Ok(()),
+                Err(err) => return Err(err.into()),
+            };
+
+        let mut ack = None;
+        match packet {
+            Packet::Publish {
+                topic,
+                payload,
+                packet_id,
+                ..
+            } if topic == topics.set.as_str() => {
+                ack = packet_id;
+                match apply_set(payload, status_receiver) {
+                    Ok(config) => {
+                        info!("Config update over MQTT: {}", config);
+                        CONTROLLER_UPDATE_CONFIG.signal(config);
+                    }
+                    Err(err) => warn!("Rejected MQTT config update: {}", err),
+                }
+            }
+            Packet::Publish { packet_id, .. } => ack = packet_id,
+            Packet::SubAck {
+                packet_id: SET_PACKET_ID,
+                return_code: 0x80,
+            } => {
+                return Err(SessionError::Refused(0x80));
+            }
+            _ => {}
+        }
+        connection.consume(length);
+
+        if let Some(packet_id) = ack {
+            connection
+                .send(|out| mqtt::encode_puback(out, packet_id))
+                .await?;
+        }
+    }
+}
+
+fn apply_set(
+    payload: &[u8],
+    status_receiver: &mut StatusReceiver,
+) -> Result<TempControllerConfig, JsonError> {
+    // Without a status the controller hasn't started yet, there is nothing to update
+    let (_, config) = status_receiver.try_get().ok_or(JsonError::InvalidValue)?;
+    api::apply_config_update(payload, config, display_unit())
+}
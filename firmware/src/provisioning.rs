@@ -0,0 +1,248 @@
+//! Setup access point for units that can't join any network, e.g. on first boot or
+//! after the router's password changed.
+//!
+//! The Wi-Fi supervisor hands over to `run` once every known network failed a few
+//! times. It scans for networks while still a station, then opens an access point,
+//! hands out addresses with `aircon_core::dhcp_server`, resolves every name to itself
+//! with `aircon_core::dns_server` so phones show the setup page as a captive portal,
+//! and lets the HTTP listeners serve `aircon_core::setup` instead of the dashboard. Once the form is saved, or
+//! after `SETUP_TIMEOUT` if networks were saved before, the access point closes and
+//! the supervisor goes back to joining the saved networks. The controller keeps
+//! regulating throughout.
+
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+use core::future::pending;
+
+use aircon_core::dhcp_server::{self, DhcpServer};
+use aircon_core::dns_server;
+use aircon_core::setup::SetupForm;
+use aircon_core::temp_controller::TempControllerConfig;
+use aircon_core::wifi::{LinkState, MAX_SSID_LENGTH};
+use cyw43::NetDriver;
+use defmt::{info, warn};
+use embassy_futures::join::join;
+use embassy_futures::select::{select3, Either3};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{ConfigV4, IpEndpoint, Ipv4Address, Ipv4Cidr, Stack, StaticConfigV4};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::{String, Vec};
+
+use crate::settings::{flash_unique_id, update_wifi_networks, wifi_networks};
+use crate::wifi::set_state;
+use crate::{SharedControl, CONTROLLER_UPDATE_CONFIG};
+
+const AP_CHANNEL: u8 = 6;
+/// The unit's address on its own network, phones get the ones right after it
+const AP_ADDRESS: Ipv4Address = Ipv4Address::new(192, 168, 4, 1);
+const AP_PREFIX_LENGTH: u8 = 24;
+const POOL_START: [u8; 4] = [192, 168, 4, 2];
+const LEASES: usize = 4;
+const LEASE_TIME: Duration = Duration::from_secs(60 * 60);
+
+/// With networks saved, give up on setup after this long and try them again, the
+/// router may just have been slower to boot than the unit after a power cut
+const SETUP_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Time for the confirmation page to reach the phone before the access point closes
+const SAVE_GRACE: Duration = Duration::from_secs(5);
+
+/// Networks offered on the setup page
+pub const MAX_SCANNED: usize = 12;
+pub type ScannedNetworks = Vec<String<MAX_SSID_LENGTH>, MAX_SCANNED>;
+
+static ACTIVE: BlockingMutex<CriticalSectionRawMutex, Cell<bool>> =
+    BlockingMutex::new(Cell::new(false));
+static SCANNED: BlockingMutex<CriticalSectionRawMutex, RefCell<ScannedNetworks>> =
+    BlockingMutex::new(RefCell::new(Vec::new()));
+static SUBMITTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Whether the setup access point is up, the HTTP listeners serve the setup page then
+pub fn is_active() -> bool {
+    ACTIVE.lock(|active| active.get())
+}
+
+pub fn scanned_networks() -> ScannedNetworks {
+    SCANNED.lock(|scanned| scanned.borrow().clone())
+}
+
+/// Saves what the setup form was filled in with and ends setup to join the network.
+/// `config` is the controller's current config, if it has reported one yet.
+pub fn submit(form: SetupForm, config: Option<TempControllerConfig>) {
+    update_wifi_networks(|networks| {
+        if networks.set(form.credentials.clone()).is_err() {
+            // Every slot is taken by networks that didn't work, drop the last tried
+            let last = networks.iter().last().map(|network| network.ssid.clone());
+            if let Some(last) = last {
+                networks.forget(&last);
+            }
+            let _ = networks.set(form.credentials.clone());
+        }
+    });
+
+    match (form.setpoint, config) {
+        (Some(setpoint), Some(config)) => CONTROLLER_UPDATE_CONFIG.signal(TempControllerConfig {
+            threshold_temperature: setpoint,
+            ..config
+        }),
+        (Some(_), None) => warn!("Controller hasn't started yet, setpoint not changed"),
+        (None, _) => {}
+    }
+    SUBMITTED.signal(());
+}
+
+/// Name of the setup access point, made unique by the end of the flash ID
+fn ap_ssid() -> String<MAX_SSID_LENGTH> {
+    let id = flash_unique_id();
+    let mut ssid = String::new();
+    let _ = write!(ssid, "aircon-setup-{:02x}{:02x}", id[6], id[7]);
+    ssid
+}
+
+/// Collects the names of nearby networks, has to happen before the access point is up
+async fn scan(control: &SharedControl) {
+    let mut found = ScannedNetworks::new();
+    let mut control = control.lock().await;
+    let mut scanner = control.scan(Default::default()).await;
+    while let Some(bss) = scanner.next().await {
+        let Ok(ssid) = core::str::from_utf8(&bss.ssid[..bss.ssid_len as usize]) else {
+            continue;
+        };
+        // Hidden networks have no name, and every access point of a mesh reports its own
+        if ssid.is_empty() || found.iter().any(|known| known == ssid) {
+            continue;
+        }
+        if let Ok(ssid) = String::try_from(ssid) {
+            let _ = found.push(ssid);
+        }
+    }
+    info!("Found {} networks", found.len());
+    SCANNED.lock(|scanned| *scanned.borrow_mut() = found);
+}
+
+async fn serve_dhcp(stack: &'static Stack<NetDriver<'static>>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 1024];
+    let mut request = [0; 576];
+    let mut reply = [0; dhcp_server::REPLY_SIZE];
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(err) = socket.bind(dhcp_server::SERVER_PORT) {
+        warn!("Failed to bind DHCP server: {:?}", err);
+        return pending().await;
+    }
+
+    let mut server =
+        DhcpServer::<LEASES>::new(AP_ADDRESS.0, AP_PREFIX_LENGTH, POOL_START, LEASE_TIME);
+    // Clients have no address yet, so every reply is broadcast
+    let clients = IpEndpoint::new(Ipv4Address::BROADCAST.into(), dhcp_server::CLIENT_PORT);
+    loop {
+        let length = match socket.recv_from(&mut request).await {
+            Ok((length, _)) => length,
+            Err(err) => {
+                warn!("DHCP receive error: {:?}", err);
+                continue;
+            }
+        };
+        match server.handle(&request[..length], Instant::now(), &mut reply) {
+            Ok(Some(length)) => {
+                if let Err(err) = socket.send_to(&reply[..length], clients).await {
+                    warn!("DHCP send error: {:?}", err);
+                }
+            }
+            Ok(None) => {}
+            Err(err) => warn!("Ignoring DHCP message: {}", err),
+        }
+    }
+}
+
+async fn serve_dns(stack: &'static Stack<NetDriver<'static>>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 1024];
+    let mut query = [0; 512];
+    let mut reply = [0; dns_server::MAX_REPLY_SIZE];
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(err) = socket.bind(dns_server::SERVER_PORT) {
+        warn!("Failed to bind DNS server: {:?}", err);
+        return pending().await;
+    }
+
+    loop {
+        let (length, client) = match socket.recv_from(&mut query).await {
+            Ok(received) => received,
+            Err(err) => {
+                warn!("DNS receive error: {:?}", err);
+                continue;
+            }
+        };
+        match dns_server::answer(&query[..length], AP_ADDRESS.0, &mut reply) {
+            Ok(Some(length)) => {
+                if let Err(err) = socket.send_to(&reply[..length], client).await {
+                    warn!("DNS send error: {:?}", err);
+                }
+            }
+            Ok(None) => {}
+            Err(err) => warn!("Ignoring DNS query: {}", err),
+        }
+    }
+}
+
+/// Runs the setup access point until the form is saved or, with networks saved,
+/// until `SETUP_TIMEOUT` passes
+pub async fn run(stack: &'static Stack<NetDriver<'static>>, control: &'static SharedControl) {
+    scan(control).await;
+
+    let ssid = ap_ssid();
+    info!("Starting setup access point {}", ssid.as_str());
+    control.lock().await.start_ap_open(&ssid, AP_CHANNEL).await;
+    stack.set_config_v4(ConfigV4::Static(StaticConfigV4 {
+        address: Ipv4Cidr::new(AP_ADDRESS, AP_PREFIX_LENGTH),
+        gateway: None,
+        dns_servers: Vec::new(),
+    }));
+    SUBMITTED.reset();
+    ACTIVE.lock(|active| active.set(true));
+    set_state(LinkState::Setup);
+
+    let has_networks = !wifi_networks().is_empty();
+    let give_up = async {
+        if has_networks {
+            Timer::after(SETUP_TIMEOUT).await
+        } else {
+            pending().await
+        }
+    };
+    if let Either3::Second(()) = select3(
+        join(serve_dhcp(stack), serve_dns(stack)),
+        SUBMITTED.wait(),
+        give_up,
+    )
+    .await
+    {
+        Timer::after(SAVE_GRACE).await;
+    }
+
+    info!("Leaving setup");
+    ACTIVE.lock(|active| active.set(false));
+    control.lock().await.close_ap().await;
+    stack.set_config_v4(ConfigV4::Dhcp(Default::default()));
+}
@@ -0,0 +1,99 @@
+//! Applies the weekly schedule to the controller as local time passes its blocks.
+//!
+//! A scheduled setpoint only lives in RAM, the one saved in flash is the last one set by
+//! hand and comes back once nothing is scheduled. Setting the setpoint by hand while a
+//! block is in effect overrides it until the next block starts.
+
+use core::cell::Cell;
+
+use aircon_core::schedule::{Action, Change, Schedule, Scheduler};
+use aircon_core::temp_controller::{Clock, TempController, TempControllerConfig};
+use aircon_core::units::Temperature;
+use defmt::info;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embedded_hal_1::digital::OutputPin;
+
+use crate::clock::local_time;
+use crate::settings;
+
+static OVERRIDDEN: BlockingMutex<CriticalSectionRawMutex, Cell<bool>> =
+    BlockingMutex::new(Cell::new(false));
+
+/// Whether a setpoint set by hand overrides the block in effect
+pub fn is_overridden() -> bool {
+    OVERRIDDEN.lock(|overridden| overridden.get())
+}
+
+/// Schedule state of the controller task
+pub struct ScheduleRunner {
+    scheduler: Scheduler,
+    /// Copy of the schedule last polled, an edit applies the block in effect again
+    schedule: Schedule,
+    /// Setpoint set by hand, used while nothing is scheduled
+    setpoint: Temperature,
+}
+
+impl ScheduleRunner {
+    pub fn new(config: &TempControllerConfig) -> Self {
+        ScheduleRunner {
+            scheduler: Scheduler::new(),
+            schedule: Schedule::new(),
+            setpoint: config.threshold_temperature,
+        }
+    }
+
+    fn publish(&self) {
+        OVERRIDDEN.lock(|overridden| overridden.set(self.scheduler.is_overridden()));
+    }
+
+    /// Applies the block that started since the last call, if any
+    pub fn poll<R: OutputPin, C: Clock>(&mut self, controller: &mut TempController<R, C>) {
+        let schedule = settings::schedule();
+        if schedule != self.schedule {
+            self.scheduler.reset();
+            self.schedule = schedule;
+        }
+
+        let now = local_time();
+        let Some(change) = self
+            .scheduler
+            .poll(&self.schedule, now.as_ref().map(|time| &time.datetime))
+        else {
+            return;
+        };
+        let (setpoint, standby) = match change {
+            Change::Apply(Action::Setpoint(setpoint)) => (setpoint, false),
+            Change::Apply(Action::Off) => (self.setpoint, true),
+            Change::Release => (self.setpoint, false),
+        };
+        info!("Schedule: {}", change);
+        controller.update_config(TempControllerConfig {
+            threshold_temperature: setpoint,
+            ..controller.get_config()
+        });
+        controller.set_standby(standby);
+        self.publish();
+    }
+
+    /// Applies a config changed by hand, returns the config to save
+    pub fn apply_manual<R: OutputPin, C: Clock>(
+        &mut self,
+        controller: &mut TempController<R, C>,
+        config: TempControllerConfig,
+    ) -> TempControllerConfig {
+        // Input sites keep the exact setpoint in effect when a client sends back the one it
+        // was shown, rounded in its display unit, so any difference is a manual change
+        if config.threshold_temperature != controller.get_config().threshold_temperature {
+            self.setpoint = config.threshold_temperature;
+            self.scheduler.override_block();
+            controller.set_standby(false);
+            self.publish();
+        }
+        controller.update_config(config);
+        TempControllerConfig {
+            threshold_temperature: self.setpoint,
+            ..config
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Keeps the user adjustable settings in the last few sectors of the on-board flash.
+
+use core::cell::{Cell, RefCell};
+
+use aircon_core::config_store::{ConfigStore, Settings};
+use aircon_core::schedule::Schedule;
+use aircon_core::schedule_store::ScheduleStore;
+use aircon_core::temp_controller::TempControllerConfig;
+use aircon_core::units::TemperatureUnit;
+use aircon_core::wall_clock::TimeZone;
+use aircon_core::wifi::WifiNetworks;
+use aircon_core::wifi_store::WifiStore;
+use defmt::{info, warn};
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_rp::flash::{Blocking, Flash};
+use embassy_rp::peripherals::FLASH;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Timer;
+use static_cell::StaticCell;
+
+use crate::clock;
+
+/// Size of the Pico W flash chip
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+const SECTOR_SIZE: u32 = 4096;
+/// Sectors reserved for settings at the end of flash, `memory.x` keeps the program out of them
+pub const SETTINGS_SECTORS: u32 = 4;
+/// Sectors after the settings holding the saved Wi-Fi networks
+pub const WIFI_SECTORS: u32 = 2;
+/// Sectors before the settings holding the weekly schedule, so the other regions kept their place
+pub const SCHEDULE_SECTORS: u32 = 2;
+const SETTINGS_OFFSET: u32 = FLASH_SIZE as u32 - (SETTINGS_SECTORS + WIFI_SECTORS) * SECTOR_SIZE;
+const WIFI_OFFSET: u32 = SETTINGS_OFFSET + SETTINGS_SECTORS * SECTOR_SIZE;
+const SCHEDULE_OFFSET: u32 = SETTINGS_OFFSET - SCHEDULE_SECTORS * SECTOR_SIZE;
+
+pub type SettingsFlash = Flash<'static, FLASH, Blocking, FLASH_SIZE>;
+/// Both stores write through their own partition of the one flash peripheral
+type SharedFlash = BlockingMutex<CriticalSectionRawMutex, RefCell<SettingsFlash>>;
+type FlashPartition = BlockingPartition<'static, CriticalSectionRawMutex, SettingsFlash>;
+pub type SettingsStore = ConfigStore<FlashPartition>;
+pub type NetworkStore = WifiStore<FlashPartition>;
+pub type WeeklyScheduleStore = ScheduleStore<FlashPartition>;
+
+/// The flash stores, owned by `settings_task` once loaded
+pub struct Stores {
+    settings: SettingsStore,
+    wifi: NetworkStore,
+    schedule: WeeklyScheduleStore,
+}
+
+/// Controller config to write to flash, signalled once the controller applied it
+pub static SAVE_CONTROLLER_CONFIG: Signal<CriticalSectionRawMutex, TempControllerConfig> =
+    Signal::new();
+static SAVE_DISPLAY_UNIT: Signal<CriticalSectionRawMutex, TemperatureUnit> = Signal::new();
+static SAVE_WIFI_NETWORKS: Signal<CriticalSectionRawMutex, WifiNetworks> = Signal::new();
+static SAVE_TIME_ZONE: Signal<CriticalSectionRawMutex, TimeZone> = Signal::new();
+static SAVE_SCHEDULE: Signal<CriticalSectionRawMutex, Schedule> = Signal::new();
+
+/// Unit every user facing output uses, the controller itself always works in °C
+static DISPLAY_UNIT: BlockingMutex<CriticalSectionRawMutex, Cell<TemperatureUnit>> =
+    BlockingMutex::new(Cell::new(TemperatureUnit::Celsius));
+
+/// Saved networks the Wi-Fi supervisor tries, in priority order
+static WIFI_NETWORKS: BlockingMutex<CriticalSectionRawMutex, RefCell<WifiNetworks>> =
+    BlockingMutex::new(RefCell::new(WifiNetworks::new()));
+
+/// Weekly setpoint schedule, applied by the controller task
+static SCHEDULE: BlockingMutex<CriticalSectionRawMutex, RefCell<Schedule>> =
+    BlockingMutex::new(RefCell::new(Schedule::new()));
+
+/// Unique ID of the flash chip, read once at boot since reading it needs the flash peripheral
+static FLASH_UNIQUE_ID: BlockingMutex<CriticalSectionRawMutex, Cell<[u8; 8]>> =
+    BlockingMutex::new(Cell::new([0; 8]));
+
+pub fn flash_unique_id() -> [u8; 8] {
+    FLASH_UNIQUE_ID.lock(|id| id.get())
+}
+
+pub fn display_unit() -> TemperatureUnit {
+    DISPLAY_UNIT.lock(|unit| unit.get())
+}
+
+/// Changes the display unit and queues it to be saved
+pub fn set_display_unit(unit: TemperatureUnit) {
+    DISPLAY_UNIT.lock(|current| current.set(unit));
+    SAVE_DISPLAY_UNIT.signal(unit);
+}
+
+/// Changes the time zone of the wall clock and queues it to be saved
+pub fn set_time_zone(zone: TimeZone) {
+    clock::apply_time_zone(zone);
+    SAVE_TIME_ZONE.signal(zone);
+}
+
+pub fn wifi_networks() -> WifiNetworks {
+    WIFI_NETWORKS.lock(|networks| networks.borrow().clone())
+}
+
+/// Applies `update` to the saved networks and queues them to be saved if they changed
+pub fn update_wifi_networks<R>(update: impl FnOnce(&mut WifiNetworks) -> R) -> R {
+    WIFI_NETWORKS.lock(|networks| {
+        let mut networks = networks.borrow_mut();
+        let before = networks.clone();
+        let result = update(&mut networks);
+        if *networks != before {
+            SAVE_WIFI_NETWORKS.signal(networks.clone());
+        }
+        result
+    })
+}
+
+pub fn schedule() -> Schedule {
+    SCHEDULE.lock(|schedule| schedule.borrow().clone())
+}
+
+/// Applies `update` to the schedule and queues it to be saved if it changed
+pub fn update_schedule<R>(update: impl FnOnce(&mut Schedule) -> R) -> R {
+    SCHEDULE.lock(|schedule| {
+        let mut schedule = schedule.borrow_mut();
+        let before = schedule.clone();
+        let result = update(&mut schedule);
+        if *schedule != before {
+            SAVE_SCHEDULE.signal(schedule.clone());
+        }
+        result
+    })
+}
+
+/// Reads the flash unique ID, then opens the settings, Wi-Fi and schedule regions and loads what
+/// they hold, falling back to defaults
+pub fn load_settings(flash: FLASH) -> (Stores, Settings) {
+    let mut flash = Flash::new_blocking(flash);
+    let mut unique_id = [0; 8];
+    match flash.blocking_unique_id(&mut unique_id) {
+        Ok(()) => FLASH_UNIQUE_ID.lock(|id| id.set(unique_id)),
+        Err(err) => warn!("Failed to read flash unique ID: {}", err),
+    }
+
+    static SHARED_FLASH: StaticCell<SharedFlash> = StaticCell::new();
+    let flash = &*SHARED_FLASH.init(BlockingMutex::new(RefCell::new(flash)));
+    let mut stores = Stores {
+        settings: ConfigStore::new(
+            BlockingPartition::new(flash, SETTINGS_OFFSET, SETTINGS_SECTORS * SECTOR_SIZE),
+            0,
+            SETTINGS_SECTORS,
+        ),
+        wifi: WifiStore::new(
+            BlockingPartition::new(flash, WIFI_OFFSET, WIFI_SECTORS * SECTOR_SIZE),
+            0,
+        ),
+        schedule: ScheduleStore::new(
+            BlockingPartition::new(flash, SCHEDULE_OFFSET, SCHEDULE_SECTORS * SECTOR_SIZE),
+            0,
+        ),
+    };
+
+    let settings = match stores.settings.load() {
+        Ok(Some(settings)) => {
+            info!("Loaded stored settings: {}", settings);
+            settings
+        }
+        Ok(None) => {
+            info!("No valid stored settings, using defaults");
+            Settings::default()
+        }
+        Err(err) => {
+            warn!("Failed to read settings from flash: {}", err);
+            Settings::default()
+        }
+    };
+    DISPLAY_UNIT.lock(|unit| unit.set(settings.display_unit));
+    clock::apply_time_zone(settings.time_zone);
+
+    match stores.wifi.load() {
+        Ok(Some(networks)) => {
+            info!("Loaded {} saved Wi-Fi networks", networks.iter().count());
+            WIFI_NETWORKS.lock(|saved| *saved.borrow_mut() = networks);
+        }
+        Ok(None) => info!("No saved Wi-Fi networks"),
+        Err(err) => warn!("Failed to read Wi-Fi networks from flash: {}", err),
+    }
+
+    match stores.schedule.load() {
+        Ok(Some(schedule)) => {
+            info!("Loaded {} scheduled blocks", schedule.iter().count());
+            SCHEDULE.lock(|saved| *saved.borrow_mut() = schedule);
+        }
+        Ok(None) => info!("No saved schedule"),
+        Err(err) => warn!("Failed to read schedule from flash: {}", err),
+    }
+
+    (stores, settings)
+}
+
+#[embassy_executor::task]
+pub async fn settings_task(mut stores: Stores, mut settings: Settings) {
+    loop {
+        let saved = match select4(
+            SAVE_CONTROLLER_CONFIG.wait(),
+            SAVE_DISPLAY_UNIT.wait(),
+            SAVE_TIME_ZONE.wait(),
+            // Both have a store of their own
+            select(SAVE_WIFI_NETWORKS.wait(), SAVE_SCHEDULE.wait()),
+        )
+        .await
+        {
+            Either4::First(config) => {
+                settings.controller = config;
+                stores.settings.save(&settings)
+            }
+            Either4::Second(unit) => {
+                settings.display_unit = unit;
+                stores.settings.save(&settings)
+            }
+            Either4::Third(zone) => {
+                settings.time_zone = zone;
+                stores.settings.save(&settings)
+            }
+            Either4::Fourth(Either::First(networks)) => stores.wifi.save(&networks),
+            Either4::Fourth(Either::Second(schedule)) => stores.schedule.save(&schedule),
+        };
+
+        match saved {
+            Ok(()) => info!("Saved settings"),
+            Err(err) => warn!("Failed to save settings: {}", err),
+        }
+        // Flash writes stall the whole chip, don't let a chatty client wear it out either
+        Timer::after_secs(1).await;
+    }
+}
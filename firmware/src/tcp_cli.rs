@@ -0,0 +1,155 @@
+//! The UART command line over TCP, for scripts on the LAN.
+//!
+//! Each line is one command, same syntax as on the UART. The reply is the command's
+//! output followed by an empty line, so a client reads until it sees one:
+//!
+//! ```text
+//! > temp
+//! < Temp: 22.0°C
+//! < Humidity: 40.0%
+//! <
+//! ```
+//!
+//! Commands that don't parse get a single `error: ...` line instead.
+
+use core::convert::Infallible;
+
+use aircon_core::cli::BaseCommand;
+use cyw43::NetDriver;
+use defmt::{info, warn};
+use embassy_net::tcp::{Error as TcpError, TcpSocket};
+use embassy_net::Stack;
+use embassy_time::Duration;
+use embedded_cli::cli::{CliBuilder, CliHandle};
+use embedded_io::ErrorType;
+use embedded_io_async::Write;
+use heapless::String;
+
+use crate::commands::CommandContext;
+
+pub const CLI_PORT: u16 = 1234;
+
+/// Connections without a command for this long are dropped so the port frees up
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Fits the longest reply, `schedule show` with every block taken at under 50 bytes a line
+type Reply = String<1024>;
+
+/// embedded-cli echoes input and draws a prompt, none of which belongs in a reply
+struct Discard;
+
+impl ErrorType for Discard {
+    type Error = Infallible;
+}
+
+impl embedded_io::Write for Discard {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[embassy_executor::task]
+pub async fn tcp_cli_task(network_stack: &'static Stack<NetDriver<'static>>) -> ! {
+    let mut rx_buffer = [0; 256];
+    let mut tx_buffer = [0; 512];
+    let mut command_buffer = [0; 128];
+    let mut history_buffer = [0; 32];
+
+    let mut context = CommandContext::new(network_stack);
+
+    loop {
+        let mut socket = TcpSocket::new(network_stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(IDLE_TIMEOUT));
+
+        info!("Listening on TCP:{}...", CLI_PORT);
+        if let Err(e) = socket.accept(CLI_PORT).await {
+            warn!("accept error: {:?}", e);
+            continue;
+        }
+        info!("CLI connection from {:?}", socket.remote_endpoint());
+
+        // A fresh command line per connection, so a half typed command doesn't leak into the next
+        let mut cli = CliBuilder::default()
+            .writer(Discard)
+            .command_buffer(&mut command_buffer[..])
+            .history_buffer(&mut history_buffer[..])
+            .build()
+            .ok()
+            .unwrap();
+
+        if let Err(e) = handle_connection(&mut socket, |byte, reply| {
+            let mut handled = false;
+            let _ = cli.process_byte::<BaseCommand, _>(
+                byte,
+                &mut BaseCommand::processor(
+                    |_cli: &mut CliHandle<'_, Discard, Infallible>, command| {
+                        if context.execute(command, reply).is_err() {
+                            reply.clear();
+                            let _ = reply.push_str("error: reply too long");
+                        }
+                        handled = true;
+                        Ok(())
+                    },
+                ),
+            );
+            handled
+        })
+        .await
+        {
+            warn!("CLI connection error: {:?}", e);
+        }
+        socket.close();
+        let _ = socket.flush().await;
+    }
+}
+
+/// Splits the input into lines and replies to each, `process` feeds one byte to the
+/// command line and returns whether that byte ran a command
+async fn handle_connection(
+    socket: &mut TcpSocket<'_>,
+    mut process: impl FnMut(u8, &mut Reply) -> bool,
+) -> Result<(), TcpError> {
+    let mut buffer = [0; 64];
+    let mut reply = Reply::new();
+    let mut blank_line = true;
+    let mut handled = false;
+
+    loop {
+        let n = socket.read(&mut buffer).await?;
+        if n == 0 {
+            return Ok(());
+        }
+
+        for &byte in &buffer[..n] {
+            // The command line ends lines on CR like a serial terminal, scripts send LF or CRLF
+            let byte = match byte {
+                b'\r' => continue,
+                b'\n' => b'\r',
+                byte => byte,
+            };
+            blank_line &= byte.is_ascii_whitespace();
+            handled |= process(byte, &mut reply);
+            if byte != b'\r' {
+                continue;
+            }
+
+            if !handled && !blank_line {
+                reply.clear();
+                let _ = reply.push_str("error: unknown command or invalid arguments");
+            }
+            if handled || !blank_line {
+                // Written on its own so a client always finds the end of the reply
+                socket.write_all(reply.as_bytes()).await?;
+                socket.write_all(b"\n\n").await?;
+                socket.flush().await?;
+            }
+            reply.clear();
+            blank_line = true;
+            handled = false;
+        }
+    }
+}
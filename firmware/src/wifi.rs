@@ -0,0 +1,177 @@
+//! Keeps the Wi-Fi link up: joins at boot, notices when the link or the DHCP lease is
+//! lost, and rejoins with exponential backoff. Nothing else waits on it, the controller
+//! keeps regulating while the network is down.
+//!
+//! Networks saved with `wifi set` are tried in order, then the one built in from the
+//! `wifi_network` and `wifi_password` files, if those aren't empty. When none of them
+//! can be joined the unit opens its setup access point, see `provisioning`.
+
+use core::cell::Cell;
+
+use aircon_core::backoff::Backoff;
+use aircon_core::wifi::{LinkState, WifiCredentials, WifiStatus};
+use cyw43::NetDriver;
+use defmt::{info, warn};
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_time::{with_timeout, Duration, Timer};
+
+use crate::provisioning;
+use crate::settings::wifi_networks;
+use crate::SharedControl;
+
+/// Built in fallback network, leave the password file empty for an open network
+const WIFI_NETWORK: &str = include_str!("wifi_network");
+const WIFI_PASSWORD: &str = include_str!("wifi_password");
+
+/// How long the access point gets to hand out a lease before we rejoin
+const DHCP_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often link, lease and RSSI are checked while up
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Failed rounds through every known network before falling back to the setup access point
+const SETUP_AFTER_RETRIES: u32 = 3;
+
+static WIFI_STATUS: BlockingMutex<CriticalSectionRawMutex, Cell<WifiStatus>> =
+    BlockingMutex::new(Cell::new(WifiStatus {
+        state: LinkState::Disconnected,
+        rssi_dbm: None,
+        retries: 0,
+    }));
+
+pub fn wifi_status() -> WifiStatus {
+    WIFI_STATUS.lock(|status| status.get())
+}
+
+fn update_status(update: impl FnOnce(&mut WifiStatus)) {
+    WIFI_STATUS.lock(|status| {
+        let mut updated = status.get();
+        update(&mut updated);
+        status.set(updated);
+    });
+}
+
+pub fn set_state(state: LinkState) {
+    update_status(|status| {
+        status.state = state;
+        if state != LinkState::Up {
+            status.rssi_dbm = None;
+        }
+    });
+}
+
+/// The network built in at compile time, if any
+pub fn fallback_network() -> Option<WifiCredentials> {
+    let ssid = WIFI_NETWORK.trim();
+    if ssid.is_empty() {
+        return None;
+    }
+    let passphrase = Some(WIFI_PASSWORD.trim()).filter(|passphrase| !passphrase.is_empty());
+    match WifiCredentials::new(ssid, passphrase) {
+        Ok(credentials) => Some(credentials),
+        Err(err) => {
+            warn!("Ignoring built in Wi-Fi network: {}", err);
+            None
+        }
+    }
+}
+
+async fn join(control: &SharedControl, network: &WifiCredentials) -> bool {
+    info!("Joining {}", network.ssid.as_str());
+    let mut control = control.lock().await;
+    let joined = match &network.passphrase {
+        Some(passphrase) => control.join_wpa2(&network.ssid, passphrase).await,
+        None => control.join_open(&network.ssid).await,
+    };
+    match joined {
+        Ok(()) => true,
+        Err(err) => {
+            warn!(
+                "join {} failed with status={}",
+                network.ssid.as_str(),
+                err.status
+            );
+            false
+        }
+    }
+}
+
+async fn wait_for_dhcp(stack: &Stack<NetDriver<'static>>) {
+    while !stack.is_config_up() {
+        Timer::after_millis(100).await;
+    }
+}
+
+#[embassy_executor::task]
+pub async fn wifi_supervisor(
+    stack: &'static Stack<NetDriver<'static>>,
+    control: &'static SharedControl,
+) -> ! {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(120));
+
+    loop {
+        set_state(LinkState::Joining);
+        let saved = wifi_networks();
+        let fallback = fallback_network();
+        let mut joined = false;
+        for network in saved.iter().chain(fallback.iter()) {
+            if join(control, network).await {
+                joined = true;
+                break;
+            }
+        }
+        if saved.is_empty() && fallback.is_none() {
+            warn!("No Wi-Fi network configured, starting setup");
+            provisioning::run(stack, control).await;
+            continue;
+        }
+
+        let connected = if joined {
+            set_state(LinkState::Dhcp);
+            info!("waiting for DHCP...");
+            match with_timeout(DHCP_TIMEOUT, wait_for_dhcp(stack)).await {
+                Ok(()) => true,
+                Err(_) => {
+                    warn!("No DHCP lease after {}s", DHCP_TIMEOUT.as_secs());
+                    control.lock().await.leave().await;
+                    false
+                }
+            }
+        } else {
+            false
+        };
+
+        if !connected {
+            update_status(|status| status.retries += 1);
+            if wifi_status().retries >= SETUP_AFTER_RETRIES {
+                warn!("No network could be joined, starting setup");
+                provisioning::run(stack, control).await;
+                backoff.reset();
+                update_status(|status| status.retries = 0);
+                continue;
+            }
+            set_state(LinkState::Disconnected);
+            let delay = backoff.next_delay();
+            info!("Rejoining in {}s", delay.as_secs());
+            Timer::after(delay).await;
+            continue;
+        }
+
+        info!("DHCP is now up!");
+        backoff.reset();
+        update_status(|status| {
+            status.state = LinkState::Up;
+            status.retries = 0;
+        });
+
+        while stack.is_link_up() && stack.is_config_up() {
+            let rssi = control.lock().await.get_rssi().await;
+            update_status(|status| status.rssi_dbm = Some(rssi));
+            Timer::after(CHECK_INTERVAL).await;
+        }
+
+        warn!("Wi-Fi link lost, rejoining");
+        set_state(LinkState::Disconnected);
+        control.lock().await.leave().await;
+    }
+}
@@ -0,0 +1,276 @@
+//! Runs the real `TempController` against a simulated room, faster than real time.
+//!
+//! Prints one CSV row per tick to stdout, e.g.
+//!
+//! ```text
+//! cargo run -p aircon-simulator -- simulator/scenarios/hot_afternoon.txt --threshold 22 > run.csv
+//! ```
+
+use std::cell::Cell;
+use std::convert::Infallible;
+use std::io::{self, Write};
+use std::process::ExitCode;
+use std::rc::Rc;
+
+use aircon_core::temp_controller::{
+    user_duration, Clock, ControllerState, DutyLimit, TempController, TempControllerConfig,
+    DEFAULT_DUTY_WINDOW,
+};
+use embassy_time::{Duration, Instant};
+use embedded_hal::digital::{ErrorType, OutputPin};
+
+mod room;
+mod scenario;
+mod sensor;
+
+use room::Room;
+use scenario::Scenario;
+use sensor::SimulatedDht11;
+
+const USAGE: &str = "usage: aircon-simulator [SCENARIO] [--threshold C] [--hysteresis C] \
+[--min-runtime SECS] [--cooldown SECS] [--sensor-timeout SECS] [--max-runtime SECS] \
+[--duty-limit PERCENT] [--forced-rest SECS] [--tick SECS]";
+
+/// Relay shared with the simulation loop so it can see what the controller switched
+#[derive(Clone, Default)]
+struct SimRelay(Rc<Cell<bool>>);
+
+impl ErrorType for SimRelay {
+    type Error = Infallible;
+}
+
+impl OutputPin for SimRelay {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set(true);
+        Ok(())
+    }
+}
+
+/// Clock advanced by the simulation loop instead of the wall clock
+#[derive(Clone)]
+struct SimClock(Rc<Cell<Instant>>);
+
+impl Clock for SimClock {
+    fn now(&self) -> Instant {
+        self.0.get()
+    }
+}
+
+struct Options {
+    scenario: Option<String>,
+    config: TempControllerConfig,
+    tick: Duration,
+}
+
+fn parse_options() -> Result<Options, String> {
+    // Same defaults the firmware starts with when nothing is stored in flash
+    let mut options = Options {
+        scenario: None,
+        config: TempControllerConfig::default(),
+        tick: Duration::from_secs(1),
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            options.scenario = Some(arg);
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", arg))?;
+        let invalid = || format!("invalid value `{}` for {}", value, arg);
+        let duration = || {
+            value
+                .parse()
+                .ok()
+                .and_then(user_duration)
+                .ok_or_else(invalid)
+        };
+        match arg.as_str() {
+            "--threshold" => {
+                options.config.threshold_temperature = value.parse().map_err(|_| invalid())?
+            }
+            "--hysteresis" => options.config.hysteresis = value.parse().map_err(|_| invalid())?,
+            "--min-runtime" => options.config.minimum_runtime = duration()?,
+            "--cooldown" => options.config.cooldown_time = duration()?,
+            "--sensor-timeout" => options.config.sensor_timeout = duration()?,
+            "--max-runtime" => options.config.maximum_runtime = Some(duration()?),
+            // Over the same one hour window the firmware defaults to
+            "--duty-limit" => {
+                options.config.duty_limit = Some(DutyLimit {
+                    percent: value.parse().map_err(|_| invalid())?,
+                    window: DEFAULT_DUTY_WINDOW,
+                })
+            }
+            "--forced-rest" => options.config.forced_rest = duration()?,
+            "--tick" => options.tick = duration()?,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    options.config.validate().map_err(|err| err.to_string())?;
+    Ok(options)
+}
+
+fn state_name(state: ControllerState) -> &'static str {
+    match state {
+        ControllerState::Idle => "Idle",
+        ControllerState::Running { .. } => "Running",
+        ControllerState::Cooldown { .. } => "Cooldown",
+        ControllerState::Fault { .. } => "Fault",
+        ControllerState::Rest { .. } => "Rest",
+    }
+}
+
+/// Runs `config` against `scenario`, writing one CSV row per `tick` to `out`
+fn simulate(
+    scenario: &Scenario,
+    config: TempControllerConfig,
+    tick: Duration,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let relay = SimRelay::default();
+    let clock = SimClock(Rc::new(Cell::new(Instant::from_secs(0))));
+    let mut controller = TempController::new(config, relay.clone(), clock.clone());
+    let mut room = Room::new(scenario);
+    let mut sensor = SimulatedDht11::new(scenario);
+
+    let dt = tick.as_micros() as f64 / 1_000_000.0;
+    // Like the firmware the controller gets the last reading, it faults once that is too old
+    let mut last_reading = None;
+
+    writeln!(out, "time_s,outside_temp,room_temp,sensor_temp,relay,state")?;
+    let mut time = 0.0;
+    while time <= scenario.duration {
+        if let Some(reading) = sensor.read(time, clock.now(), room.temperature()) {
+            last_reading = Some(reading);
+        }
+        controller.update(last_reading);
+        let sensor_temp = match last_reading {
+            Some(reading) => reading.temperature.to_string(),
+            None => String::new(),
+        };
+
+        writeln!(
+            out,
+            "{},{:.2},{:.2},{},{},{}",
+            clock.now().as_secs(),
+            scenario.outside_temperature(time),
+            room.temperature(),
+            sensor_temp,
+            relay.0.get() as u8,
+            state_name(controller.get_state()),
+        )?;
+
+        clock.0.set(clock.now() + tick);
+        time += dt;
+        room.step(time, dt, relay.0.get());
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let scenario = match &options.scenario {
+        Some(path) => {
+            let parsed = std::fs::read_to_string(path)
+                .map_err(|err| err.to_string())
+                .and_then(|text| Scenario::parse(&text).map_err(|err| err.to_string()));
+            match parsed {
+                Ok(scenario) => scenario,
+                Err(err) => {
+                    eprintln!("failed to load scenario {}: {}", path, err);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        None => Scenario::default(),
+    };
+
+    let mut out = io::stdout().lock();
+    if let Err(err) = simulate(&scenario, options.config, options.tick, &mut out) {
+        eprintln!("failed to write the results: {}", err);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(scenario: &str, config: TempControllerConfig) -> Vec<Vec<String>> {
+        let scenario = Scenario::parse(scenario).unwrap();
+        let mut out = Vec::new();
+        simulate(&scenario, config, Duration::from_secs(1), &mut out).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| line.split(',').map(str::to_owned).collect())
+            .collect()
+    }
+
+    #[test]
+    fn writes_a_row_per_tick() {
+        let rows = run(
+            "duration_hours = 0.01\nsensor_noise = 0\noutside 0 30\n",
+            TempControllerConfig::default(),
+        );
+        assert_eq!(
+            rows[0],
+            [
+                "time_s",
+                "outside_temp",
+                "room_temp",
+                "sensor_temp",
+                "relay",
+                "state"
+            ]
+        );
+        // 36 s, both ends included
+        assert_eq!(rows.len(), 1 + 37);
+        assert!(rows.iter().all(|row| row.len() == 6));
+        assert_eq!(rows[1], ["0", "30.00", "24.00", "24.0", "0", "Cooldown"]);
+        assert_eq!(rows[37][0], "36");
+    }
+
+    #[test]
+    fn starts_the_compressor_once_the_cooldown_is_over() {
+        let rows = run(
+            "duration_hours = 0.1\nsensor_noise = 0\n",
+            TempControllerConfig::default(),
+        );
+        let first_on = rows[1..].iter().position(|row| row[4] == "1").unwrap();
+        let (before, on) = (&rows[first_on], &rows[first_on + 1]);
+        assert_eq!(before[4..], ["0", "Idle"]);
+        assert_eq!(on[0], "12");
+        assert_eq!(on[4..], ["1", "Running"]);
+        // The room cools down while it runs
+        let last = rows.last().unwrap();
+        assert!(last[2].parse::<f64>().unwrap() < on[2].parse::<f64>().unwrap());
+    }
+
+    #[test]
+    fn faults_during_a_sensor_dropout() {
+        let rows = run(
+            "duration_hours = 0.2\nsensor_noise = 0\ndropout 0.05 0.2\n",
+            TempControllerConfig::default(),
+        );
+        // The last reading is from 179 s, it is too old 61 s later
+        assert_eq!(rows[1 + 239][4..], ["1", "Running"]);
+        assert_eq!(rows[1 + 240][4..], ["0", "Fault"]);
+    }
+}
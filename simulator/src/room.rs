@@ -0,0 +1,87 @@
+use crate::scenario::Scenario;
+
+/// Lumped thermal model of a single room
+pub struct Room<'a> {
+    scenario: &'a Scenario,
+    temperature: f64,
+}
+
+impl<'a> Room<'a> {
+    pub fn new(scenario: &'a Scenario) -> Self {
+        Room {
+            scenario,
+            temperature: scenario.initial_room_temp,
+        }
+    }
+
+    pub fn temperature(&self) -> f64 {
+        self.temperature
+    }
+
+    /// Advances the model by `dt` seconds ending at `time`
+    pub fn step(&mut self, time: f64, dt: f64, cooling: bool) {
+        let outside = self.scenario.outside_temperature(time);
+        let loss = self.scenario.envelope_loss + self.scenario.door_loss(time);
+        let mut heat_flow = loss * (outside - self.temperature);
+        if cooling {
+            heat_flow -= self.scenario.cooling_power;
+        }
+        self.temperature += heat_flow * dt / self.scenario.thermal_mass;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scenario(outside: f64) -> Scenario {
+        Scenario {
+            initial_room_temp: 25.0,
+            thermal_mass: 1_000_000.0,
+            envelope_loss: 100.0,
+            cooling_power: 2000.0,
+            outside: vec![(0.0, outside)],
+            ..Scenario::default()
+        }
+    }
+
+    #[test]
+    fn drifts_towards_the_outside_temperature() {
+        let scenario = scenario(35.0);
+        let mut room = Room::new(&scenario);
+        assert_eq!(room.temperature(), 25.0);
+        // 100 W/K over 10 K for 10 s into 1 MJ/K
+        room.step(10.0, 10.0, false);
+        assert!((room.temperature() - 25.01).abs() < 1e-9);
+
+        let still = self::scenario(25.0);
+        let mut room = Room::new(&still);
+        room.step(10.0, 10.0, false);
+        assert_eq!(room.temperature(), 25.0);
+    }
+
+    #[test]
+    fn cooling_removes_heat() {
+        let scenario = scenario(25.0);
+        let mut room = Room::new(&scenario);
+        room.step(10.0, 10.0, true);
+        assert!((room.temperature() - 24.98).abs() < 1e-9);
+    }
+
+    #[test]
+    fn open_doors_lose_more_heat() {
+        let scenario = Scenario {
+            doors: vec![crate::scenario::DoorOpening {
+                window: crate::scenario::Window {
+                    start: 0.0,
+                    end: 100.0,
+                },
+                extra_loss: 900.0,
+            }],
+            ..scenario(35.0)
+        };
+        let mut room = Room::new(&scenario);
+        room.step(10.0, 10.0, false);
+        assert!((room.temperature() - 25.1).abs() < 1e-9);
+    }
+}
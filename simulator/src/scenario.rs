@@ -0,0 +1,318 @@
+//! Scenario files describing the simulated room.
+//!
+//! A scenario is a plain text file with one setting per line, `#` starts a comment:
+//!
+//! ```text
+//! duration_hours = 8
+//! initial_room_temp = 24
+//! outside 0 28        # hour, °C, outside temperature is interpolated between points
+//! outside 4 35
+//! door 2.0 2.5 300    # start hour, end hour, extra heat loss in W/K while open
+//! dropout 5.0 5.5     # start hour, end hour, sensor gives no readings
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A window of time in seconds since the start of the simulation
+#[derive(Debug, Clone, Copy)]
+pub struct Window {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Window {
+    pub fn contains(&self, time: f64) -> bool {
+        time >= self.start && time < self.end
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DoorOpening {
+    pub window: Window,
+    /// Additional heat exchange with the outside in W/K while the door is open
+    pub extra_loss: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    /// Length of the simulation in seconds
+    pub duration: f64,
+    pub initial_room_temp: f64,
+    /// Heat capacity of the room and its contents in J/K
+    pub thermal_mass: f64,
+    /// Heat exchange through walls and windows in W/K
+    pub envelope_loss: f64,
+    /// Heat removed by the AC while the relay is on in W
+    pub cooling_power: f64,
+    /// Peak amplitude of the noise added to sensor readings in °C
+    pub sensor_noise: f64,
+    /// Relative humidity reported by the sensor in %
+    pub humidity: u16,
+    pub seed: u64,
+    /// Outside temperature profile as (seconds, °C), sorted by time
+    pub outside: Vec<(f64, f64)>,
+    pub doors: Vec<DoorOpening>,
+    pub dropouts: Vec<Window>,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario {
+            duration: 4.0 * 3600.0,
+            initial_room_temp: 24.0,
+            thermal_mass: 2_000_000.0,
+            envelope_loss: 60.0,
+            cooling_power: 2500.0,
+            sensor_noise: 0.5,
+            humidity: 50,
+            seed: 1,
+            outside: vec![(0.0, 30.0)],
+            doors: Vec::new(),
+            dropouts: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn parse_value<T: FromStr>(line: usize, value: &str) -> Result<T, ParseError> {
+    value.parse().map_err(|_| ParseError {
+        line,
+        message: format!("invalid value `{}`", value),
+    })
+}
+
+fn parse_args<const N: usize>(line: usize, args: &[&str]) -> Result<[f64; N], ParseError> {
+    if args.len() != N {
+        return Err(ParseError {
+            line,
+            message: format!("expected {} values, got {}", N, args.len()),
+        });
+    }
+    let mut values = [0.0; N];
+    for (value, arg) in values.iter_mut().zip(args) {
+        *value = parse_value(line, arg)?;
+    }
+    Ok(values)
+}
+
+fn hours_window(start: f64, end: f64) -> Window {
+    Window {
+        start: start * 3600.0,
+        end: end * 3600.0,
+    }
+}
+
+impl Scenario {
+    pub fn parse(text: &str) -> Result<Scenario, ParseError> {
+        let mut scenario = Scenario::default();
+        let mut outside = Vec::new();
+
+        for (index, raw_line) in text.lines().enumerate() {
+            let line = index + 1;
+            let content = raw_line.split('#').next().unwrap_or("").trim();
+            if content.is_empty() {
+                continue;
+            }
+
+            if let Some((key, value)) = content.split_once('=') {
+                let value = value.trim();
+                match key.trim() {
+                    "duration_hours" => {
+                        scenario.duration = parse_value::<f64>(line, value)? * 3600.0
+                    }
+                    "initial_room_temp" => scenario.initial_room_temp = parse_value(line, value)?,
+                    "thermal_mass_kj_per_k" => {
+                        scenario.thermal_mass = parse_value::<f64>(line, value)? * 1000.0
+                    }
+                    "envelope_w_per_k" => scenario.envelope_loss = parse_value(line, value)?,
+                    "cooling_w" => scenario.cooling_power = parse_value(line, value)?,
+                    "sensor_noise" => scenario.sensor_noise = parse_value(line, value)?,
+                    "humidity" => scenario.humidity = parse_value(line, value)?,
+                    "seed" => scenario.seed = parse_value(line, value)?,
+                    other => {
+                        return Err(ParseError {
+                            line,
+                            message: format!("unknown setting `{}`", other),
+                        })
+                    }
+                }
+                continue;
+            }
+
+            let mut words = content.split_whitespace();
+            let keyword = words.next().unwrap_or("");
+            let args: Vec<&str> = words.collect();
+            match keyword {
+                "outside" => {
+                    let [hour, temperature] = parse_args(line, &args)?;
+                    outside.push((hour * 3600.0, temperature));
+                }
+                "door" => {
+                    let [start, end, extra_loss] = parse_args(line, &args)?;
+                    scenario.doors.push(DoorOpening {
+                        window: hours_window(start, end),
+                        extra_loss,
+                    });
+                }
+                "dropout" => {
+                    let [start, end] = parse_args(line, &args)?;
+                    scenario.dropouts.push(hours_window(start, end));
+                }
+                other => {
+                    return Err(ParseError {
+                        line,
+                        message: format!("unknown keyword `{}`", other),
+                    })
+                }
+            }
+        }
+
+        if !outside.is_empty() {
+            outside.sort_by(|a, b| a.0.total_cmp(&b.0));
+            scenario.outside = outside;
+        }
+        Ok(scenario)
+    }
+
+    /// Outside temperature at `time` seconds, linearly interpolated between profile points
+    pub fn outside_temperature(&self, time: f64) -> f64 {
+        let first = self.outside[0];
+        if time <= first.0 {
+            return first.1;
+        }
+        for pair in self.outside.windows(2) {
+            let ((t0, temp0), (t1, temp1)) = (pair[0], pair[1]);
+            if time <= t1 {
+                return temp0 + (temp1 - temp0) * (time - t0) / (t1 - t0);
+            }
+        }
+        self.outside[self.outside.len() - 1].1
+    }
+
+    /// Additional heat loss from open doors at `time` seconds in W/K
+    pub fn door_loss(&self, time: f64) -> f64 {
+        self.doors
+            .iter()
+            .filter(|door| door.window.contains(time))
+            .map(|door| door.extra_loss)
+            .sum()
+    }
+
+    pub fn sensor_dropped_out(&self, time: f64) -> bool {
+        self.dropouts.iter().any(|window| window.contains(time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOT_AFTERNOON: &str = "\
+# A hot afternoon
+duration_hours = 0.5
+initial_room_temp = 26.5
+thermal_mass_kj_per_k = 1500
+envelope_w_per_k = 80     # leaky
+cooling_w = 3000
+sensor_noise = 0
+humidity = 40
+seed = 7
+
+outside 2 35
+outside 0 30
+door 0.25 0.5 300
+dropout 1.0 1.5
+";
+
+    fn parse_error(text: &str) -> ParseError {
+        Scenario::parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn parses_every_setting() {
+        let scenario = Scenario::parse(HOT_AFTERNOON).unwrap();
+        assert_eq!(scenario.duration, 1800.0);
+        assert_eq!(scenario.initial_room_temp, 26.5);
+        assert_eq!(scenario.thermal_mass, 1_500_000.0);
+        assert_eq!(scenario.envelope_loss, 80.0);
+        assert_eq!(scenario.cooling_power, 3000.0);
+        assert_eq!(scenario.sensor_noise, 0.0);
+        assert_eq!(scenario.humidity, 40);
+        assert_eq!(scenario.seed, 7);
+        // Sorted by time whatever order the file lists them in
+        assert_eq!(scenario.outside, [(0.0, 30.0), (7200.0, 35.0)]);
+        assert_eq!(scenario.doors.len(), 1);
+        assert_eq!(scenario.dropouts.len(), 1);
+    }
+
+    #[test]
+    fn keeps_defaults_for_missing_settings() {
+        let scenario = Scenario::parse("# nothing\n\n   \n").unwrap();
+        let defaults = Scenario::default();
+        assert_eq!(scenario.duration, defaults.duration);
+        assert_eq!(scenario.outside, defaults.outside);
+        assert!(scenario.doors.is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_settings_and_keywords() {
+        let error = parse_error("seed = 1\ncolour = blue\n");
+        assert_eq!(error.line, 2);
+        assert_eq!(error.message, "unknown setting `colour`");
+        assert_eq!(error.to_string(), "line 2: unknown setting `colour`");
+
+        let error = parse_error("window 1 2\n");
+        assert_eq!(error.line, 1);
+        assert_eq!(error.message, "unknown keyword `window`");
+    }
+
+    #[test]
+    fn rejects_bad_numbers() {
+        let error = parse_error("\n\ncooling_w = lots\n");
+        assert_eq!(error.line, 3);
+        assert_eq!(error.message, "invalid value `lots`");
+
+        assert_eq!(parse_error("humidity = -5").message, "invalid value `-5`");
+        assert_eq!(parse_error("outside 1 hot").message, "invalid value `hot`");
+        assert_eq!(parse_error("door 1 2").message, "expected 3 values, got 2");
+        assert_eq!(
+            parse_error("dropout 1 2 3").message,
+            "expected 2 values, got 3"
+        );
+    }
+
+    #[test]
+    fn interpolates_the_outside_temperature() {
+        let scenario = Scenario::parse("outside 1 30\noutside 3 34\n").unwrap();
+        assert_eq!(scenario.outside_temperature(0.0), 30.0);
+        assert_eq!(scenario.outside_temperature(3600.0), 30.0);
+        assert_eq!(scenario.outside_temperature(7200.0), 32.0);
+        assert_eq!(scenario.outside_temperature(9000.0), 33.0);
+        assert_eq!(scenario.outside_temperature(20_000.0), 34.0);
+    }
+
+    #[test]
+    fn applies_doors_and_dropouts_within_their_windows() {
+        let scenario = Scenario::parse(HOT_AFTERNOON).unwrap();
+        assert_eq!(scenario.door_loss(899.0), 0.0);
+        assert_eq!(scenario.door_loss(900.0), 300.0);
+        assert_eq!(scenario.door_loss(1800.0), 0.0);
+        assert!(!scenario.sensor_dropped_out(3599.0));
+        assert!(scenario.sensor_dropped_out(3600.0));
+        assert!(!scenario.sensor_dropped_out(5400.0));
+    }
+}
@@ -0,0 +1,128 @@
+use aircon_core::dht11::{Reading, SensorModel};
+use aircon_core::units::{Humidity, Temperature};
+use embassy_time::Instant;
+
+use crate::scenario::Scenario;
+
+/// Small xorshift generator so runs are reproducible from the scenario seed
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        XorShift(seed.max(1))
+    }
+
+    /// Uniformly distributed value in [-1, 1)
+    fn next_symmetric(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+}
+
+/// DHT11 stand-in, reports whole degrees with noise and no readings during dropouts
+pub struct SimulatedDht11<'a> {
+    scenario: &'a Scenario,
+    rng: XorShift,
+}
+
+impl<'a> SimulatedDht11<'a> {
+    pub fn new(scenario: &'a Scenario) -> Self {
+        SimulatedDht11 {
+            scenario,
+            rng: XorShift::new(scenario.seed),
+        }
+    }
+
+    /// Valid readings the firmware would forward, `None` while the sensor is silent.
+    /// `now` is the controller clock at simulation `time`.
+    pub fn read(&mut self, time: f64, now: Instant, room_temperature: f64) -> Option<Reading> {
+        let noise = self.rng.next_symmetric() * self.scenario.sensor_noise;
+        if self.scenario.sensor_dropped_out(time) {
+            return None;
+        }
+        // DHT11 readings only have whole degree resolution
+        let (min_temperature, max_temperature) = SensorModel::Dht11.temperature_range();
+        let temperature = Temperature::from_tenths((room_temperature + noise).round() as i16 * 10);
+        Some(Reading {
+            temperature: temperature.clamp(min_temperature, max_temperature),
+            humidity: Humidity::from_tenths(self.scenario.humidity * 10),
+            taken_at: now,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scenario(seed: u64) -> Scenario {
+        Scenario {
+            sensor_noise: 0.5,
+            seed,
+            ..Scenario::parse("humidity = 45\ndropout 1 2\n").unwrap()
+        }
+    }
+
+    fn temperatures(scenario: &Scenario) -> Vec<i16> {
+        let mut sensor = SimulatedDht11::new(scenario);
+        (0..20)
+            .map(|second| {
+                let reading = sensor.read(second as f64, Instant::from_secs(second), 22.4);
+                reading.unwrap().temperature.to_tenths()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_whole_degrees_with_noise() {
+        let scenario = scenario(3);
+        let readings = temperatures(&scenario);
+        assert!(readings
+            .iter()
+            .all(|tenths| *tenths == 220 || *tenths == 230));
+        assert!(readings.contains(&220) && readings.contains(&230));
+
+        let reading = SimulatedDht11::new(&scenario)
+            .read(5.0, Instant::from_secs(5), 22.4)
+            .unwrap();
+        assert_eq!(reading.humidity, Humidity::from_tenths(450));
+        assert_eq!(reading.taken_at, Instant::from_secs(5));
+    }
+
+    #[test]
+    fn repeats_a_run_from_the_seed() {
+        assert_eq!(temperatures(&scenario(3)), temperatures(&scenario(3)));
+        assert_ne!(temperatures(&scenario(3)), temperatures(&scenario(4)));
+    }
+
+    #[test]
+    fn stays_silent_during_dropouts() {
+        let scenario = scenario(3);
+        let mut sensor = SimulatedDht11::new(&scenario);
+        assert!(sensor
+            .read(3599.0, Instant::from_secs(3599), 22.0)
+            .is_some());
+        assert!(sensor
+            .read(3600.0, Instant::from_secs(3600), 22.0)
+            .is_none());
+        assert!(sensor
+            .read(7200.0, Instant::from_secs(7200), 22.0)
+            .is_some());
+    }
+
+    #[test]
+    fn clamps_to_the_dht11_range() {
+        let scenario = Scenario {
+            sensor_noise: 0.0,
+            ..Scenario::default()
+        };
+        let mut sensor = SimulatedDht11::new(&scenario);
+        let reading = sensor.read(0.0, Instant::from_secs(0), 80.0).unwrap();
+        assert_eq!(
+            reading.temperature,
+            SensorModel::Dht11.temperature_range().1
+        );
+    }
+}